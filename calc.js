@@ -0,0 +1,33 @@
+// calc in a web page, over the wasm32 build of the library:
+//   cargo build --lib --release --target wasm32-unknown-unknown
+//   const calc = await Calc.load(fetch("calc.wasm"));
+//   calc.evaluate("2 ^ 10")     // {input, ok: true, result: 1024, repr: "<2^10>"}
+// a failure is thrown as {input, ok: false, repr, diagnostics: [{code, message, span: [start, end] or null}]}
+export class Calc {
+    constructor(instance) {
+        this.exports = instance.exports;
+    }
+
+    static async load(source) {
+        const { instance } = await WebAssembly.instantiateStreaming(source, {});
+        return new Calc(instance);
+    }
+
+    evaluate(input) {
+        const { memory, calc_alloc, calc_dealloc, calc_evaluate_json, calc_string_free } = this.exports;
+        const bytes = new TextEncoder().encode(input + "\0");
+        const ptr = calc_alloc(bytes.length);
+        new Uint8Array(memory.buffer, ptr, bytes.length).set(bytes);
+        const out = calc_evaluate_json(ptr);
+        calc_dealloc(ptr, bytes.length);
+        // memory.buffer is read again, the evaluation may have grown it
+        const view = new Uint8Array(memory.buffer, out);
+        const json = new TextDecoder().decode(view.subarray(0, view.indexOf(0)));
+        calc_string_free(out);
+        const result = JSON.parse(json);
+        if (!result.ok) {
+            throw result;
+        }
+        return result;
+    }
+}
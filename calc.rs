@@ -1,252 +1,2021 @@
+#![allow(clippy::needless_return)]
+use calc::*;
+use std::collections::HashMap;
+use std::io::Write;
 
-trait ASTNode {
-    fn eval(&self) -> i32;
-    fn repr(&self) -> String;
+// "zh_CN.UTF-8" -> "zh", following the usual LC_ALL > LC_MESSAGES > LANG precedence
+fn lang_from_env() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|v| !v.is_empty())
+        .map_or(String::new(), |v| v.chars().take_while(|c| c.is_ascii_alphabetic()).collect())
 }
 
-struct NumNode(i32);
-struct NegNode(Box<dyn ASTNode>);
-struct ParNode(Box<dyn ASTNode>);
-struct MulNode(Box<dyn ASTNode>, Box<dyn ASTNode>);
-struct DivNode(Box<dyn ASTNode>, Box<dyn ASTNode>);
-struct AddNode(Box<dyn ASTNode>, Box<dyn ASTNode>);
-struct SubNode(Box<dyn ASTNode>, Box<dyn ASTNode>);
+// `--config FILE`, else $CALC_CONFIG, else ~/.calcrc when it exists
+fn load_config(path: Option<&str>) {
+    let path = path.map(String::from)
+        .or_else(|| std::env::var("CALC_CONFIG").ok())
+        .or_else(|| std::env::var("HOME").ok().map(|home| format!("{}/.calcrc", home)).filter(|p| std::path::Path::new(p).exists()));
+    let Some(path) = path else { return; };
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e));
+    let config = parse_config(&text).unwrap_or_else(|e| panic!("Error: {}: {}", path, e));
+    set_aliases(config.aliases).and_then(|_| set_operators(config.operators)).unwrap_or_else(|e| panic!("Error: {}: {}", path, e));
+}
+
+// spreadsheet mode: grid of cells, formulas start with '='
 
-impl ASTNode for NumNode {
-    fn eval(&self) -> i32 { self.0 }
-    fn repr(&self) -> String { format!("{}", self.eval()) }
+fn split_row(line: &str, delim: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => { field.push('"'); chars.next(); },
+            '"' => quoted = !quoted,
+            c if c == delim && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    return fields;
 }
-impl ASTNode for NegNode {
-    fn eval(&self) -> i32 { - self.0.eval() }
-    fn repr(&self) -> String { format!("<-{}>", self.0.repr())}
+
+fn join_row(row: &[String], delim: char) -> String {
+    row.iter().map(|f| {
+        if f.contains(delim) || f.contains('"') {
+            format!("\"{}\"", f.replace('"', "\"\""))
+        } else {
+            f.clone()
+        }
+    }).collect::<Vec<String>>().join(&delim.to_string())
 }
-impl ASTNode for ParNode {
-    fn eval(&self) -> i32 { self.0.eval() }
-    fn repr(&self) -> String { format!("({})", self.0.repr())}
+
+struct Sheet {
+    grid: Vec<Vec<String>>,
+    values: HashMap<String, Value>,
+    progress: Option<Progress>,     // ticks once per row
+}
+
+impl Sheet {
+    fn new(grid: Vec<Vec<String>>) -> Self {
+        Sheet { grid, values: HashMap::new(), progress: None }
+    }
+
+    fn cell(&self, name: &str) -> &str {
+        let (row, col) = parse_cell_name(name).unwrap();
+        self.grid.get(row).and_then(|r| r.get(col)).map(|s| s.trim()).unwrap_or("")
+    }
+
+    // evaluate a cell after its dependencies, `path` holds the cells being evaluated
+    fn eval_cell(&mut self, name: &str, path: &mut Vec<String>) -> Value {
+        if let Some(v) = self.values.get(name) {
+            return v.clone();
+        }
+        if let Some(pos) = path.iter().position(|n| n == name) {
+            let mut cycle = path[pos..].to_vec();
+            cycle.push(name.to_string());
+            panic!("{}", error("E0010", &[&cycle.join(" -> ")]));
+        }
+        let content = self.cell(name).to_string();
+        let value = if let Some(formula) = content.strip_prefix('=') {
+            path.push(name.to_string());
+            let mut deps = HashMap::new();
+            for t in TokenParser::with_cells(formula.to_string(), HashMap::new()) {
+                if let Ok(Token::REF(dep)) = t {
+                    let v = self.eval_cell(&dep, path);
+                    deps.insert(dep, v);
+                }
+            }
+            path.pop();
+            evaluate(TokenParser::with_cells(formula.to_string(), deps)).and_then(|n| n.eval()).unwrap_or_else(|e| panic!("{}", e))
+        }
+        else if content.is_empty() {
+            Value::Int(0)
+        }
+        else {
+            Value::parse(&content).unwrap_or_else(|| panic!("{}", error("E0011", &[&name, &content])))
+        };
+        self.values.insert(name.to_string(), value.clone());
+        return value;
+    }
+
+    // computed grid, formulas replaced by their values
+    fn compute(&mut self) -> Vec<Vec<String>> {
+        let mut out = self.grid.clone();
+        for (r, row) in self.grid.clone().iter().enumerate() {
+            for (c, content) in row.iter().enumerate() {
+                if content.trim().starts_with('=') {
+                    out[r][c] = self.eval_cell(&cell_name(r, c), &mut Vec::new()).to_string();
+                }
+            }
+            if let Some(p) = &mut self.progress {
+                p.tick(r + 1);
+            }
+        }
+        if let Some(p) = &mut self.progress {
+            p.finish();
+        }
+        return out;
+    }
+
+    // `--export-shell`: "export B2=12" for every computed formula cell
+    fn exports(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (r, row) in self.grid.iter().enumerate() {
+            for (c, content) in row.iter().enumerate() {
+                let name = cell_name(r, c);
+                if let (true, Some(v)) = (content.trim().starts_with('='), self.values.get(&name)) {
+                    lines.push(format!("export {}={}", name, shell_word(&v.to_string())));
+                }
+            }
+        }
+        return lines;
+    }
 }
-impl ASTNode for MulNode {
-    fn eval(&self) -> i32 { self.0.eval() * self.1.eval() }
-    fn repr(&self) -> String { format!("<{}*{}>", self.0.repr(), self.1.repr())}
+
+// `--export-shell`: "export x=12" for every variable the input assigned, in order
+fn shell_exports(env: &Env, cli: &Cli) -> Result<Vec<String>, CalcError> {
+    env.assigned().into_iter().map(|(name, v)| Ok(format!("export {}={}", name, shell_word(&round_result(cli, v.clone())?.to_string())))).collect()
 }
-impl ASTNode for DivNode {
-    fn eval(&self) -> i32 { self.0.eval() / self.1.eval() }
-    fn repr(&self) -> String { format!("<{}/{}>", self.0.repr(), self.1.repr())}
+
+// quoted for a POSIX shell unless plain, `'12 m'`
+fn shell_word(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "._+-/:,".contains(c)) {
+        return s.to_string();
+    }
+    return format!("'{}'", s.replace('\'', "'\\''"));
 }
-impl ASTNode for AddNode {
-    fn eval(&self) -> i32 { self.0.eval() + self.1.eval() }
-    fn repr(&self) -> String { format!("<{}+{}>", self.0.repr(), self.1.repr())}
+
+fn run_sheet(path: &str, progress: bool, export: bool) {
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e));
+    let delim = if path.ends_with(".tsv") || text.lines().next().unwrap_or("").contains('\t') { '\t' } else { ',' };
+    let grid: Vec<Vec<String>> = text.lines().map(|l| split_row(l, delim)).collect();
+    let progress = progress.then(|| Progress::new(grid.len()));
+    let mut sheet = Sheet { progress, ..Sheet::new(grid) };
+    let computed = sheet.compute();
+    if export {
+        for line in sheet.exports() {
+            println!("{}", line);
+        }
+        return;
+    }
+    for row in computed {
+        println!("{}", join_row(&row, delim));
+    }
 }
-impl ASTNode for SubNode {
-    fn eval(&self) -> i32 { self.0.eval() - self.1.eval() }
-    fn repr(&self) -> String { format!("<{}-{}>", self.0.repr(), self.1.repr())}
+
+
+// `:debug <expr>`: the evaluation is recorded up front, then replayed on command
+struct DebugEvent {
+    enter: bool,
+    depth: usize,
+    text: String,       // source of the subtree
+    step: String,       // the reduction, for exit events
 }
 
-#[derive(Clone, PartialEq, Debug)]
-enum Token {
-    ADD, SUB, 
-    MUL, DIV, 
-    NUM(i32), LPR, RPR
+// only reductions are recorded, `depth` counts the enclosing ones
+#[derive(Default)]
+struct Recorder {
+    source: Vec<char>,
+    depth: usize,
+    events: Vec<DebugEvent>,
 }
 
-struct TokenParser {
-    input: Vec<char>,
-    idx: Option<usize>, 
+impl Recorder {
+    fn record(&mut self, node: &Expr, step: Option<String>) {
+        let span = node.span();
+        let text = self.source[span.start..span.end].iter().collect();
+        self.events.push(DebugEvent { enter: step.is_none(), depth: self.depth, text, step: step.unwrap_or_default() });
+    }
 }
 
-impl TokenParser {
-    fn new(input: String) -> Self {
-        TokenParser {
-            input: input.chars().collect(),
-            idx: Some(0),       
+impl EvalObserver for Recorder {
+    fn enter(&mut self, node: &Expr) {
+        if is_reduction(node) {
+            self.record(node, None);
+            self.depth += 1;
         }
     }
+    fn exit(&mut self, node: &Expr, operands: &[Value], result: &Value) {
+        if is_reduction(node) {
+            self.depth -= 1;
+            self.record(node, Some(reduction(node, operands, result)));
+        }
+    }
+}
 
-    fn next_char_idx(&self) -> Option<usize> {
-        let mut c : &char;
-        let mut idx = self.idx?; 
-        while idx + 1 < self.input.len() {
-            idx += 1;
-            c = &self.input[idx];
-            if c.is_whitespace() {
+const DEBUG_HELP: &str = "step (s)      go to the next event, entering subexpressions
+next (n)      evaluate the current subexpression in one go
+continue (c)  run to the end
+print (p)     print the current subexpression
+quit (q)      leave the debugger";
+
+// the variables of `env` are known, assignments stay inside the debugger
+fn debug(input: &str, env: &Env, commands: &mut dyn std::io::BufRead, out: &mut dyn Write) -> std::io::Result<()> {
+    let n = match parse(input) {
+        Ok(n) => n,
+        Err(e) => return writeln!(out, "{}", paint(&e.to_string(), RED)),
+    };
+    let mut rec = Recorder { source: input.chars().collect(), ..Recorder::default() };
+    let result = n.eval_with(&mut env.clone(), &mut rec);
+    let events = rec.events;
+
+    let show = |out: &mut dyn Write, e: &DebugEvent| {
+        let indent = "  ".repeat(e.depth);
+        if e.enter { writeln!(out, "{}enter {}", indent, e.text) } else { writeln!(out, "{}{}", indent, e.step) }
+    };
+    // index of the next event to replay
+    let mut pos = 0;
+    let mut last = String::from("step");
+    writeln!(out, "Debugging: {}  ({} events, `help` for commands)", input.trim(), events.len())?;
+    while pos < events.len() {
+        write!(out, "(debug) ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if commands.read_line(&mut line)? == 0 {
+            break;
+        }
+        let cmd = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+        match cmd.as_str() {
+            "s" | "step" => {
+                show(out, &events[pos])?;
+                pos += 1;
+            },
+            "n" | "next" => {
+                let e = &events[pos];
+                if e.enter {
+                    // skip to the matching exit
+                    pos += events[pos..].iter().position(|x| !x.enter && x.depth == e.depth).unwrap();
+                }
+                show(out, &events[pos])?;
+                pos += 1;
+            },
+            "c" | "continue" => {
+                for e in &events[pos..] {
+                    if !e.enter { show(out, e)?; }
+                }
+                pos = events.len();
+            },
+            "p" | "print" => {
+                // an exit event pending means we are inside its subexpression
+                let e = &events[pos];
+                writeln!(out, "{} {}", if e.enter { "next:" } else { "current:" }, e.text)?;
+            },
+            "q" | "quit" => return Ok(()),
+            "help" => writeln!(out, "{}", DEBUG_HELP)?,
+            _ => writeln!(out, "Unknown command '{}', `help` for commands", cmd)?,
+        }
+        last = cmd;
+    }
+    match result {
+        Ok(v) => writeln!(out, "Result: {}", v)?,
+        Err(e) => writeln!(out, "{}", paint(&e.to_string(), RED))?,
+    }
+    return Ok(());
+}
+
+// interactive mode: evaluate line after line until end of input (Ctrl-D), variables carry over
+fn repl(cli: &Cli, input: &mut dyn std::io::BufRead, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut env = Env::default();
+    let mut memory = Value::Int(0);
+    let mut history = Vec::new();
+    let mut last: Option<Expr> = None;     // for :again
+    // `--session FILE`: restored now if it exists, saved again at the end
+    let session = cli.flag("--session");
+    if let Some(path) = session && std::path::Path::new(path).exists() {
+        load_session(path, &mut env, &mut history, out)?;
+    }
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(out)?;
+            if let Some(path) = session {
+                std::fs::write(path, session_text(&env, &history))?;
+            }
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        // `!5` is entry 5 of :history again, `! 5` and `!(5)` still negate
+        if let Some(no) = line.trim().strip_prefix('!').and_then(|no| no.parse::<usize>().ok()) {
+            let Some(entry) = no.checked_sub(1).and_then(|i| history.get(i)) else {
+                writeln!(out, "{}", paint(&format!("Error: no entry {} in the history", no), RED))?;
+                continue;
+            };
+            line = entry.clone();
+            writeln!(out, "{}", line)?;
+        }
+        history.push(line.trim().to_string());
+        match line.trim().split_once(' ') {
+            Some((":save", path)) => {
+                match std::fs::write(path.trim(), session_text(&env, &history)) {
+                    Ok(()) => writeln!(out, "saved {} variables to {}", env.vars().len(), path.trim())?,
+                    Err(e) => writeln!(out, "{}", paint(&format!("Error: Cannot write {}: {}", path.trim(), e), RED))?,
+                }
                 continue;
+            },
+            Some((":load", path)) => {
+                load_session(path.trim(), &mut env, &mut history, out)?;
+                continue;
+            },
+            _ if line.trim() == ":history" => {
+                for (i, entry) in history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", i + 1, entry)?;
+                }
+                continue;
+            },
+            _ => {},
+        }
+        if let Some(expr) = line.trim().strip_prefix(":debug ") {
+            debug(expr, &env, input, out)?;
+            continue;
+        }
+        if let Some(mode) = line.trim().strip_prefix(":mode ") {
+            match mode.trim() {
+                "deg" => set_degrees(true),
+                "rad" => set_degrees(false),
+                other => writeln!(out, "{}", paint(&format!("Error: unknown mode {}, expected deg or rad", other), RED))?,
+            }
+            writeln!(out, "angles in {}", if degrees() { "degrees" } else { "radians" })?;
+            continue;
+        }
+        // `:undo 2` takes back the last two assignments
+        if line.trim() == ":undo" || line.trim().starts_with(":undo ") {
+            let count = line.trim()[":undo".len()..].trim();
+            let Ok(count) = (if count.is_empty() { Ok(1) } else { count.parse::<usize>() }) else {
+                writeln!(out, "{}", paint(&format!("Error: expected a count after :undo, got {}", count), RED))?;
+                continue;
+            };
+            for _ in 0..count {
+                match env.undo() {
+                    Some((name, Some(v))) => writeln!(out, "{} = {}", name, result_text(cli, &v))?,
+                    Some((name, None)) => writeln!(out, "{} unset", name)?,
+                    None => {
+                        writeln!(out, "{}", paint("Error: nothing to undo", RED))?;
+                        break;
+                    },
+                }
+            }
+            continue;
+        }
+        // `:again x = 5; y = 2` is the last expression with those variables, they are only set for it
+        if line.trim() == ":again" || line.trim().starts_with(":again ") {
+            let Some(n) = &last else {
+                writeln!(out, "{}", paint("Error: no expression to run again", RED))?;
+                continue;
+            };
+            let mut scratch = env.clone();
+            let overrides = line.trim()[":again".len()..].trim();
+            let result = if overrides.is_empty() { Ok(Value::Int(0)) } else { eval_str_in(overrides, &mut scratch) };
+            match result.and_then(|_| n.eval_in(&mut scratch)).and_then(|v| round_result(cli, v)) {
+                Ok(v) => {
+                    writeln!(out, "{}", paint(&result_text(cli, &v), GREEN))?;
+                    env.set(&format!("_{}", history.len()), v.clone());
+                    env.set("ans", v);
+                },
+                Err(e) => writeln!(out, "{}", paint(&e.to_string(), RED))?,
             }
-            return Some(idx); 
-        }
-        return None;
-    }
-}
-
-impl Iterator for TokenParser {
-    type Item = Token;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let c = &self.input[self.idx?];
-
-        let token = match c {
-            '+' => Token::ADD, 
-            '-' => Token::SUB, 
-            '*' => Token::MUL,
-            '/' => Token::DIV, 
-            '(' => Token::LPR, 
-            ')' => Token::RPR, 
-            '0'..='9' => {
-                let mut accum = 0;
-                loop {
-                    let curr_c = self.input[self.idx.unwrap()];
-                    let digit = curr_c.to_digit(10).unwrap() as i32;
-                    accum = accum * 10 + digit;
-                    match self.next_char_idx() {
-                        Some(next_char_idx) => {
-                            let next_char = &self.input[next_char_idx]; 
-                            if next_char.is_digit(10) {
-                                self.idx = Some(next_char_idx);
-                            }
-                            else {
-                                break;
-                            }
-                        }, 
-                        None => break,
-                    }; 
-                }
-                Token::NUM(accum)
-            }, 
-            _ => panic!("Invalid token '{}' at index {}", c, self.idx.unwrap_or(0)),
+            continue;
+        }
+        // memory keys as on a pocket calculator, M+ and M- take the last result (`ans`)
+        if let key @ ("M+" | "M-" | "MR" | "MC") = line.trim() {
+            let ans = env.get("ans").cloned().unwrap_or(Value::Int(0));
+            match match key { "M+" => &memory + &ans, "M-" => &memory - &ans, "MR" => Ok(memory.clone()), _ => Ok(Value::Int(0)) } {
+                Ok(v) if key == "MR" => {
+                    writeln!(out, "{}", paint(&result_text(cli, &v), GREEN))?;
+                    env.set("ans", v);
+                },
+                Ok(v) => {
+                    writeln!(out, "M = {}", result_text(cli, &v))?;
+                    memory = v;
+                },
+                Err(e) => writeln!(out, "{}", paint(&e.to_string(), RED))?,
+            }
+            continue;
+        }
+        // an unclosed parenthesis continues the expression on the next line
+        while let Ok(open) = paren_balance(&line) {
+            if open == 0 {
+                break;
+            }
+            if cli.has("--auto-close") {
+                line = line.trim_end().to_string() + &")".repeat(open);
+                break;
+            }
+            write!(out, "{} ", paint(&format!("{}…", "(".repeat(open)), YELLOW))?;
+            out.flush()?;
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+        }
+        // the history has the lines joined, as one entry that !N can run again
+        *history.last_mut().unwrap() = line.lines().map(|l| l.trim()).collect::<Vec<&str>>().join(" ");
+        if let Err(idx) = paren_balance(&line) {
+            writeln!(out, "{}: unmatched ')'\n{}", paint("warning", YELLOW), caret(&line, Span { start: idx, end: idx + 1 }))?;
+        }
+        let n = match parse_recovering(line.trim()) {
+            Ok(n) => n,
+            Err(errors) => {
+                for (e, span) in errors {
+                    writeln!(out, "{}", diagnostic(line.trim(), &e, span))?;
+                }
+                continue;
+            },
         };
-        self.idx = self.next_char_idx();
-        return Some(token);
+        last = Some(n.clone());
+        match n.eval_in(&mut env).and_then(|v| round_result(cli, v)) {
+            Ok(v) => {
+                writeln!(out, "{}", paint(&result_text(cli, &v), GREEN))?;
+                // `_3` is the result of entry 3 of :history
+                env.set(&format!("_{}", history.len()), v.clone());
+                env.set("ans", v);
+            },
+            Err(e @ CalcError::DivisionByZero(_, span)) => writeln!(out, "{}", diagnostic(line.trim(), &e, span))?,
+            Err(e) => writeln!(out, "{}", paint(&e.to_string(), RED))?,
+        }
     }
 }
 
-// main entry point
-fn evaluate(mut p: TokenParser) -> Box<dyn ASTNode> {
-    let (n, t) = parse_e(&mut p);
-    if t.is_some() {
-        panic!("Error: Extra token after expression: {:?}", t.unwrap());
+// a session as a .calc file, the history as `#>` comments and the variables as assignments, 
+// so `calc --file` or `calc test` can also run it; `ans` and `_3` are left out, they are not assigned
+fn session_text(env: &Env, history: &[String]) -> String {
+    let mut text = String::from("# calc session\n");
+    for line in history {
+        text += &format!("#> {}\n", line);
     }
-    return n;
+    for (name, value) in env.assigned() {
+        text += &format!("{} = {}\n", name, value.literal());
+    }
+    return text;
 }
 
-// <t1>+<t2>, <t1>-<t2>
-fn parse_e(p: &mut TokenParser) -> (Box<dyn ASTNode>, Option<Token>) {
-    let (mut n0, t1) = parse_t(p);
-    let Some(mut tv) = t1 else { return (n0, None); };
+// `:load FILE` and `--session FILE`, lines that no longer evaluate are reported and skipped
+fn load_session(path: &str, env: &mut Env, history: &mut Vec<String>, out: &mut dyn Write) -> std::io::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return writeln!(out, "{}", paint(&format!("Error: Cannot read {}: {}", path, e), RED)),
+    };
+    let (mut restored, mut failed) = (0, 0);
+    for (i, line) in text.lines().enumerate() {
+        if let Some(entry) = line.strip_prefix("#>") {
+            history.push(entry.trim().to_string());
+        } else if !line.trim().is_empty() && !line.trim_start().starts_with('#') {
+            match eval_str_in(line, env) {
+                Ok(_) => restored += 1,
+                Err(e) => {
+                    writeln!(out, "{}", paint(&format!("{} line {}: {}", path, i + 1, e), RED))?;
+                    failed += 1;
+                },
+            }
+        }
+    }
+    if failed > 0 {
+        return writeln!(out, "restored {} variables from {}, {} lines failed", restored, path, failed);
+    }
+    return writeln!(out, "restored {} variables from {}", restored, path);
+}
 
-    while tv == Token::ADD || tv == Token::SUB {
-        let (n1, tn) = parse_t(p);
-        n0 = match tv {
-            Token::ADD => Box::new(AddNode(n0, n1)), 
-            Token::SUB => Box::new(SubNode(n0, n1)), 
-            _ => unreachable!()
+// `calc test FILE`: every `assert <expr> == <expr>` (or `assert <expr>`, true when non-zero)
+// must hold, other lines must evaluate without error; blank and `#` lines are skipped
+fn run_tests(text: &str, out: &mut dyn Write) -> std::io::Result<usize> {
+    let (mut passed, mut failed) = (0, 0);
+    let mut env = Env::default();      // variables carry over to the following lines
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let outcome = match line.strip_prefix("assert ") {
+            Some(cond) => match cond.split_once("==") {
+                Some((l, r)) => eval_str_in(l, &mut env).and_then(|a| eval_str_in(r, &mut env).map(|b| (a, b))).map(|(a, b)| {
+                    if a == b { None } else { Some(format!("left {}, right {}", a, b)) }
+                }),
+                None => eval_str_in(cond, &mut env).and_then(|v| v.is_zero())
+                    .map(|zero| if !zero { None } else { Some("evaluated to 0".to_string()) }),
+            },
+            None => eval_str_in(line, &mut env).map(|_| None),
         };
-        match tn {
-            Some(next_token) => tv = next_token,
-            None => return (n0, None),
+        let outcome = outcome.map_err(|e| e.to_string());
+        match outcome {
+            Ok(None) => {
+                passed += 1;
+                writeln!(out, "ok    line {}: {}", i + 1, line)?;
+            },
+            Ok(Some(reason)) | Err(reason) => {
+                failed += 1;
+                writeln!(out, "FAIL  line {}: {} ({})", i + 1, line, reason)?;
+            },
         }
-    };
-    return (n0, Some(tv));
+    }
+    writeln!(out, "{} passed, {} failed", passed, failed)?;
+    return Ok(failed);
+}
+
+// `--progress`: a bar with the ETA on stderr, redrawn at most every 100ms
+struct Progress {
+    total: usize,
+    started: std::time::Instant,
+    drawn: Option<std::time::Instant>,
 }
 
-// <f1>*<f2>, <f1>/<f2>
-fn parse_t(p: &mut TokenParser) -> (Box<dyn ASTNode>, Option<Token>) {
-    let (mut n0, t1) = parse_f(p);
-    let Some(mut tv) = t1 else { return (n0, None); };
+impl Progress {
+    fn new(total: usize) -> Self {
+        Progress { total, started: std::time::Instant::now(), drawn: None }
+    }
 
-    while tv == Token::MUL || tv == Token::DIV {
-        let (n1, tn) = parse_f(p);
-        n0 = match tv {
-            Token::MUL => Box::new(MulNode(n0, n1)), 
-            Token::DIV => Box::new(DivNode(n0, n1)), 
-            _ => unreachable!()
-        }; 
-        match tn {
-            Some(next_token) => tv = next_token,
-            None => return (n0, None),
+    fn tick(&mut self, done: usize) {
+        if self.drawn.is_some_and(|t| t.elapsed() < std::time::Duration::from_millis(100)) && done < self.total {
+            return;
         }
-    };
-    return (n0, Some(tv));
+        self.drawn = Some(std::time::Instant::now());
+        eprint!("\r{}", progress_bar(done, self.total, self.started.elapsed()));
+    }
+
+    fn finish(&mut self) {
+        if self.drawn.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+// "[##########··········]  50%  500/1000  ETA 3s"
+fn progress_bar(done: usize, total: usize, elapsed: std::time::Duration) -> String {
+    const WIDTH: usize = 20;
+    let fraction = if total > 0 { done as f64 / total as f64 } else { 1.0 };
+    let filled = (fraction * WIDTH as f64) as usize;
+    let eta = if done > 0 { elapsed.as_secs_f64() * (total - done) as f64 / done as f64 } else { 0.0 };
+    format!("[{}{}] {:>3.0}%  {}/{}  ETA {:.0}s", "#".repeat(filled), "·".repeat(WIDTH - filled), fraction * 100.0, done, total, eta)
+}
+
+// whether to draw progress for `--progress` (default auto: only when stderr is a terminal)
+fn show_progress(cli: &Cli) -> bool {
+    match cli.flag("--progress").unwrap_or("auto") {
+        "on" => true,
+        "off" => false,
+        _ => std::io::IsTerminal::is_terminal(&std::io::stderr()),
+    }
+}
+
+// `--file`: every non-empty line is an independent expression, `format` is one of OUTPUT_FORMATS
+fn run_batch(text: &str, format: &str, out: &mut dyn Write, mut progress: Option<Progress>) -> std::io::Result<usize> {
+    let mut failed = 0;
+    batch_header(format, out)?;
+    let lines = text.lines().map(|l| l.trim()).enumerate().filter(|(_, l)| !l.is_empty() && !l.starts_with('#')).collect::<Vec<(usize, &str)>>();
+    if let Some(p) = &mut progress {
+        p.total = lines.len();
+    }
+    let mut env = Env::default();
+    for (i, (no, line)) in lines.into_iter().enumerate() {
+        if let Some(p) = &mut progress {
+            p.tick(i);
+        }
+        if !batch_line(line, no, format, &mut env, out)? {
+            failed += 1;
+        }
+    }
+    if let Some(p) = &mut progress {
+        p.finish();
+    }
+    return Ok(failed);
 }
 
-// num, -<num>, (<expr>)
-fn parse_f(p: &mut TokenParser) -> (Box<dyn ASTNode>, Option<Token>) {
-    let t0 = p.next().unwrap_or_else(||panic!("empty"));
-    match t0 {
-        Token::NUM(num) => {
-            return (Box::new(NumNode(num)), p.next());
+// `calc -`, or no EXPR with a pipe for stdin: --file a line at a time as it arrives, 
+// so `tail -f log | calc -` answers each line before the next one is written
+fn run_pipe(input: &mut dyn std::io::BufRead, format: &str, out: &mut dyn Write) -> std::io::Result<usize> {
+    let mut failed = 0;
+    batch_header(format, out)?;
+    let mut env = Env::default();
+    let mut line = String::new();
+    for no in 0.. {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.trim().is_empty() || line.trim().starts_with('#') {
+            continue;
         }
-        Token::SUB => {
-            let t1 = p.next().expect("Error: Nothing follows NEG!");
-            if let Token::NUM(num) = t1 {
-                return (Box::new(NegNode(Box::new(NumNode(num)))), p.next());
+        if !batch_line(line.trim(), no, format, &mut env, out)? {
+            failed += 1;
+        }
+        out.flush()?;
+    }
+    return Ok(failed);
+}
+
+fn batch_header(format: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    if format != "text" {
+        let delim = if format == "tsv" { '\t' } else { ',' };
+        writeln!(out, "{}", join_row(&["input".to_string(), "result".to_string(), "error".to_string()], delim))?;
+    }
+    return Ok(());
+}
+
+// line `no` (0-based) of --file or a pipe, false if it failed; variables carry over to the next line in `env`
+fn batch_line(line: &str, no: usize, format: &str, env: &mut Env, out: &mut dyn Write) -> std::io::Result<bool> {
+    let delim = if format == "tsv" { '\t' } else { ',' };
+    // all syntax errors of the line, or the one evaluating it
+    let result = match parse_recovering(line) {
+        Ok(n) => n.eval_in(env).map_err(|e| vec![e.to_string()]),
+        Err(errors) => Err(errors.into_iter().map(|(e, _)| e.to_string()).collect()),
+    };
+    let ok = result.is_ok();
+    match (format, result) {
+        ("text", Ok(v)) => writeln!(out, "{}", v)?,
+        ("text", Err(errors)) => {
+            for e in errors {
+                writeln!(out, "line {}: {}", no + 1, e)?;
             }
-            panic!("Error: Non-num follows NEG: {:?}", t1);
+        },
+        (_, Ok(v)) => writeln!(out, "{}", join_row(&[line.to_string(), v.to_string(), String::new()], delim))?,
+        (_, Err(errors)) => writeln!(out, "{}", join_row(&[line.to_string(), String::new(), errors.join("; ")], delim))?,
+    }
+    return Ok(ok);
+}
+
+// `--check --file`: the syntax errors of every line, nothing is evaluated, returns the number of lines with errors
+fn check_lines(text: &str, out: &mut dyn Write) -> std::io::Result<usize> {
+    let mut failed = 0;
+    for (no, line) in text.lines().map(|l| l.trim()).enumerate().filter(|(_, l)| !l.is_empty() && !l.starts_with('#')) {
+        let Err(errors) = validate(line) else { continue; };
+        failed += 1;
+        for d in errors {
+            writeln!(out, "line {}:{}: Error[{}]: {}", no + 1, d.span.start + 1, d.code, d.message)?;
+        }
+    }
+    return Ok(failed);
+}
+
+// `--check --json`: {"input": "1 +", "ok": false, "errors": [{"code": "E0009", "message": ..., "start": 3, "end": 4}]}
+fn check_json(input: &str) -> (bool, String) {
+    let errors = validate(input).err().unwrap_or_default();
+    let objects = errors.iter().map(|d| format!("{{\"code\": {}, \"message\": {}, \"start\": {}, \"end\": {}}}",
+        json_string(d.code), json_string(&d.message), d.span.start, d.span.end)).collect::<Vec<String>>();
+    return (errors.is_empty(), format!("{{\"input\": {}, \"ok\": {}, \"errors\": [{}]}}",
+        json_string(input), errors.is_empty(), objects.join(", ")));
+}
+
+// `calc grade`: line i of `answers` is the answer to line i of `problems`, 
+// returns the number of correct answers
+fn grade(problems: &str, answers: &str, tolerance: f64, out: &mut dyn Write) -> std::io::Result<usize> {
+    let answers = answers.lines().collect::<Vec<&str>>();
+    let (mut correct, mut total) = (0, 0);
+    for (i, problem) in problems.lines().enumerate() {
+        let problem = problem.trim();
+        if problem.is_empty() {
+            continue;
+        }
+        total += 1;
+        let answer = answers.get(i).map_or("", |a| a.trim());
+        let feedback = match (eval_str(problem), answer.parse::<f64>()) {
+            (Err(e), _) => format!("cannot evaluate problem ({})", e),
+            (Ok(_), _) if answer.is_empty() => "no answer".to_string(),
+            (Ok(_), Err(_)) => format!("answer '{}' is not a number", answer),
+            (Ok(expected), Ok(given)) if expected.float().is_ok_and(|x| (given - x).abs() <= tolerance) => {
+                correct += 1;
+                "correct".to_string()
+            },
+            (Ok(expected), Ok(_)) => format!("wrong, expected {}, got {}", expected, answer),
+        };
+        writeln!(out, "{:>3}. {} = {}: {}", i + 1, problem, answer, feedback)?;
+    }
+    let percent = if total > 0 { 100.0 * correct as f64 / total as f64 } else { 0.0 };
+    writeln!(out, "Score: {}/{} ({:.0}%)", correct, total, percent)?;
+    return Ok(correct);
+}
+
+// `calc quiz`: (depth, operators, largest literal) of each level
+const QUIZ_LEVELS: &[(usize, &[char], u64)] = &[
+    (1, &['+', '-'], 20),
+    (1, &['+', '-', '*'], 12),
+    (2, &['+', '-', '*'], 20),
+    (2, GEN_OPS, 20),
+    (3, GEN_OPS, 50),
+];
+
+// a problem worth asking: at least one operator and no truncating division
+fn quiz_problem(rng: &mut Rng, level: usize) -> (String, i32) {
+    let (depth, ops, max) = QUIZ_LEVELS[level - 1];
+    loop {
+        let n = random_expr(rng, depth, ops, max);
+        let mut lints = Vec::new();
+        lint(&n, &mut lints);
+        if let (true, Ok(Value::Int(v))) = (lints.is_empty(), n.eval()) && !n.children().is_empty() {
+            return (grouping(&n), v);
         }
-        Token::LPR => {
-            let (expr, t1) = parse_e(p);
-            match t1 {
-                Some(Token::RPR) => {
-                    return (Box::new(ParNode(expr)), p.next());
+    }
+}
+
+// three right answers in a row move up a level, two wrong ones in a row move down
+fn quiz(rng: &mut Rng, mut level: usize, input: &mut dyn std::io::BufRead, out: &mut dyn Write) -> std::io::Result<usize> {
+    let (mut asked, mut correct, mut streak, mut best, mut misses) = (0, 0, 0, 0, 0);
+    let mut elapsed = 0.0;
+    writeln!(out, "Level {} of {}, `q` to stop", level, QUIZ_LEVELS.len())?;
+    loop {
+        let (problem, expected) = quiz_problem(rng, level);
+        write!(out, "{} = ", problem)?;
+        out.flush()?;
+        let start = std::time::Instant::now();
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 || line.trim() == "q" {
+            writeln!(out)?;
+            break;
+        }
+        let secs = start.elapsed().as_secs_f64();
+        asked += 1;
+        elapsed += secs;
+        if line.trim().parse() == Ok(expected) {
+            correct += 1;
+            streak += 1;
+            best = best.max(streak);
+            misses = 0;
+            writeln!(out, "correct ({:.1}s, streak {})", secs, streak)?;
+        } else {
+            streak = 0;
+            misses += 1;
+            writeln!(out, "wrong, the answer is {} ({:.1}s)", expected, secs)?;
+        }
+        if streak > 0 && streak % 3 == 0 && level < QUIZ_LEVELS.len() {
+            level += 1;
+            writeln!(out, "Level up: {}", level)?;
+        }
+        if misses == 2 && level > 1 {
+            level -= 1;
+            misses = 0;
+            writeln!(out, "Level down: {}", level)?;
+        }
+    }
+    let average = if asked > 0 { elapsed / asked as f64 } else { 0.0 };
+    writeln!(out, "Score: {}/{}, best streak {}, {:.1}s per answer", correct, asked, best, average)?;
+    return Ok(correct);
+}
+
+// ANSI colors, decided once from `--color`, `NO_COLOR` and whether stdout is a terminal
+static COLOR: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+const RED: &str = "1;31";
+const GREEN: &str = "1;32";
+const YELLOW: &str = "33";
+const CYAN: &str = "36";
+const BLUE: &str = "34";
+const MAGENTA: &str = "35";
+
+fn use_color(mode: &str, no_color: bool, tty: bool) -> bool {
+    match mode {
+        "always" => true,
+        "never" => false,
+        _ => tty && !no_color,
+    }
+}
+
+fn paint(text: &str, color: &str) -> String {
+    if COLOR.load(std::sync::atomic::Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+// what to color in an expression: numbers, operators, and parentheses or brackets without a partner in red; 
+// nothing past a token that does not lex
+fn highlights(source: &str) -> Vec<(Span, &'static str)> {
+    let mut p = TokenParser::new(source.to_string());
+    let mut marks = Vec::new();
+    let mut open = Vec::new();     // index into marks of each open ( or [
+    while let Some(Ok(token)) = p.next() {
+        let color = match token {
+            Token::NUM(_) | Token::SUP(_) => MAGENTA,
+            Token::LPR | Token::LBR => {
+                open.push((marks.len(), token == Token::LPR));
+                ""
+            },
+            Token::RPR | Token::RBR => match open.last() {
+                Some(&(_, paren)) if paren == (token == Token::RPR) => {
+                    open.pop();
+                    ""
                 },
-                _ => panic!("Error: Open parenthesis."),
-            }
+                _ => RED,
+            },
+            Token::IDENT(_) | Token::REF(_) | Token::ENV(_) | Token::COMMA | Token::SEMI => "",
+            _ => BLUE,
+        };
+        marks.push((p.span(), color));
+    }
+    for (i, _) in open {
+        marks[i].1 = RED;
+    }
+    marks.retain(|(_, color)| !color.is_empty());
+    return marks;
+}
+
+fn highlight(source: &str) -> String {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut out = String::new();
+    let mut at = 0;
+    for (span, color) in highlights(source) {
+        out.extend(&chars[at..span.start]);
+        out += &paint(&chars[span.start..span.end].iter().collect::<String>(), color);
+        at = span.end;
+    }
+    out.extend(&chars[at..]);
+    return out;
+}
+
+// the source line with `^` under the span
+fn caret(source: &str, span: Span) -> String {
+    let marker = "^".repeat((span.end - span.start).max(1));
+    format!("   | {}\n   | {}{}", highlight(source.trim_end()), " ".repeat(span.start), paint(&marker, YELLOW))
+}
+
+// a parse error with a caret under where it went wrong, and what was expected there
+fn diagnostic(source: &str, e: &CalcError, span: Span) -> String {
+    let mut text = paint(&e.to_string(), RED);
+    // the caret can't point into a continued expression
+    if !source.contains('\n') {
+        text += &format!("\n{}", caret(source, span));
+        if let Some(h) = hint(e.code()) {
+            text += &format!(" {}", paint(h, YELLOW));
         }
-        _ => {
-            panic!("Error: Illegal factor: {:?}", t0);
+    }
+    return text;
+}
+
+// command line interface, the tables below also drive `--help` and shell completions
+// flags and commands taking a value, e.g. `--sheet FILE` or `completions SHELL`
+struct Arg {
+    name: &'static str,
+    value: Option<&'static str>,            // placeholder of the argument's value
+    choices: &'static [&'static str],       // accepted values, empty for free-form
+    help: &'static str,
+}
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell", "rlwrap"];
+const REPL_COMMANDS: &[&str] = &[":debug", ":mode", ":again", ":undo", ":save", ":load", ":history", "M+", "M-", "MR", "MC", "ans"];
+const COLOR_MODES: &[&str] = &["auto", "always", "never"];
+const OUTPUT_FORMATS: &[&str] = &["text", "csv", "tsv"];
+const PROGRESS_MODES: &[&str] = &["auto", "on", "off"];
+const OUTPUT_BASES: &[&str] = &["dec", "hex", "oct", "bin"];
+const SHORT_FLAGS: &[(&str, &str)] = &[("-v", "--verbose"), ("-t", "--predicate"), ("-f", "--file")];
+
+const FLAGS: &[Arg] = &[
+    Arg { name: "--sheet", value: Some("FILE"), choices: &[], help: "evaluate a CSV/TSV grid of cells and formulas" },
+    Arg { name: "--predicate", value: Some("EXPR"), choices: &[], help: "print nothing, exit 0 if EXPR is non-zero, 1 if zero, 2 on error" },
+    Arg { name: "--file", value: Some("FILE"), choices: &[], help: "evaluate every line of FILE as an expression" },
+    Arg { name: "--output", value: Some("FORMAT"), choices: OUTPUT_FORMATS, help: "--file: `input,result,error` rows for csv and tsv (default text)" },
+    Arg { name: "--progress", value: Some("WHEN"), choices: PROGRESS_MODES, help: "--file, --sheet: progress bar on stderr (default auto: terminal only)" },
+    Arg { name: "--round-step", value: Some("N"), choices: &[], help: "round the result to the nearest multiple of N, e.g. 0.05" },
+    Arg { name: "--export-shell", value: None, choices: &[], help: "print `export NAME=value` lines (each assigned variable and RESULT, or the formula cells of --sheet)" },
+    Arg { name: "--deg", value: None, choices: &[], help: "sin, cos and tan take degrees (`:mode deg` at the prompt)" },
+    Arg { name: "--exact-roots", value: None, choices: &[], help: "make isqrt and iroot fail when the root is not an integer" },
+    Arg { name: "--bignum", value: None, choices: &[], help: "integers of any size instead of overflowing 32 bits" },
+    Arg { name: "--decimal", value: None, choices: &[], help: "exact base-10 fractions instead of floats, e.g. for money" },
+    Arg { name: "--overflow", value: Some("MODE"), choices: OVERFLOW_MODES, help: "past 32 bits: an error (checked, the default), wrap around or saturate" },
+    Arg { name: "--nan", value: Some("POLICY"), choices: NAN_POLICIES, help: "a NaN or infinity from finite numbers: the result (propagate, the default) or an error" },
+    Arg { name: "--all-bases", value: None, choices: &[], help: "also print the result in hex, octal and binary" },
+    Arg { name: "--eng", value: None, choices: &[], help: "print numbers with an exponent that is a multiple of 3, 12.3e3" },
+    Arg { name: "--si", value: None, choices: &[], help: "print numbers with an SI prefix, 12.3k" },
+    Arg { name: "--sigfig", value: Some("N"), choices: &[], help: "round printed numbers to N significant digits" },
+    Arg { name: "--locale", value: Some("NAME"), choices: LOCALES, help: "read and print numbers with this locale's separators, 1.234,5 in de (default c)" },
+    Arg { name: "--obase", value: Some("BASE"), choices: OUTPUT_BASES, help: "print integer results as 0x, 0o or 0b literals (default dec)" },
+    Arg { name: "--roman", value: None, choices: &[], help: "print the result as a Roman numeral" },
+    Arg { name: "--explain", value: None, choices: &[], help: "show the evaluation as a sequence of reductions" },
+    Arg { name: "--format", value: Some("FORMAT"), choices: FORMATS, help: "how REPR prints the expression, `infix` with only the needed parentheses" },
+    Arg { name: "--check", value: None, choices: &[], help: "only report syntax errors, of EXPR or every line of --file, without evaluating" },
+    Arg { name: "--dump-ast", value: None, choices: &[], help: "print the parsed expression as JSON instead of evaluating it" },
+    Arg { name: "--load-ast", value: Some("FILE"), choices: &[], help: "evaluate an expression saved with --dump-ast" },
+    Arg { name: "--json", value: None, choices: &[], help: "print the input, result and repr (or the error) as one JSON object" },
+    Arg { name: "--daemon", value: None, choices: &[], help: "answer {\"id\", \"expr\"} JSON lines on stdin with --json lines, keeping variables" },
+    Arg { name: "--serve", value: Some("ADDR"), choices: &[], help: "answer `POST /eval` requests on ADDR (e.g. 127.0.0.1:8080) with the --json object" },
+    Arg { name: "--partial", value: None, choices: &[], help: "print what is left after folding, e.g. 2 * x + 12, when variables are unset" },
+    Arg { name: "--simplify", value: None, choices: &[], help: "also print the expression with constants folded and identities removed" },
+    Arg { name: "--show-grouping", value: None, choices: &[], help: "print the expression with all groupings made explicit" },
+    Arg { name: "--width", value: Some("N"), choices: &[], help: "break the repr, infix, --simplify and --show-grouping lines longer than N characters" },
+    Arg { name: "--session", value: Some("FILE"), choices: &[], help: "restore the prompt's variables and history from FILE, and save them there at the end" },
+    Arg { name: "--auto-close", value: None, choices: &[], help: "close parentheses left open at the prompt instead of asking for more" },
+    Arg { name: "--deny-warnings", value: None, choices: &[], help: "exit with an error if the expression has lint warnings" },
+    Arg { name: "--verbose", value: None, choices: &[], help: "print tokens, parse tree, passes and timings" },
+    Arg { name: "--trace", value: None, choices: &[], help: "log every node evaluation to stderr" },
+    Arg { name: "--trace-file", value: Some("FILE"), choices: &[], help: "log every node evaluation to FILE" },
+    Arg { name: "--trace-json", value: Some("FILE"), choices: &[], help: "write the evaluation tree with timings as JSON to FILE" },
+    Arg { name: "--problems", value: Some("FILE"), choices: &[], help: "grade: one expression per line" },
+    Arg { name: "--answers", value: Some("FILE"), choices: &[], help: "grade: one answer per line, matching --problems" },
+    Arg { name: "--tolerance", value: Some("N"), choices: &[], help: "grade: accepted absolute error (default 1e-9)" },
+    Arg { name: "--depth", value: Some("N"), choices: &[], help: "gen: maximum operator nesting (default 3)" },
+    Arg { name: "--ops", value: Some("LIST"), choices: &[], help: "gen: operators to use, e.g. '+,-,*,/' (default all)" },
+    Arg { name: "--count", value: Some("N"), choices: &[], help: "gen: number of expressions (default 10)" },
+    Arg { name: "--seed", value: Some("N"), choices: &[], help: "seed for random generation and rand()" },
+    Arg { name: "--with-answers", value: None, choices: &[], help: "gen: print ` = <result>` after each expression" },
+    Arg { name: "--level", value: Some("N"), choices: &[], help: "quiz: starting difficulty, 1 to 5 (default 1)" },
+    Arg { name: "--color", value: Some("WHEN"), choices: COLOR_MODES, help: "colorize output (default auto, off with NO_COLOR)" },
+    Arg { name: "--no-color", value: None, choices: &[], help: "the same as --color never" },
+    Arg { name: "--no-env", value: None, choices: &[], help: "make $NAME and env(\"NAME\") an error instead of reading the environment" },
+    Arg { name: "--max-depth", value: Some("N"), choices: &[], help: "reject expressions nested deeper (default 100)" },
+    Arg { name: "--config", value: Some("FILE"), choices: &[], help: "operator aliases (default $CALC_CONFIG or ~/.calcrc)" },
+    Arg { name: "--lang", value: Some("LANG"), choices: LANGS, help: "language of error messages (default from the locale)" },
+    Arg { name: "--help", value: None, choices: &[], help: "print this help" },
+];
+
+const COMMANDS: &[Arg] = &[
+    Arg { name: "completions", value: Some("SHELL"), choices: SHELLS, help: "print a shell completion script, or the words for Tab at the prompt with rlwrap" },
+    Arg { name: "test", value: Some("FILE"), choices: &[], help: "run the `assert` lines of a .calc file" },
+    Arg { name: "grade", value: None, choices: &[], help: "score --answers against --problems" },
+    Arg { name: "gen", value: None, choices: &[], help: "print random well-formed expressions" },
+    Arg { name: "quiz", value: None, choices: &[], help: "practice arithmetic, difficulty adapts to your answers" },
+    Arg { name: "diff", value: None, choices: &[], help: "print the derivative of EXPR by VAR, e.g. diff \"x*x + 3*x\" x" },
+    Arg { name: "autodiff", value: None, choices: &[], help: "print the slope of EXPR by VAR at AT, exactly and without a symbolic derivative" },
+    Arg { name: "solve", value: None, choices: &[], help: "print the x with EQUATION true, linear or quadratic, e.g. solve \"2*x + 3 = 11\" x" },
+    Arg { name: "eval", value: None, choices: &[], help: "evaluate EXPR, the same as without a command" },
+    Arg { name: "repl", value: None, choices: &[], help: "prompt for expressions, the same as without arguments" },
+    Arg { name: "fmt", value: None, choices: &[], help: "print EXPR with only the parentheses it needs (or in --format) without evaluating it" },
+    Arg { name: "tokens", value: None, choices: &[], help: "print the tokens of EXPR with their spans, one per line" },
+    Arg { name: "ast", value: None, choices: &[], help: "print the parse tree of EXPR" },
+    Arg { name: "disasm", value: None, choices: &[], help: "print the bytecode EXPR compiles to, its constants and instructions" },
+];
+
+impl Arg {
+    // "--sheet FILE", "--color auto|always|never"
+    fn synopsis(&self) -> String {
+        match (self.value, self.choices.is_empty()) {
+            (Some(_), false) => format!("{} {}", self.name, self.choices.join("|")),
+            (Some(v), true) => format!("{} {}", self.name, v),
+            (None, _) => self.name.to_string(),
+        }
+    }
+}
+
+struct Cli {
+    flags: HashMap<&'static str, String>,
+    positional: Vec<String>,
+}
+
+impl Cli {
+    fn parse(args: &[String]) -> Self {
+        let mut cli = Cli { flags: HashMap::new(), positional: Vec::new() };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            // everything after `--` is the expression
+            if arg == "--" {
+                cli.positional.extend(iter.cloned());
+                break;
+            }
+            let arg = SHORT_FLAGS.iter().find(|(short, _)| short == arg).map_or(arg.as_str(), |(_, long)| long);
+            // a single dash may start an expression like "-1 * 2", and two one like "--5"
+            let Some(flag) = FLAGS.iter().find(|f| f.name == arg) else {
+                if arg.strip_prefix("--").is_some_and(|name| name.starts_with(|c: char| c.is_ascii_alphabetic())) {
+                    panic!("Error: Unknown flag: {}", arg);
+                }
+                cli.positional.push(arg.to_string());
+                continue;
+            };
+            let value = match flag.value {
+                Some(placeholder) => iter.next()
+                    .unwrap_or_else(|| panic!("Error: {} expects {}", flag.name, placeholder))
+                    .clone(),
+                None => String::new(),
+            };
+            if !flag.choices.is_empty() && !flag.choices.contains(&value.as_str()) {
+                panic!("Error: {} expects one of {}", flag.name, flag.choices.join("|"));
+            }
+            // no digits at all would print every number as 0
+            if flag.name == "--sigfig" && value.parse::<usize>() == Ok(0) {
+                panic!("Error: --sigfig expects at least 1 digit, got 0");
+            }
+            cli.flags.insert(flag.name, value);
         }
+        return cli;
+    }
+
+    fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(|v| v.as_str())
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+}
+
+fn usage() -> String {
+    let mut out = String::from("Usage: calc [FLAGS] [EXPR]\n       calc [FLAGS] -    (an EXPR per line of stdin, also when it is a pipe)\n       calc <COMMAND> [ARG]\n\nFlags:\n");
+    let flags = FLAGS.iter().map(|f| match SHORT_FLAGS.iter().find(|(_, long)| *long == f.name) {
+        Some((short, _)) => (format!("{}, {}", short, f.synopsis()), f.help),
+        None => (f.synopsis(), f.help),
+    }).collect::<Vec<_>>();
+    let commands = COMMANDS.iter().map(|c| (c.synopsis(), c.help)).collect::<Vec<_>>();
+    // one column for the help of flags and commands, two spaces past the longest synopsis
+    let width = flags.iter().chain(&commands).map(|(synopsis, _)| synopsis.chars().count()).max().unwrap_or(0) + 2;
+    for (synopsis, help) in flags {
+        out += &format!("  {:<width$}{}\n", synopsis, help);
+    }
+    out += "\nCommands:\n";
+    for (synopsis, help) in commands {
+        out += &format!("  {:<width$}{}\n", synopsis, help);
+    }
+    return out;
+}
+
+fn completions(shell: &str) -> String {
+    let flags = FLAGS.iter().map(|f| f.name).collect::<Vec<_>>().join(" ");
+    let commands = COMMANDS.iter().map(|c| c.name).collect::<Vec<_>>().join(" ");
+    // the functions an EXPR can call, offered alongside the commands
+    let functions = BUILTINS.iter().map(|b| b.name).collect::<Vec<_>>().join(" ");
+    let with_value = || FLAGS.iter().chain(COMMANDS).filter(|a| a.value.is_some());
+    let mut out = String::new();
+    match shell {
+        "bash" => {
+            out += "_calc() {\n    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+            out += "    case \"$prev\" in\n";
+            for a in with_value() {
+                let action = if a.choices.is_empty() { "-f".to_string() } else { format!("-W \"{}\"", a.choices.join(" ")) };
+                out += &format!("        {}) COMPREPLY=( $(compgen {} -- \"$cur\") ); return ;;\n", a.name, action);
+            }
+            out += "    esac\n";
+            out += &format!("    COMPREPLY=( $(compgen -W \"{} {} {}\" -- \"$cur\") )\n}}\ncomplete -F _calc calc\n", flags, commands, functions);
+        },
+        "zsh" => {
+            let action = |a: &Arg| match (a.value, a.choices.is_empty()) {
+                (Some(v), true) => format!(":{}:_files", v.to_lowercase()),
+                (Some(v), false) => format!(":{}:({})", v.to_lowercase(), a.choices.join(" ")),
+                (None, _) => String::new(),
+            };
+            out += "#compdef calc\n\n_calc() {\n    local -a commands functions\n    commands=(\n";
+            for c in COMMANDS {
+                out += &format!("        '{}:{}'\n", c.name, c.help);
+            }
+            out += &format!("    )\n    functions=({})\n    _arguments \\\n", functions);
+            for f in FLAGS {
+                out += &format!("        '{}[{}]{}' \\\n", f.name, f.help, action(f));
+            }
+            out += "        '1: :{_describe command commands; _describe function functions}' \\\n        '*:: :->args'\n";
+            out += "    case $words[1] in\n";
+            for c in COMMANDS {
+                out += &format!("        {}) _arguments '1{}' ;;\n", c.name, action(c));
+            }
+            out += "    esac\n}\n\n_calc \"$@\"\n";
+        },
+        "fish" => {
+            let action = |a: &Arg| match (a.value, a.choices.is_empty()) {
+                (Some(_), true) => " -r -F".to_string(),
+                (Some(_), false) => format!(" -x -a '{}'", a.choices.join(" ")),
+                (None, _) => String::new(),
+            };
+            for f in FLAGS {
+                out += &format!("complete -c calc -l {} -d '{}'{}\n", &f.name[2..], f.help, action(f));
+            }
+            for c in COMMANDS {
+                out += &format!("complete -c calc -n '__fish_use_subcommand' -a {} -d '{}'\n", c.name, c.help);
+                out += &format!("complete -c calc -n '__fish_seen_subcommand_from {}'{}\n", c.name, action(c));
+            }
+            out += &format!("complete -c calc -n '__fish_use_subcommand' -a '{}' -d 'function'\n", functions);
+        },
+        "powershell" => {
+            out += "Register-ArgumentCompleter -Native -CommandName calc -ScriptBlock {\n";
+            out += "    param($wordToComplete, $commandAst, $cursorPosition)\n";
+            out += "    $prev = $commandAst.CommandElements | Where-Object { $_.Extent.EndOffset -lt $cursorPosition } | Select-Object -Last 1\n";
+            out += "    $words = switch ($prev.ToString()) {\n";
+            for a in with_value() {
+                let words = if a.choices.is_empty() { "Get-ChildItem -Name".to_string() } else { format!("@('{}')", a.choices.join("', '")) };
+                out += &format!("        '{}' {{ {} }}\n", a.name, words);
+            }
+            out += &format!("        default {{ @('{}') }}\n    }}\n", [flags, commands, functions].join(" ").replace(' ', "', '"));
+            out += "    $words | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n";
+            out += "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n";
+        },
+        // words for Tab at the prompt, `rlwrap -f <(calc completions rlwrap) calc`; 
+        // rlwrap also completes the words typed before, so variables are offered once set
+        "rlwrap" => {
+            for word in symbols(&Env::default()).iter().map(|s| s.as_str()).chain(REPL_COMMANDS.iter().copied()) {
+                out += &format!("{}\n", word);
+            }
+        },
+        _ => panic!("Error: Unsupported shell '{}', expected one of {}", shell, SHELLS.join("|")),
     }
+    return out;
 }
 
+// the binary reports its errors by panicking with their message
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<String>() {
+        Some(msg) => msg.clone(),
+        None => payload.downcast_ref::<&str>().unwrap_or(&"unknown error").to_string(),
+    }
+}
 
 fn main(){
+    // before parsing, so a usage error is reported like any other; red once --color is known
+    std::panic::set_hook(Box::new(|info| eprintln!("{}", paint(&panic_message(info.payload()), RED))));
     let args = std::env::args().collect::<Vec<String>>();
+    let cli = Cli::parse(&args[1..]);
+    set_lang(&cli.flag("--lang").map_or_else(lang_from_env, |l| l.to_string()));
+    let no_color = std::env::var("NO_COLOR").is_ok_and(|v| !v.is_empty());
+    let tty = std::io::IsTerminal::is_terminal(&std::io::stdout());
+    let mode = if cli.has("--no-color") { "never" } else { cli.flag("--color").unwrap_or("auto") };
+    COLOR.store(use_color(mode, no_color, tty), std::sync::atomic::Ordering::Relaxed);
+    load_config(cli.flag("--config"));
+    set_exact_roots(cli.has("--exact-roots"));
+    set_degrees(cli.has("--deg"));
+    if let Some(seed) = cli.flag("--seed") {
+        set_seed(parse_number(seed, "--seed"));
+    }
+    if let Some(depth) = cli.flag("--max-depth") {
+        set_max_depth(parse_number(depth, "--max-depth"));
+    }
+    set_bignum(cli.has("--bignum"));
+    set_decimal(cli.has("--decimal"));
+    set_env_vars(!cli.has("--no-env"));
+    set_locale(Locale::from_name(cli.flag("--locale").unwrap_or("c")).unwrap());
+    set_overflow(Overflow::from_name(cli.flag("--overflow").unwrap_or("checked")).unwrap());
+    set_nan_policy(NanPolicy::from_name(cli.flag("--nan").unwrap_or("propagate")).unwrap());
+
+    if cli.has("--help") {
+        print!("{}", usage());
+        return;
+    }
+    if let Some(path) = cli.flag("--sheet") {
+        run_sheet(path, show_progress(&cli), cli.has("--export-shell"));
+        return;
+    }
+    if let Some(expr) = cli.flag("--predicate") {
+        std::process::exit(predicate(expr));
+    }
+    if let Some(path) = cli.flag("--load-ast") {
+        let json = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e));
+        let n = Expr::from_json(&json).unwrap_or_else(|e| panic!("Error: {}: {}", path, e));
+        println!("Result: {}", n.eval().and_then(|v| round_result(&cli, v)).unwrap_or_else(|e| panic!("{}", e)));
+        return;
+    }
+    if cli.has("--daemon") {
+        let format = Format::from_name(cli.flag("--format").unwrap_or("repr")).unwrap();
+        daemon(&mut std::io::stdin().lock(), &mut std::io::stdout(), format, notation(&cli)).expect("Failed to run the daemon");
+        return;
+    }
+    if let Some(addr) = cli.flag("--serve") {
+        serve(addr, Format::from_name(cli.flag("--format").unwrap_or("repr")).unwrap(), notation(&cli));
+        return;
+    }
+    if let Some(path) = cli.flag("--file") {
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e));
+        if cli.has("--check") {
+            let failed = check_lines(&text, &mut std::io::stdout()).expect("Failed to write output");
+            std::process::exit(if failed > 0 { 1 } else { 0 });
+        }
+        if cli.has("--export-shell") {
+            let mut env = Env::default();
+            for (no, line) in text.lines().map(|l| l.trim()).enumerate().filter(|(_, l)| !l.is_empty() && !l.starts_with('#')) {
+                if let Err(e) = parse_spanned(line).map_err(|(e, _)| e).and_then(|n| n.eval_in(&mut env)) {
+                    panic!("line {}: {}", no + 1, e);
+                }
+            }
+            for line in shell_exports(&env, &cli).unwrap_or_else(|e| panic!("{}", e)) {
+                println!("{}", line);
+            }
+            return;
+        }
+        let progress = show_progress(&cli).then(|| Progress::new(0));
+        let failed = run_batch(&text, cli.flag("--output").unwrap_or("text"), &mut std::io::stdout(), progress).expect("Failed to write output");
+        std::process::exit(if failed > 0 { 1 } else { 0 });
+    }
+    // `calc eval EXPR` is `calc EXPR`, also for an EXPR that is the name of a command
+    let mut positional = cli.positional.clone();
+    let eval = positional.first().is_some_and(|p| p == "eval");
+    if eval {
+        positional.remove(0);
+        if positional.is_empty() {
+            panic!("Error: eval expects EXPR");
+        }
+    }
+    if !eval && let Some(cmd) = COMMANDS.iter().find(|c| positional.first().map(|s| s.as_str()) == Some(c.name)) {
+        let arg = match cmd.value {
+            Some(_) => cli.positional.get(1)
+                .unwrap_or_else(|| panic!("Error: {} expects {}", cmd.name, cmd.synopsis())).as_str(),
+            None => "",
+        };
+        match cmd.name {
+            "completions" => print!("{}", completions(arg)),
+            "test" => {
+                let text = std::fs::read_to_string(arg)
+                    .unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", arg, e));
+                let failed = run_tests(&text, &mut std::io::stdout()).expect("Failed to write output");
+                std::process::exit(if failed > 0 { 1 } else { 0 });
+            },
+            "grade" => {
+                let read = |flag: &str| {
+                    let path = cli.flag(flag).unwrap_or_else(|| panic!("Error: grade expects {} FILE", flag));
+                    std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e))
+                };
+                let tolerance = cli.flag("--tolerance").map_or(1e-9, |t| {
+                    t.parse().unwrap_or_else(|_| panic!("Error: Invalid tolerance: {}", t))
+                });
+                grade(&read("--problems"), &read("--answers"), tolerance, &mut std::io::stdout())
+                    .expect("Failed to write output");
+            },
+            "gen" => {
+                let ops = cli.flag("--ops").map_or(GEN_OPS.to_vec(), |list| {
+                    list.split(',').map(|op| match op.trim() {
+                        op if op.len() == 1 && GEN_OPS.contains(&op.chars().next().unwrap()) => op.chars().next().unwrap(),
+                        op => panic!("Error: Unsupported operator for gen: '{}'", op),
+                    }).collect()
+                });
+                let mut rng = cli.flag("--seed").map_or_else(Rng::from_time, |seed| Rng(parse_number(seed, "--seed")));
+                let depth = cli.flag("--depth").map_or(3, |d| parse_number(d, "--depth"));
+                let count = cli.flag("--count").map_or(10, |c| parse_number(c, "--count"));
+                for (expr, value) in generate(&mut rng, depth, &ops, count) {
+                    if cli.has("--with-answers") {
+                        println!("{} = {}", expr, value);
+                    } else {
+                        println!("{}", expr);
+                    }
+                }
+            },
+            "quiz" => {
+                let mut rng = cli.flag("--seed").map_or_else(Rng::from_time, |seed| Rng(parse_number(seed, "--seed")));
+                let level = cli.flag("--level").map_or(1, |l| parse_number(l, "--level"));
+                if !(1..=QUIZ_LEVELS.len()).contains(&level) {
+                    panic!("Error: --level expects 1 to {}, got {}", QUIZ_LEVELS.len(), level);
+                }
+                quiz(&mut rng, level, &mut std::io::stdin().lock(), &mut std::io::stdout()).expect("Failed to run quiz");
+            },
+            "diff" => {
+                let (Some(expr), Some(var)) = (cli.positional.get(1), cli.positional.get(2)) else {
+                    panic!("Error: diff expects EXPR VAR");
+                };
+                let n = parse(expr).unwrap_or_else(|e| {
+                    eprintln!("{}", paint(&e.to_string(), RED));
+                    std::process::exit(1);
+                });
+                let d = n.derivative(var).unwrap_or_else(|e| {
+                    eprintln!("{}", paint(&e.to_string(), RED));
+                    std::process::exit(1);
+                });
+                println!("{}", infix(&d.simplify()));
+            },
+            "autodiff" => {
+                let (Some(expr), Some(var), Some(at)) = (cli.positional.get(1), cli.positional.get(2), cli.positional.get(3)) else {
+                    panic!("Error: autodiff expects EXPR VAR AT");
+                };
+                let slope = parse(expr).and_then(|n| n.autodiff(var, eval_str(at)?.float()?)).unwrap_or_else(|e| {
+                    eprintln!("{}", paint(&e.to_string(), RED));
+                    std::process::exit(1);
+                });
+                println!("{}", Value::Float(slope.1));
+            },
+            "solve" => {
+                let (Some(equation), Some(var)) = (cli.positional.get(1), cli.positional.get(2)) else {
+                    panic!("Error: solve expects EQUATION VAR");
+                };
+                let n = parse_equation(equation).unwrap_or_else(|(e, span)| {
+                    eprintln!("{}", diagnostic(equation, &e, span));
+                    std::process::exit(1);
+                });
+                let roots = n.solve(var).unwrap_or_else(|e| {
+                    eprintln!("{}", paint(&e.to_string(), RED));
+                    std::process::exit(1);
+                });
+                if roots.is_empty() {
+                    println!("no real solution");
+                    std::process::exit(1);
+                }
+                for root in roots {
+                    println!("{} = {}", var, root);
+                }
+            },
+            "repl" => repl(&cli, &mut std::io::stdin().lock(), &mut std::io::stdout()).expect("Failed to run the prompt"),
+            "fmt" | "tokens" | "ast" | "disasm" => {
+                if positional.len() < 2 {
+                    panic!("Error: {} expects EXPR", cmd.name);
+                }
+                let expr = &positional[1..].join(" ");
+                let lines = match cmd.name {
+                    "tokens" => token_lines(expr).unwrap_or_else(|(e, span)| {
+                        eprintln!("{}", diagnostic(expr, &e, span));
+                        std::process::exit(1);
+                    }),
+                    "disasm" => compile(&parse_reporting(expr)).disassemble(),
+                    "fmt" => vec![highlight(&Format::from_name(cli.flag("--format").unwrap_or("infix")).unwrap().render(&parse_reporting(expr)))],
+                    _ => {
+                        let mut lines = Vec::new();
+                        tree(&parse_reporting(expr), &expr.chars().collect::<Vec<char>>(), 0, &mut lines);
+                        lines
+                    },
+                };
+                println!("{}", lines.join("\n"));
+            },
+            _ => unreachable!(),
+        }
+        return;
+    }
+
+    let piped = positional.is_empty() && !std::io::IsTerminal::is_terminal(&std::io::stdin());
+    if piped || positional == ["-"] {
+        if cli.has("--check") {
+            let text = std::io::read_to_string(std::io::stdin()).expect("Failed to read stdin");
+            let failed = check_lines(&text, &mut std::io::stdout()).expect("Failed to write output");
+            std::process::exit(if failed > 0 { 1 } else { 0 });
+        }
+        let failed = run_pipe(&mut std::io::stdin().lock(), cli.flag("--output").unwrap_or("text"), &mut std::io::stdout()).expect("Failed to write output");
+        std::process::exit(if failed > 0 { 1 } else { 0 });
+    }
+    if positional.is_empty() {
+        repl(&cli, &mut std::io::stdin().lock(), &mut std::io::stdout()).expect("Failed to run the prompt");
+        return;
+    }
+    // `calc 1 + 2 * 3` without quotes
+    let input = positional.join(" ");
+    let format = Format::from_name(cli.flag("--format").unwrap_or("repr")).unwrap();
+    if cli.has("--check") {
+        if cli.has("--json") {
+            let (ok, json) = check_json(&input);
+            println!("{}", json);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        parse_reporting(&input);
+        return;
+    }
+    if cli.has("--json") {
+        let (ok, json) = json_report(&input, format, notation(&cli));
+        println!("{}", json);
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let verbose = cli.has("--verbose");
+    let mut timings = Vec::new();
+    if verbose {
+        let started = std::time::Instant::now();
+        let tokens = TokenParser::new(input.clone()).map_while(Result::ok).map(|t| format!("{:?}", t)).collect::<Vec<String>>();
+        timings.push(("tokenize", started.elapsed()));
+        println!("TOKENS: {}", tokens.join(" "));
+    }
+    let started = std::time::Instant::now();
+    let n = parse_reporting(&input);
+    timings.push(("parse", started.elapsed()));
+    if cli.has("--dump-ast") {
+        println!("{}", n.to_json());
+        return;
+    }
+    if cli.has("--partial") {
+        let format = Format::from_name(cli.flag("--format").unwrap_or("infix")).unwrap();
+        println!("Result: {}", highlight(&format.render(&n.partial_eval(&Env::default()))));
+        return;
+    }
+    if verbose {
+        let mut lines = Vec::new();
+        tree(&n, &input.chars().collect::<Vec<char>>(), 1, &mut lines);
+        println!("AST:\n{}", lines.join("\n"));
+    }
+
+    let started = std::time::Instant::now();
+    let mut lints = Vec::new();
+    lint(&n, &mut lints);
+    lint_assignments(&n, &mut lints);
+    timings.push(("lint", started.elapsed()));
+    if verbose {
+        println!("PASSES: lint ({} warning(s))", lints.len());
+    }
+    for l in &lints {
+        let text: String = input.chars().skip(l.span.start).take(l.span.end - l.span.start).collect();
+        eprintln!("{}: {}\n  --> {}..{}: {}", paint("warning", YELLOW), l.message, l.span.start, l.span.end, text);
+        eprintln!("{}", caret(&input, l.span));
+    }
+    if !lints.is_empty() && cli.has("--deny-warnings") {
+        eprintln!("{}", paint(&format!("Error: {} warning(s) denied", lints.len()), RED));
+        std::process::exit(1);
+    }
+
+    if cli.has("--export-shell") {
+        let mut env = Env::default();
+        let result = n.eval_in(&mut env).and_then(|v| round_result(&cli, v)).unwrap_or_else(|e| panic!("{}", e));
+        for line in shell_exports(&env, &cli).unwrap_or_else(|e| panic!("{}", e)) {
+            println!("{}", line);
+        }
+        println!("export RESULT={}", shell_word(&result.to_string()));
+        return;
+    }
+
+    // with --width a long line goes on under its label, broken where it can be
+    let width = cli.flag("--width").map(|w| parse_number(w, "--width"));
+    let show = |label: &str, n: &Expr, format: Option<Format>| match width.map(|w| pretty(n, w, format)) {
+        Some(lines) if lines.len() > 1 => println!("{}:\n{}", label, paint(&lines.join("\n"), CYAN)),
+        _ => println!("{}: {}", label, paint(&format.map_or_else(|| grouping(n), |f| f.render(n)), CYAN)),
+    };
+    show("REPR", &n, Some(format));
+    if cli.has("--simplify") {
+        show("SIMPLIFIED", &n.simplify(), Some(format));
+    }
+    if cli.has("--show-grouping") {
+        show("GROUPING", &n, None);
+    }
+    // an error is reported by the evaluation below
+    if cli.has("--explain") {
+        for step in explain(&n).unwrap_or_default() {
+            println!("{}", step);
+        }
+    }
+    let started = std::time::Instant::now();
+    let trace = trace_output(&cli).map(|out| Trace { source: input.chars().collect(), out });
+    let mut observers = (trace, cli.has("--trace-json").then(|| JsonTrace::new(&input)));
+    let result = n.eval_with(&mut Env::default(), &mut observers);
+    timings.push(("eval", started.elapsed()));
+    // written before an error is reported, with the nodes it stopped
+    if let (Some(path), (_, Some(mut trace))) = (cli.flag("--trace-json"), observers) {
+        if let Err(e) = &result {
+            trace.fail(e);
+        }
+        std::fs::write(path, trace.root + "\n").unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+    }
+    let result = result.and_then(|v| round_result(&cli, v)).unwrap_or_else(|e| panic!("{}", e));
+    let shown = if cli.has("--roman") {
+        result.int().and_then(to_roman).unwrap_or_else(|| panic!("Error: {} has no Roman numeral, only 1 to 3999 do", result))
+    } else {
+        result_text(&cli, &result)
+    };
+    match matrix_lines(&result) {
+        Some(rows) => println!("Result:\n{}", paint(&rows.join("\n"), GREEN)),
+        None => println!("Result: {}", paint(&shown, GREEN)),
+    }
+    if cli.has("--all-bases") {
+        let result = result.int().unwrap_or_else(|| panic!("Error: --all-bases needs an integer result, got {}", result));
+        for line in other_bases(result) {
+            println!("{}", line);
+        }
+    }
+    if verbose {
+        let phases = timings.iter().map(|(phase, t)| format!("{} {:?}", phase, t)).collect::<Vec<String>>();
+        println!("TIMINGS: {}", phases.join(", "));
+    }
+}
+
+// every parse error of `input` on stderr and exit 1 if there is one
+fn parse_reporting(input: &str) -> Expr {
+    parse_recovering(input).unwrap_or_else(|errors| {
+        for (e, span) in errors {
+            eprintln!("{}", diagnostic(input, &e, span));
+        }
+        std::process::exit(1);
+    })
+}
+
+// `calc tokens`: "0..1 NUM(1)"
+fn token_lines(input: &str) -> Result<Vec<String>, (CalcError, Span)> {
+    let mut p = TokenParser::new(input.to_string());
+    let mut lines = Vec::new();
+    while let Some(token) = p.next() {
+        let token = token.map_err(|e| {
+            let span = e.position().map_or(p.span(), |idx| Span { start: idx, end: idx + 1 });
+            (e, span)
+        })?;
+        lines.push(format!("{}..{} {:?}", p.span().start, p.span().end, token));
+    }
+    return Ok(lines);
+}
+
+fn round_result(cli: &Cli, result: Value) -> Result<Value, CalcError> {
+    let Some(step) = cli.flag("--round-step") else { return Ok(result); };
+    let step = Value::parse(step).unwrap_or_else(|| panic!("Error: --round-step expects a number, got '{}'", step));
+    return roundto(&result, &step);
+}
+
+// a matrix one row per line, the columns lined up
+fn matrix_lines(v: &Value) -> Option<Vec<String>> {
+    let Value::List(rows) = v else { return None; };
+    let cells = rows.iter()
+        .map(|r| match r { Value::List(c) => Some(c.iter().map(|v| v.to_string()).collect::<Vec<String>>()), _ => None })
+        .collect::<Option<Vec<Vec<String>>>>()?;
+    let width = cells.iter().flatten().map(|c| c.chars().count()).max()?;
+    let line = |row: &Vec<String>| format!("  [{}]", row.iter().map(|c| format!("{:>1$}", c, width)).collect::<Vec<String>>().join(", "));
+    return Some(cells.iter().map(line).collect());
+}
+
+// a result as printed: in --eng, --si or --sigfig if one is given, 
+// otherwise with `--obase` integers as literals of that base, floats as they are
+fn result_text(cli: &Cli, v: &Value) -> String {
+    let notation = notation(cli);
+    if notation != Notation::default() {
+        return notation.show(v);
+    }
+    let radix = match cli.flag("--obase") {
+        Some("hex") => 16,
+        Some("oct") => 8,
+        Some("bin") => 2,
+        _ => 10,
+    };
+    return v.to_radix(radix);
+}
+
+fn notation(cli: &Cli) -> Notation {
+    let sigfig = cli.flag("--sigfig").map(|n| parse_number(n, "--sigfig"));
+    let locale = Locale::from_name(cli.flag("--locale").unwrap_or("c")).unwrap();
+    return Notation { eng: cli.has("--eng"), si: cli.has("--si"), sigfig, locale };
+}
+
+// `--all-bases`: lined up under "Result:", negative values in two's complement
+fn other_bases(v: i32) -> Vec<String> {
+    vec![format!("   hex: {:#x}", v), format!("   oct: {:#o}", v), format!("   bin: {:#b}", v)]
+}
+
+// open parentheses left at the end of `s`, or the index of a ')' closing nothing
+fn paren_balance(s: &str) -> Result<usize, usize> {
+    let mut open = 0;
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '(' => open += 1,
+            ')' if open == 0 => return Err(i),
+            ')' => open -= 1,
+            _ => {},
+        }
+    }
+    return Ok(open);
+}
+
+// `--json`: {"input", "ok", "result", "repr"} or {"input", "ok", "error": {"code", "message", "position"}}
+fn json_report(input: &str, format: Format, notation: Notation) -> (bool, String) {
+    json_report_in(input, format, notation, &mut Env::default())
+}
+
+fn json_report_in(input: &str, format: Format, notation: Notation, env: &mut Env) -> (bool, String) {
+    // of the evaluation errors only a division by zero has a position
+    let failure = |e: CalcError, position: Option<usize>| {
+        let position = position.map_or("null".to_string(), |p| p.to_string());
+        format!("{{\"input\": {}, \"ok\": false, \"error\": {{\"code\": {}, \"message\": {}, \"position\": {}}}}}",
+            json_string(input), json_string(e.code()), json_string(&e.message()), position)
+    };
+    let n = match parse_spanned(input) {
+        Ok(n) => n,
+        Err((e, span)) => return (false, failure(e, Some(span.start))),
+    };
+    match n.eval_in(env) {
+        Ok(v) => (true, format!("{{\"input\": {}, \"ok\": true, \"result\": {}, \"repr\": {}}}",
+            json_string(input), json_result(&v, notation), json_string(&format.render(&n)))),
+        Err(e) => {
+            let position = e.position();
+            (false, failure(e, position))
+        },
+    }
+}
+
+// a number stays one in --eng or --sigfig, 12.3e3, other notations like 12.3k become strings;
+// JSON has its own '.' and no grouping, whatever the --locale
+fn json_result(v: &Value, notation: Notation) -> String {
+    let notation = Notation { locale: Locale::default(), ..notation };
+    if notation == Notation::default() {
+        return json_number(v);
+    }
+    let text = notation.show(v);
+    return if matches!(v, Value::Int(_) | Value::Big(_) | Value::Float(_) | Value::Dec(_)) && text.parse::<f64>().is_ok() { text } else { json_string(&text) };
+}
+
+// `--daemon`: a request per line, {"id": 1, "expr": "x = 2"}, answered by a line with the `--json` object and the
+// same "id"; variables carry over from request to request
+fn daemon(input: &mut dyn std::io::BufRead, out: &mut dyn Write, format: Format, notation: Notation) -> std::io::Result<()> {
+    let mut env = Env::default();
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = Json::parse(&line);
+        let id = match request.as_ref().ok().and_then(|r| r.get("id")) {
+            Some(Json::Num(n)) => n.to_string(),
+            Some(Json::Str(s)) => json_string(s),
+            _ => "null".to_string(),
+        };
+        let expr = request.and_then(|r| {
+            r.get("expr").and_then(Json::as_str).map(String::from).ok_or("Expected an object with an \"expr\" string".to_string())
+        });
+        let json = match expr {
+            Ok(expr) => json_report_in(&expr, format, notation, &mut env).1,
+            Err(e) => format!("{{\"ok\": false, \"error\": {{\"code\": null, \"message\": {}, \"position\": null}}}}", json_string(&e)),
+        };
+        writeln!(out, "{{\"id\": {}, {}", id, &json[1..])?;
+        out.flush()?;
+    }
+}
 
-    let n: Box<dyn ASTNode>;
+// `--serve ADDR`: one connection at a time, a bad request gets its 4xx and the server carries on
+fn serve(addr: &str, format: Format, notation: Notation) {
+    let listener = std::net::TcpListener::bind(addr).unwrap_or_else(|e| panic!("Error: Cannot listen on {}: {}", addr, e));
+    eprintln!("Listening on http://{}/eval", listener.local_addr().map_or(addr.to_string(), |a| a.to_string()));
+    for stream in listener.incoming().flatten() {
+        let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(10)));
+        let _ = http_exchange(&mut std::io::BufReader::new(&stream), &mut &stream, format, notation);
+    }
+}
+
+const MAX_BODY: usize = 1 << 16;
 
-    if args.len() == 1 {
-        println!("Input your expr: "); 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).expect("Failed to read line");
-        let parser = TokenParser::new(input);
-        n = evaluate(parser);
-        println!("---")
+// `POST /eval` with the expression as the body, or as {"expr": "..."}, answered with the `--json` object
+fn http_exchange(input: &mut dyn std::io::BufRead, out: &mut dyn Write, format: Format, notation: Notation) -> std::io::Result<()> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') && name.trim().eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse().unwrap_or(usize::MAX);
+        }
     }
-    else {
-        let input = args[1].clone();
-        let parser = TokenParser::new(input);
-        n = evaluate(parser);
+    let refuse = |status: u16, message: &str| {
+        (status, format!("{{\"ok\": false, \"error\": {{\"code\": null, \"message\": {}, \"position\": null}}}}", json_string(message)))
+    };
+    let (status, body) = match (method, path) {
+        ("POST", "/eval") if length > MAX_BODY => refuse(413, "Request body too large"),
+        ("POST", "/eval") => {
+            let mut body = vec![0; length];
+            input.read_exact(&mut body)?;
+            match String::from_utf8(body) {
+                Ok(text) => {
+                    let json = Json::parse(&text).ok();
+                    let expr = json.as_ref().and_then(|j| j.get("expr")).and_then(Json::as_str).unwrap_or(&text);
+                    (200, json_report(expr.trim(), format, notation).1)
+                },
+                Err(_) => refuse(400, "The expression is not UTF-8"),
+            }
+        },
+        (_, "/eval") => refuse(405, "Use POST /eval"),
+        _ => refuse(404, "Not found, use POST /eval"),
+    };
+    let reason = match status { 200 => "OK", 400 => "Bad Request", 404 => "Not Found", 405 => "Method Not Allowed", _ => "Payload Too Large" };
+    write!(out, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body)?;
+    return out.flush();
+}
+
+// exit status of `-t`, like test(1): 0 for true, 1 for false, 2 for errors
+fn predicate(expr: &str) -> i32 {
+    match eval_str(expr).and_then(|v| v.is_zero()) {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(e) => {
+            eprintln!("{}", paint(&e.to_string(), RED));
+            2
+        },
     }
+}
 
-    println!("REPR: {}", n.repr());
-    println!("Result: {}", n.eval());
+fn parse_number<T: std::str::FromStr>(value: &str, flag: &str) -> T {
+    value.parse().unwrap_or_else(|_| panic!("Error: {} expects a number, got '{}'", flag, value))
+}
+
+fn trace_output(cli: &Cli) -> Option<Box<dyn std::io::Write>> {
+    if let Some(path) = cli.flag("--trace-file") {
+        let file = std::fs::File::create(path)
+            .unwrap_or_else(|e| panic!("Error: Cannot create {}: {}", path, e));
+        return Some(Box::new(file));
+    }
+    if cli.has("--trace") {
+        return Some(Box::new(std::io::stderr()));
+    }
+    return None;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn sheet(csv: &str) -> Vec<Vec<String>> {
+        let grid = csv.lines().map(|l| split_row(l, ',')).collect();
+        Sheet::new(grid).compute()
+    }
+
+    #[test]
+    fn test_cell_names(){
+        assert_eq!(parse_cell_name("A1"), Some((0, 0)));
+        assert_eq!(parse_cell_name("AB12"), Some((11, 27)));
+        assert_eq!(parse_cell_name("A0"), None);
+        assert_eq!(cell_name(11, 27), "AB12");
+    }
+
+    #[test]
+    fn test_sheet(){
+        let out = sheet("item,3,4\ntotal,=B1*C1,=B2-B3\n,=(B1+C1)*2,");
+        assert_eq!(out[1], vec!["total", "12", "-2"]);
+        assert_eq!(out[2], vec!["", "14", ""]);
+    }
+
+    #[test]
+    fn test_sheet_exports(){
+        let mut s = Sheet::new(vec![vec!["3".to_string(), "=A1*2".to_string()], vec!["=B1+A1".to_string()]]);
+        s.compute();
+        assert_eq!(s.exports(), vec!["export B1=6", "export A2=9"]);
+    }
+
+    #[test]
+    fn test_shell_exports(){
+        let mut env = Env::default();
+        parse("rate = 0.5; total = 3 m; rate = 2; total * rate").unwrap().eval_in(&mut env).unwrap();
+        assert_eq!(shell_exports(&env, &Cli::parse(&[])), Ok(vec!["export rate=2".to_string(), "export total='3 m'".to_string()]));
+        assert_eq!(shell_word("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    #[should_panic(expected = "Circular reference: A1 -> B1 -> A1")]
+    fn test_sheet_cycle(){
+        sheet("=B1,=A1+1");
+    }
+
+    #[test]
+    fn test_cli_parse(){
+        let args: Vec<String> = ["--sheet", "a.csv", "-v", "-1 * 2"].iter().map(|s| s.to_string()).collect();
+        let cli = Cli::parse(&args);
+        assert_eq!(cli.flag("--sheet"), Some("a.csv"));
+        assert!(cli.has("--verbose"));
+        assert_eq!(cli.positional, vec!["-1 * 2"]);
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<String>>();
+        let cli = Cli::parse(&args("-3 + --deg 2 --5"));
+        assert!(cli.has("--deg"));
+        assert_eq!(cli.positional, args("-3 + 2 --5"));
+        let cli = Cli::parse(&args("-v -- -v * 2"));
+        assert!(cli.has("--verbose"));
+        assert_eq!(cli.positional, args("-v * 2"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Error: --sigfig expects at least 1 digit, got 0")]
+    fn test_sigfig_zero(){
+        Cli::parse(&["--sigfig".to_string(), "0".to_string(), "1".to_string()]);
+    }
+
+    #[test]
+    fn test_usage(){
+        // every help text starts in the same column, past the longest synopsis
+        let text = usage();
+        let lines = text.lines().filter(|l| l.starts_with("  ") && !l.trim_start().starts_with("calc ")).collect::<Vec<&str>>();
+        let column = |l: &str| l[2..].find("  ").map(|i| i + 2 + l[i + 2..].find(|c: char| c != ' ').unwrap());
+        let columns = lines.iter().map(|l| column(l)).collect::<Vec<_>>();
+        assert!(columns.len() == FLAGS.len() + COMMANDS.len() && columns.iter().all(|c| c.is_some() && *c == columns[0]));
+    }
+
+    #[test]
+    fn test_completions(){
+        for shell in SHELLS.iter().filter(|s| **s != "rlwrap") {
+            let script = completions(shell);
+            assert!(script.contains("sheet") && script.contains("completions"), "{}", shell);
+            let words = script.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').collect::<Vec<&str>>();
+            assert!(BUILTINS.iter().all(|b| words.contains(&b.name)), "{}", shell);
+        }
+        let words = completions("rlwrap");
+        assert!(["sqrt", "pi", "km", ":save", "MR"].iter().all(|w| words.lines().any(|l| l == *w)));
+    }
+
+    #[test]
+    fn test_color(){
+        assert!(use_color("auto", false, true));
+        assert!(!use_color("auto", true, true));
+        assert!(!use_color("auto", false, false));
+        assert!(use_color("always", true, false));
+        assert!(!use_color("never", false, true));
+        assert_eq!(caret("8 / 4 + (7 / 2)", Span { start: 9, end: 14 }), "   | 8 / 4 + (7 / 2)\n   |          ^^^^^");
+        let marks = |s: &str| highlights(s).iter().map(|(span, color)| (span.start, *color)).collect::<Vec<_>>();
+        assert_eq!(marks("(2 + x) * 3"), [(1, MAGENTA), (3, BLUE), (8, BLUE), (10, MAGENTA)]);
+        assert_eq!(marks("((1)) ) [2)"), [(2, MAGENTA), (6, RED), (8, RED), (9, MAGENTA), (10, RED)]);
+        assert_eq!(marks("1 + @ 2"), [(0, MAGENTA), (2, BLUE)]);
+    }
+
+    #[test]
+    fn test_debug(){
+        let mut out = Vec::new();
+        debug("(1 + 2) * 3 - 4", &Env::default(), &mut "s\n\nn\np\nc\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("enter (1 + 2) * 3 - 4"));
+        assert!(out.contains("  enter (1 + 2) * 3\n"));
+        assert!(out.contains("1 + 2 → 3"));
+        assert!(out.contains("current: (1 + 2) * 3"));
+        assert!(out.contains("9 - 4 → 5"));
+        assert!(out.contains("Result: 5"));
+    }
+
+    #[test]
+    fn test_matrix_lines(){
+        let v = eval_str("[[1, -20], [300, 4]]").unwrap();
+        assert_eq!(matrix_lines(&v), Some(vec!["  [  1, -20]".to_string(), "  [300,   4]".to_string()]));
+        assert_eq!(matrix_lines(&eval_str("[1, 2]").unwrap()), None);
+    }
+
+    #[test]
+    fn test_repl(){
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut "x = 6\n\nx * (1 +\n 1)\n2 +\n:debug x + 2\nc\nx\n:history\n!2\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("> 6\n> > (… 12\n> Error[E0004]: Unexpected end of input\n   | 2 +\n   |    ^ expected a number, a name or '(' here\n"));
+        assert!(out.contains("Result: 8\n> 6\n> "));
+        assert!(out.contains("\n   2  x * (1 + 1)\n   3  2 +\n") && out.ends_with("> x * (1 + 1)\n12\n> \n"));
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut ":again\nr = 2\npi * r ^ 2 + extra\n:again extra = 0\n:again extra = 1; r = 1\nr\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "> Error: no expression to run again\n> 2\n> Error[E0024]: Unknown variable: extra\n\
+            > 12.566370614359172\n> 4.141592653589793\n> 2\n> \n");
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut "x = 1\nx = 2; y = 3\n:undo\nx + y\n:undo 3\n:undo x\nx\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "> 1\n> 3\n> y unset\n> Error[E0024]: Unknown variable: y\n\
+            > x = 1\nx unset\nError: nothing to undo\n> Error: expected a count after :undo, got x\n> x\n> \n");
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut "7 / (2 - 2)\n1 + 1\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "> Error[E0031]: Division by zero: 7 / 0\n   | 7 / (2 - 2)\n   |   ^\n> 2\n> \n");
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut "ans\n5\nM+\nans * 2\nM+\nM-\nMR\nans + 1\nMC\nMR\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "> Error[E0024]: Unknown variable: ans\n> 5\n> M = 5\n> 10\n> M = 15\n> M = 5\n> 5\n> 6\n> M = 0\n> 0\n> \n");
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut ":mode deg\nsin(90) + cos(0)\n:mode grad\n:mode rad\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "> angles in degrees\n> 2.0\n> Error: unknown mode grad, expected deg or rad\nangles in degrees\n> angles in radians\n> \n");
+
+        let mut out = Vec::new();
+        repl(&Cli::parse(&[]), &mut "2 * 3\nx = 4\n!1\n_1 + _2 * _3\n!9\n!0\n!x\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "> 6\n> 4\n> 2 * 3\n6\n> 30\n> Error: no entry 9 in the history\n> Error: no entry 0 in the history\n> 0\n> \n");
+
+        let path = std::env::temp_dir().join(format!("calc-session-{}.calc", std::process::id()));
+        let path = path.to_str().unwrap();
+        let args = ["--session".to_string(), path.to_string()];
+        repl(&Cli::parse(&args), &mut "x = 3 km\nd = 2024-03-01\n:history\n".as_bytes(), &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "# calc session\n#> x = 3 km\n#> d = 2024-03-01\n#> :history\n\
+            x = 3 km\nd = 2024-03-01\n");
+        let mut out = Vec::new();
+        repl(&Cli::parse(&args), &mut "d + 12 h\n:history\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(out.starts_with("restored 2 variables from "));
+        assert!(out.contains("> 2024-03-01T12:00:00\n>    1  x = 3 km\n   2  d = 2024-03-01\n   3  :history\n   4  d + 12 h\n   5  :history\n"));
+
+        // values that print as no literal still read back
+        let entries = "eps = 2.220446049250313e-16\nbig = 1e300 * 10\nn = nan\np = poly([1, -3, 2])\nxs = [1e-20, -0.5, nan]\n";
+        repl(&Cli::parse(&args), &mut entries.as_bytes(), &mut Vec::new()).unwrap();
+        let mut out = Vec::new();
+        repl(&Cli::parse(&args), &mut "eps == 2^-52\nbig\nn == n\npolyval(p, 2)\nxs\n".as_bytes(), &mut out).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("restored 5 variables from "), "{}", out);
+        assert!(out.ends_with("> 1\n> 1e301\n> 0\n> 0\n> [1e-20, -0.5, NaN]\n> \n"), "{}", out);
+    }
+
+    #[test]
+    fn test_run_tests(){
+        let suite = "# arithmetic\nassert 12 + 34 - (56/7)*8 == -18\nassert 1 + 1 == 3\n\nassert 2 - 2\n1 / 0\nrate = 3\nassert rate * 2 == 6\n";
+        let mut out = Vec::new();
+        assert_eq!(run_tests(suite, &mut out).unwrap(), 3);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("ok    line 2"));
+        assert!(out.contains("FAIL  line 3: assert 1 + 1 == 3 (left 2, right 3)"));
+        assert!(out.contains("FAIL  line 5: assert 2 - 2 (evaluated to 0)"));
+        assert!(out.contains("FAIL  line 6: 1 / 0 (Error[E0031]: Division by zero: 1 / 0)"));
+        assert!(out.contains("ok    line 8"));
+        assert!(out.ends_with("3 passed, 3 failed\n"));
+    }
+
+    #[test]
+    fn test_run_batch(){
+        let mut out = Vec::new();
+        assert_eq!(run_batch("# totals\n1 + 2  # cash\n\n(3\n", "csv", &mut out, None).unwrap(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "input,result,error\n1 + 2  # cash,3,\n(3,,Error[E0008]: Open parenthesis.\n");
+        let mut out = Vec::new();
+        assert_eq!(run_batch("6 * 7\n\n1 +\n2\n(1 + * 2", "text", &mut out, None).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "42\nline 3: Error[E0004]: Unexpected end of input\n2\n\
+            line 5: Error[E0009]: Illegal factor: MUL\nline 5: Error[E0008]: Open parenthesis.\n");
+    }
+
+    #[test]
+    fn test_run_pipe(){
+        let mut out = Vec::new();
+        assert_eq!(run_pipe(&mut "x = 6\n# answer\n\nx * 7\n1 / 0\nx + 1\n".as_bytes(), "text", &mut out).unwrap(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "6\n42\nline 5: Error[E0031]: Division by zero: 1 / 0\n7\n");
+    }
+
+    #[test]
+    fn test_check(){
+        let mut out = Vec::new();
+        assert_eq!(check_lines("# rates\n1 / 0\n\nx +\n(1 + * 2", &mut out).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "line 4:4: Error[E0004]: Unexpected end of input\n\
+            line 5:6: Error[E0009]: Illegal factor: MUL\nline 5:9: Error[E0008]: Open parenthesis.\n");
+        assert_eq!(check_json("x / 0"), (true, r#"{"input": "x / 0", "ok": true, "errors": []}"#.to_string()));
+        assert_eq!(check_json("1 +").1,
+            r#"{"input": "1 +", "ok": false, "errors": [{"code": "E0004", "message": "Unexpected end of input", "start": 3, "end": 4}]}"#);
+    }
+
+    #[test]
+    fn test_other_bases(){
+        assert_eq!(other_bases(255), vec!["   hex: 0xff", "   oct: 0o377", "   bin: 0b11111111"]);
+        assert_eq!(other_bases(-1)[0], "   hex: 0xffffffff");
+    }
+
+    #[test]
+    fn test_paren_balance(){
+        assert_eq!(paren_balance("(1 + (2 * 3)"), Ok(1));
+        assert_eq!(paren_balance("((1)) + 2\n"), Ok(0));
+        assert_eq!(paren_balance("(1)) + (2"), Err(3));
+    }
+
+    #[test]
+    fn test_predicate(){
+        assert_eq!(predicate("3 - 2"), 0);
+        assert_eq!(predicate("2 - 2"), 1);
+        assert_eq!(predicate("(2"), 2);
+    }
+
+    #[test]
+    fn test_json_report(){
+        assert_eq!(json_report("1+2*3", Format::Repr, Notation::default()), (true, r#"{"input": "1+2*3", "ok": true, "result": 7, "repr": "<1+<2*3>>"}"#.to_string()));
+        assert_eq!(json_report("1 @ 2", Format::Infix, Notation::default()).1,
+            r#"{"input": "1 @ 2", "ok": false, "error": {"code": "E0001", "message": "Invalid token '@' at index 2", "position": 2}}"#);
+        assert!(json_report("(1 + 2", Format::Repr, Notation::default()).1.ends_with(r#""message": "Open parenthesis.", "position": 6}}"#));
+        let eng = Notation { eng: true, sigfig: Some(2), ..Notation::default() };
+        assert!(json_report("12345 * 2", Format::Repr, eng).1.contains(r#""result": 25e3,"#));
+        assert!(json_report("12345 m", Format::Repr, eng).1.contains(r#""result": "12e3 m","#));
+        assert!(json_report("12345", Format::Repr, Notation { si: true, ..Notation::default() }).1.contains(r#""result": "12.345k","#));
+        assert_eq!(json_report("z + 1", Format::Infix, Notation::default()),
+            (false, r#"{"input": "z + 1", "ok": false, "error": {"code": "E0024", "message": "Unknown variable: z", "position": null}}"#.to_string()));
+        assert!(json_report("4 / (2 - 2)", Format::Repr, Notation::default()).1.ends_with(r#""position": 2}}"#));
+    }
+
+    #[test]
+    fn test_token_lines(){
+        assert_eq!(token_lines("2 * (x+1)"), Ok(["0..1 NUM(2)", "2..3 MUL", "4..5 LPR", "5..6 IDENT(\"x\")", "6..7 ADD", "7..8 NUM(1)", "8..9 RPR"]
+            .map(String::from).to_vec()));
+        assert_eq!(token_lines("1 @"), Err((CalcError::InvalidToken('@', 2), Span { start: 2, end: 3 })));
+    }
+
+    #[test]
+    fn test_daemon(){
+        let requests = "{\"id\": 1, \"expr\": \"x = 6\"}\n\n{\"id\": \"b\", \"expr\": \"x * 7\"}\n{\"expr\": \"y\"}\n{\"id\": 4}\nnot json\n";
+        let mut out = Vec::new();
+        daemon(&mut requests.as_bytes(), &mut out, Format::Infix, Notation::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], r#"{"id": 1, "input": "x = 6", "ok": true, "result": 6, "repr": "x = 6"}"#);
+        assert_eq!(lines[1], r#"{"id": "b", "input": "x * 7", "ok": true, "result": 42, "repr": "x * 7"}"#);
+        assert!(lines[2].starts_with(r#"{"id": null, "input": "y", "ok": false, "error": {"code": "E0024""#));
+        assert!(lines[3].starts_with(r#"{"id": 4, "ok": false"#));
+        assert!(lines[4].starts_with(r#"{"id": null, "ok": false, "error": {"code": null, "message": "#));
+    }
+
+    #[test]
+    fn test_http_exchange(){
+        let exchange = |request: &str| {
+            let mut out = Vec::new();
+            http_exchange(&mut request.as_bytes(), &mut out, Format::Repr, Notation::default()).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let response = exchange("POST /eval HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\n1+2*3");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"input\": \"1+2*3\", \"ok\": true, \"result\": 7, \"repr\": \"<1+<2*3>>\"}"));
+        assert!(exchange("POST /eval HTTP/1.1\r\ncontent-length: 17\r\n\r\n{\"expr\": \"1 / 0\"}").contains("\"code\": \"E0031\""));
+        assert!(exchange("GET /eval HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405 "));
+        assert!(exchange("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 "));
+        assert!(exchange("POST /eval HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n").starts_with("HTTP/1.1 413 "));
+        assert!(exchange("garbage").starts_with("HTTP/1.1 404 "));
+    }
+
     #[test]
-    fn test_expr1(){
-        let parser = TokenParser::new(
-            "-1 * (-2 + 5)".to_string()
-        );
-        let n = evaluate(parser);
-        assert_eq!(n.eval(), -3);
+    fn test_progress_bar(){
+        let second = std::time::Duration::from_secs(1);
+        assert_eq!(progress_bar(500, 1000, 3 * second), "[##########··········]  50%  500/1000  ETA 3s");
+        assert_eq!(progress_bar(0, 10, second), "[····················]   0%  0/10  ETA 0s");
+        assert_eq!(progress_bar(10, 10, second), "[####################] 100%  10/10  ETA 0s");
     }
 
     #[test]
-    fn test_expr2(){
-        let parser = TokenParser::new(
-            "12 + 34 - (56 / 7) * 8".to_string()
-        );
-        let n = evaluate(parser);
-        assert_eq!(n.eval(), -18);
+    fn test_grade(){
+        let mut out = Vec::new();
+        let correct = grade("1 + 2\n6 * 7\n\n10 / 4\n8 - 9\n", "3\n41\n\n2.5\n", 0.5, &mut out).unwrap();
+        assert_eq!(correct, 2);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  1. 1 + 2 = 3: correct"));
+        assert!(out.contains("  2. 6 * 7 = 41: wrong, expected 42, got 41"));
+        assert!(out.contains("  4. 10 / 4 = 2.5: correct"));
+        assert!(out.contains("  5. 8 - 9 = : no answer"));
+        assert!(out.ends_with("Score: 2/4 (50%)\n"));
     }
 
     #[test]
-    fn test_expr3(){
-        let parser = TokenParser::new(
-            "(-12 + 34) * ((56 / 7) + 8)".to_string()
-        );
-        let n = evaluate(parser);
-        assert_eq!(n.eval(), 352);
+    fn test_quiz(){
+        let mut rng = Rng(3);
+        let mut replay = rng.clone();
+        let answers = (0..3).map(|_| quiz_problem(&mut replay, 1).1.to_string() + "\n").collect::<String>();
+        let mut out = Vec::new();
+        let correct = quiz(&mut rng, 1, &mut (answers + "x\nx\nq\n").as_bytes(), &mut out).unwrap();
+        assert_eq!(correct, 3);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("correct (0.0s, streak 3)"));
+        assert!(out.contains("Level up: 2"));
+        assert!(out.contains("Level down: 1"));
+        assert!(out.contains("Score: 3/5, best streak 3"));
     }
 }
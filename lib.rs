@@ -0,0 +1,6171 @@
+#![allow(clippy::needless_return)]
+// parsing and evaluation, `calc.rs` is the command line front end
+use std::collections::HashMap;
+
+// char range [start, end) of the source a node was parsed from
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span { Span { start: self.start, end: other.end } }
+}
+
+// integers stay exact, anything touching a float becomes a float
+#[derive(Clone)]
+pub enum Value {
+    Int(i32),
+    Big(BigInt),        // only with --bignum, and only for values that do not fit an i32
+    Float(f64),
+    Qty(Quantity),      // a number with a unit, 3 km
+    List(Vec<Value>),   // a vector, or a matrix as a list of rows
+    Date(i64),          // seconds since 1970-01-01T00:00:00 UTC
+    Dec(Decimal),       // only with --decimal, a number with a fraction
+    Poly(Vec<Value>),   // a polynomial in x of degree 1 or more, its coefficients from the constant up
+}
+
+// `value` times `unit`, where one `unit` is `factor` in SI base units, e.g. 3 km is (3, "km", 1000.0)
+#[derive(Clone, PartialEq, Debug)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: String,
+    pub factor: f64,
+    pub dim: [i8; 3],       // powers of m, kg and s
+}
+
+impl Quantity {
+    pub fn si(&self) -> f64 {
+        return self.value * self.factor;
+    }
+
+    // a plain number has no unit and no dimension
+    fn of(v: &Value) -> Result<Quantity, CalcError> {
+        match v {
+            Value::Qty(q) => Ok(q.clone()),
+            v => Ok(Quantity { value: v.float()?, unit: String::new(), factor: 1.0, dim: [0; 3] }),
+        }
+    }
+
+    // 3 km + 200 m keeps the left unit, products and quotients combine them
+    fn arith(a: &Value, b: &Value, op: char) -> Result<Value, CalcError> {
+        let (a, b) = (Quantity::of(a)?, Quantity::of(b)?);
+        let text = |u: &str| if u.contains(['*', '/']) { format!("({})", u) } else { u.to_string() };
+        let q = match op {
+            '+' | '-' if a.dim != b.dim => return Err(runtime("E0032", &[&format!("{} {} {}", Value::Qty(a), op, Value::Qty(b))])),
+            '+' => Quantity { value: a.value + b.si() / a.factor, ..a },
+            '-' => Quantity { value: a.value - b.si() / a.factor, ..a },
+            '*' => Quantity {
+                value: a.value * b.value,
+                unit: [a.unit.as_str(), b.unit.as_str()].iter().filter(|u| !u.is_empty()).copied().collect::<Vec<&str>>().join("*"),
+                factor: a.factor * b.factor,
+                dim: [0, 1, 2].map(|i| a.dim[i] + b.dim[i]),
+            },
+            '/' | '\\' => Quantity {
+                value: if op == '/' { a.value / b.value } else { (a.value / b.value).floor() },
+                unit: match (a.unit.as_str(), b.unit.as_str()) {
+                    (u, "") => u.to_string(),
+                    ("", u) => format!("1/{}", text(u)),
+                    (u, v) => format!("{}/{}", u, text(v)),
+                },
+                factor: a.factor / b.factor,
+                dim: [0, 1, 2].map(|i| a.dim[i] - b.dim[i]),
+            },
+            _ if b.dim != [0; 3] || b.si().fract() != 0.0 => {
+                return Err(runtime("E0032", &[&format!("{} ^ {}", Value::Qty(a), Value::Qty(b))]));
+            },
+            _ => {
+                let n = b.si() as i32;
+                Quantity { value: a.value.powi(n), unit: format!("{}^{}", text(&a.unit), n), factor: a.factor.powi(n), dim: a.dim.map(|d| d * n as i8) }
+            },
+        };
+        // km / m is a plain ratio
+        if q.dim == [0; 3] {
+            return Ok(Value::Float(q.si()));
+        }
+        return Ok(Value::Qty(q));
+    }
+
+    // `a -> target`, a in the unit of target
+    fn convert(a: &Value, target: &Value) -> Result<Value, CalcError> {
+        let (a, t) = (Quantity::of(a)?, Quantity::of(target)?);
+        if a.dim != t.dim {
+            return Err(runtime("E0032", &[&format!("{} -> {}", Value::Qty(a), t.unit)]));
+        }
+        if t.dim == [0; 3] {
+            return Ok(Value::Float(a.si()));
+        }
+        return Ok(Value::Qty(Quantity { value: a.si() / t.factor, ..t }));
+    }
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar, and back
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    return era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    return (yoe + era * 400 + (m <= 2) as i64, m, doy - (153 * mp + 2) / 5 + 1);
+}
+
+// "2024-03-01", "2024-03-01T12:30" or "2024-03-01T12:30:15" in UTC, None for anything else or a day that does not exist
+pub fn parse_date(s: &str) -> Option<i64> {
+    let (date, time) = s.split_once('T').unwrap_or((s, "00:00"));
+    let numbers = |s: &str, sep: char| s.split(sep).map(|p| p.parse::<i64>().ok().filter(|_| p.len() == 2 || p.len() == 4)).collect::<Option<Vec<i64>>>();
+    let (d, t) = (numbers(date, '-')?, numbers(time, ':')?);
+    if date.len() != 10 || d.len() != 3 || !matches!(t.len(), 2 | 3) || time.len() != t.len() * 3 - 1 {
+        return None;
+    }
+    let days = days_from_civil(d[0], d[1], d[2]);
+    if civil_from_days(days) != (d[0], d[1], d[2]) || t[0] > 23 || t[1] > 59 || t.get(2).is_some_and(|s| *s > 59) {
+        return None;
+    }
+    return Some(days * 86400 + t[0] * 3600 + t[1] * 60 + t.get(2).unwrap_or(&0));
+}
+
+// the way parse_date reads it, the time only when it is not midnight
+fn date_text(t: i64) -> String {
+    let (y, m, d) = civil_from_days(t.div_euclid(86400));
+    let s = t.rem_euclid(86400);
+    if s == 0 {
+        return format!("{:04}-{:02}-{:02}", y, m, d);
+    }
+    return format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", y, m, d, s / 3600, s / 60 % 60, s % 60);
+}
+
+// a date plus or minus a duration is a date, the difference of two dates a duration in days
+fn date_arith(a: &Value, b: &Value, op: char) -> Result<Value, CalcError> {
+    let seconds = |v: &Value| match v {
+        Value::Qty(q) if q.dim == [0, 0, 1] => Some(q.si().round() as i64),
+        _ => None,
+    };
+    let (date, duration) = match (a, b) {
+        (Value::Date(t), Value::Date(u)) if op == '-' => {
+            return Ok(Value::Qty(Quantity { value: (t - u) as f64 / 86400.0, ..unit("d").unwrap() }));
+        },
+        (Value::Date(t), d) if matches!(op, '+' | '-') => (*t, seconds(d)),
+        (d, Value::Date(t)) if op == '+' => (*t, seconds(d)),
+        _ => (0, None),
+    };
+    match duration {
+        Some(s) if op == '-' => Ok(Value::Date(date - s)),
+        Some(s) => Ok(Value::Date(date + s)),
+        None => {
+            let op = if op == '\\' { "//".to_string() } else { op.to_string() };
+            Err(runtime("E0036", &[&format!("{} {} {}", a, op, b)]))
+        },
+    }
+}
+
+// `--bignum`: integers grow past 32 bits instead of overflowing
+static BIGNUM: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_bignum(big: bool) {
+    BIGNUM.store(big, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn bignum() -> bool {
+    BIGNUM.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// `--decimal`: numbers with a fraction are exact in base 10 rather than floats, 0.1 + 0.2 == 0.3
+static DECIMAL: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_decimal(decimal: bool) {
+    DECIMAL.store(decimal, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn decimal() -> bool {
+    DECIMAL.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// what an integer result too large for an i32 does, without --bignum
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Overflow {
+    Checked,        // an error
+    Wrapping,       // two's complement, i32::MAX + 1 is i32::MIN
+    Saturating,     // stops at i32::MAX or i32::MIN
+}
+
+pub const OVERFLOW_MODES: &[&str] = &["checked", "wrapping", "saturating"];
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        let all = [Overflow::Checked, Overflow::Wrapping, Overflow::Saturating];
+        return OVERFLOW_MODES.iter().position(|m| *m == name).map(|i| all[i]);
+    }
+
+    // a + b, a - b, a * b, a // b (op '\\'), a ^ b or -b (op '_')
+    fn apply(self, a: i32, b: i32, op: char) -> Result<i32, CalcError> {
+        // the quotient rounded down rather than toward zero
+        let floor = |q: i32| if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) { q - 1 } else { q };
+        let (checked, wrapping, saturating) = match op {
+            '+' => (a.checked_add(b), a.wrapping_add(b), a.saturating_add(b)),
+            '-' => (a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b)),
+            '*' => (a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b)),
+            '\\' if b == 0 => return Err(CalcError::DivisionByZero(format!("{} // {}", a, b), Span::default())),
+            '\\' => (a.checked_div(b).map(floor), floor(a.wrapping_div(b)), floor(a.saturating_div(b))),
+            '_' => (b.checked_neg(), b.wrapping_neg(), b.saturating_neg()),
+            _ => (a.checked_pow(b as u32), a.wrapping_pow(b as u32), a.saturating_pow(b as u32)),
+        };
+        match self {
+            Overflow::Checked => checked.ok_or_else(|| {
+                let expr = match op {
+                    '_' => format!("-({})", b),
+                    '\\' => format!("{} // {}", a, b),
+                    _ => format!("{} {} {}", a, op, b),
+                };
+                runtime("E0030", &[&expr])
+            }),
+            Overflow::Wrapping => Ok(wrapping),
+            Overflow::Saturating => Ok(saturating),
+        }
+    }
+
+    // an integer literal beyond the i32 range
+    fn literal(self, b: &BigInt) -> Option<i32> {
+        match self {
+            Overflow::Checked => None,
+            Overflow::Wrapping => {
+                let low = b.limbs.first().copied().unwrap_or(0) as i32;
+                Some(if b.negative { low.wrapping_neg() } else { low })
+            },
+            Overflow::Saturating => Some(if b.negative { i32::MIN } else { i32::MAX }),
+        }
+    }
+}
+
+static OVERFLOW: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+pub fn set_overflow(mode: Overflow) {
+    OVERFLOW.store(mode as u8, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn overflow() -> Overflow {
+    match OVERFLOW.load(std::sync::atomic::Ordering::Relaxed) {
+        1 => Overflow::Wrapping,
+        2 => Overflow::Saturating,
+        _ => Overflow::Checked,
+    }
+}
+
+// what a NaN or an infinity does where the operands had none, 0.0 / 0 or 10.0 ^ 400
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NanPolicy {
+    Propagate,      // it is the result, printed as NaN or inf
+    Error,          // an error naming the sub-expression
+}
+
+pub const NAN_POLICIES: &[&str] = &["propagate", "error"];
+
+impl NanPolicy {
+    pub fn from_name(name: &str) -> Option<NanPolicy> {
+        let all = [NanPolicy::Propagate, NanPolicy::Error];
+        return NAN_POLICIES.iter().position(|p| *p == name).map(|i| all[i]);
+    }
+
+    // `result` of node `n`; finite < infinite < NaN, and an error only for a step up from the operands
+    fn check(self, n: &Expr, operands: &[Value], result: &Value) -> Result<(), CalcError> {
+        let class = |v: &Value| match v {
+            Value::Float(x) | Value::Qty(Quantity { value: x, .. }) => if x.is_nan() { 2 } else if x.is_infinite() { 1 } else { 0 },
+            _ => 0,
+        };
+        if self == NanPolicy::Error && class(result) > operands.iter().map(class).max().unwrap_or(0) {
+            return Err(runtime("E0043", &[&infix(n), result]));
+        }
+        return Ok(());
+    }
+}
+
+static NAN_ERROR: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_nan_policy(policy: NanPolicy) {
+    NAN_ERROR.store(policy == NanPolicy::Error, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn nan_policy() -> NanPolicy {
+    if NAN_ERROR.load(std::sync::atomic::Ordering::Relaxed) { NanPolicy::Error } else { NanPolicy::Propagate }
+}
+
+impl Value {
+    // lists and dates are not numbers
+    pub fn float(&self) -> Result<f64, CalcError> {
+        self.as_f64().ok_or_else(|| runtime("E0034", &[self]))
+    }
+
+    // None for a list or a date, for where those are ruled out already
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Big(b) => Some(b.to_f64()),
+            Value::Float(f) => Some(*f),
+            Value::Qty(q) => Some(q.value),
+            Value::Dec(d) => Some(d.to_f64()),
+            Value::List(_) | Value::Date(_) | Value::Poly(_) => None,
+        }
+    }
+
+    pub fn int(&self) -> Option<i32> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    // false as a condition, an error for a list or a date
+    pub fn is_zero(&self) -> Result<bool, CalcError> {
+        return Ok(self.float()? == 0.0);
+    }
+
+    pub fn pow(&self, other: &Value) -> Result<Value, CalcError> {
+        return self.arith(other, '^');
+    }
+
+    // `//`, the quotient rounded down, an integer unless either side is a float
+    pub fn floor_div(&self, other: &Value) -> Result<Value, CalcError> {
+        return self.arith(other, '\\');
+    }
+
+    // an integer literal, as a BigInt when it is too large for an i32 and --bignum is on
+    pub fn parse_int(s: &str) -> Option<Value> {
+        return Value::parse_radix(s, 10);
+    }
+
+    // digits of a 0x, 0o or 0b literal, 0xFFFFFFFF is -1 when wrapping
+    pub fn parse_radix(s: &str, radix: u32) -> Option<Value> {
+        if let Ok(i) = i32::from_str_radix(s, radix) {
+            return Some(Value::Int(i));
+        }
+        let big = BigInt::parse_radix(s, radix)?;
+        if bignum() {
+            return Some(Value::Big(big));
+        }
+        return overflow().literal(&big).map(Value::Int);
+    }
+
+    // an integer as a 0x, 0o or 0b literal (plain digits for 10), the way parse_radix reads it back;
+    // a float stays decimal
+    pub fn to_radix(&self, radix: u32) -> String {
+        let prefix = match radix { 16 => "0x", 8 => "0o", 2 => "0b", _ => "" };
+        let big = match self {
+            Value::Float(_) | Value::Qty(_) | Value::List(_) | Value::Date(_) | Value::Dec(_) | Value::Poly(_) => return self.to_string(),
+            v => v.big(),
+        };
+        let digits = BigInt { negative: false, ..big.clone() }.to_radix(radix);
+        return format!("{}{}{}", if big.negative { "-" } else { "" }, prefix, digits);
+    }
+
+    // "42" or "2.5", e.g. the content of a cell or an environment variable
+    pub fn parse(s: &str) -> Option<Value> {
+        let s = s.trim();
+        return Value::parse_int(s).or_else(|| Value::parse_fraction(s));
+    }
+
+    // "2.5", a Decimal with --decimal unless it has more digits than one holds
+    pub fn parse_fraction(s: &str) -> Option<Value> {
+        if decimal() && let Some(d) = Decimal::parse(s) {
+            return Some(Value::Dec(d));
+        }
+        return s.parse().ok().map(Value::Float);
+    }
+
+    fn from_big(b: BigInt) -> Value {
+        match b.to_i32() {
+            Some(i) => Value::Int(i),
+            None => Value::Big(b),
+        }
+    }
+
+    fn big(&self) -> BigInt {
+        match self {
+            Value::Int(i) => BigInt::from(*i as i64),
+            Value::Big(b) => b.clone(),
+            Value::Float(_) | Value::Qty(_) | Value::List(_) | Value::Date(_) | Value::Dec(_) | Value::Poly(_) => unreachable!(),
+        }
+    }
+
+    // `/` is true division and gives a float, `//` (op '\\') rounds down, 
+    // a negative integer power is a float too
+    fn arith(&self, other: &Value, op: char) -> Result<Value, CalcError> {
+        let float = |a: f64, b: f64| match op {
+            '+' => a + b, '-' => a - b, '*' => a * b, '/' => a / b, '\\' => (a / b).floor(), _ => a.powf(b),
+        };
+        // the arms before the first use of these rule out lists and dates
+        let (x, y) = (self.as_f64().unwrap_or(f64::NAN), other.as_f64().unwrap_or(f64::NAN));
+        let v = match (self, other) {
+            (Value::List(_), _) | (_, Value::List(_)) => return list_arith(self, other, op),
+            (Value::Date(_), _) | (_, Value::Date(_)) => return date_arith(self, other, op),
+            (Value::Poly(_), _) | (_, Value::Poly(_)) => return poly_arith(self, other, op),
+            (Value::Qty(_), _) | (_, Value::Qty(_)) => return Quantity::arith(self, other, op),
+            (Value::Float(_), _) | (_, Value::Float(_)) => Value::Float(float(x, y)),
+            (a, _) if op == '/' && y == 0.0 => return Err(CalcError::DivisionByZero(format!("{} / 0", a), Span::default())),
+            (Value::Dec(_), _) | (_, Value::Dec(_)) => return decimal_arith(self, other, op),
+            (_, Value::Int(b)) if decimal() && (op == '/' || (op == '^' && *b < 0)) => return decimal_arith(self, other, op),
+            _ if op == '/' => Value::Float(float(x, y)),
+            (_, &Value::Int(b)) if op == '^' && b < 0 => Value::Float(float(x, b as f64)),
+            (&Value::Int(a), &Value::Int(b)) if !bignum() => Value::Int(overflow().apply(a, b, op)?),
+            // an exponent beyond an i32 would not fit in memory anyway
+            (_, Value::Big(b)) if op == '^' => Value::Float(float(x, b.to_f64())),
+            (a, b) => {
+                let (a, b) = (a.big(), b.big());
+                if op == '\\' && b.limbs.is_empty() {
+                    return Err(CalcError::DivisionByZero(format!("{} // 0", a), Span::default()));
+                }
+                Value::from_big(match op {
+                    '+' => a + b, '-' => a - b, '*' => a * b,
+                    '\\' => {
+                        let q = a.clone() / b.clone();
+                        if q.clone() * b.clone() != a && a.negative != b.negative { q - BigInt::from(1) } else { q }
+                    },
+                    _ => a.pow(other.int().unwrap() as u32),
+                })
+            },
+        };
+        return Ok(v);
+    }
+}
+
+// +, -, *, / and // of decimals and integers stay exact, up to Decimal::MAX_SCALE digits after the point; 
+// a power needs an integer exponent, and an integer too large for a Decimal makes it a float
+fn decimal_arith(a: &Value, b: &Value, op: char) -> Result<Value, CalcError> {
+    let (Some(x), Some(y)) = (Decimal::of(a), Decimal::of(b)) else {
+        return Value::Float(a.float()?).arith(b, op);
+    };
+    let text = || format!("{} {} {}", a, if op == '\\' { "//".to_string() } else { op.to_string() }, b);
+    if y.mantissa == 0 && (op == '\\' || (op == '^' && b.float()? < 0.0)) {
+        return Err(CalcError::DivisionByZero(text(), Span::default()));
+    }
+    let result = match (op, b) {
+        ('+', _) => x.add(y),
+        ('-', _) => x.sub(y),
+        ('*', _) => x.mul(y),
+        ('/', _) => x.div(y),
+        ('\\', _) => x.floor_div(y),
+        (_, Value::Int(n)) if n.unsigned_abs() > Decimal::MAX_EXPONENT => return Err(runtime("E0045", &[&text(), &Decimal::MAX_EXPONENT])),
+        (_, Value::Int(n)) => x.pow(*n),
+        _ => return Ok(Value::Float(a.float()?.powf(b.float()?))),
+    };
+    return result.map(Value::Dec).ok_or_else(|| runtime("E0040", &[&text()]));
+}
+
+// a matrix is a list of rows of the same length, the number of rows and columns
+fn matrix_shape(v: &Value) -> Option<(usize, usize)> {
+    let Value::List(rows) = v else { return None; };
+    let Some(Value::List(first)) = rows.first() else { return None; };
+    if !rows.iter().all(|r| matches!(r, Value::List(cells) if cells.len() == first.len() && cells.iter().all(|c| !matches!(c, Value::List(_))))) {
+        return None;
+    }
+    return Some((rows.len(), first.len()));
+}
+
+// a number is applied to every element; two lists of the same length go element by element for + and -, 
+// `*` is the dot product of two vectors and the matrix product of two matrices (a vector is a column there)
+fn list_arith(a: &Value, b: &Value, op: char) -> Result<Value, CalcError> {
+    let mismatch = || {
+        let op = if op == '\\' { "//".to_string() } else { op.to_string() };
+        Err(runtime("E0033", &[&format!("{} {} {}", a, op, b)]))
+    };
+    let (Value::List(x), Value::List(y)) = (a, b) else {
+        return match (a, b) {
+            (Value::List(x), b) => Ok(Value::List(x.iter().map(|u| u.arith(b, op)).collect::<Result<Vec<Value>, CalcError>>()?)),
+            (a, Value::List(y)) if op != '^' => Ok(Value::List(y.iter().map(|v| a.arith(v, op)).collect::<Result<Vec<Value>, CalcError>>()?)),
+            _ => mismatch(),
+        };
+    };
+    // element j of a row, a number is a row of one
+    let cell = |row: &Value, j: usize| match row { Value::List(cells) => cells[j].clone(), v => v.clone() };
+    let product = |n: usize, p: usize| x.iter().map(|r| {
+        let row = (0..p).map(|j| (0..n).try_fold(Value::Int(0), |acc, k| &acc + &(&cell(r, k) * &cell(&y[k], j))?));
+        Ok(Value::List(row.collect::<Result<Vec<Value>, CalcError>>()?))
+    }).collect::<Result<Vec<Value>, CalcError>>();
+    match op {
+        '+' | '-' if x.len() == y.len() => Ok(Value::List(x.iter().zip(y).map(|(u, v)| u.arith(v, op)).collect::<Result<Vec<Value>, CalcError>>()?)),
+        '*' => match (matrix_shape(a), matrix_shape(b)) {
+            (Some((_, n)), Some((m, p))) if n == m => Ok(Value::List(product(n, p)?)),
+            (Some((_, n)), None) if y.len() == n => Ok(Value::List(product(n, 1)?.iter().map(|r| cell(r, 0)).collect())),
+            (None, None) if x.len() == y.len() => x.iter().zip(y).try_fold(Value::Int(0), |acc, (u, v)| &acc + &(u * v)?),
+            _ => mismatch(),
+        },
+        _ => mismatch(),
+    }
+}
+
+// a number is a polynomial of degree 0; + and - go term by term and * multiplies out, 
+// a polynomial is only divided by a number and only raised to a whole power
+fn poly_arith(a: &Value, b: &Value, op: char) -> Result<Value, CalcError> {
+    let terms = |v: &Value| match v { Value::Poly(c) => c.clone(), v => vec![v.clone()] };
+    let (x, y) = (terms(a), terms(b));
+    let zero = Value::Int(0);
+    match (op, b) {
+        ('+' | '-', _) => {
+            let sum = (0..x.len().max(y.len())).map(|i| x.get(i).unwrap_or(&zero).arith(y.get(i).unwrap_or(&zero), op));
+            Ok(polynomial(sum.collect::<Result<Vec<Value>, CalcError>>()?))
+        },
+        ('*', _) => {
+            let mut product = vec![zero; x.len() + y.len() - 1];
+            for (i, u) in x.iter().enumerate() {
+                for (j, v) in y.iter().enumerate() {
+                    product[i + j] = (&product[i + j] + &(u * v)?)?;
+                }
+            }
+            Ok(polynomial(product))
+        },
+        ('/', b) if !matches!(b, Value::Poly(_)) => Ok(polynomial(x.iter().map(|u| u / b).collect::<Result<Vec<Value>, CalcError>>()?)),
+        ('^', Value::Int(n)) if *n >= 0 && matches!(a, Value::Poly(_)) => (0..*n).try_fold(Value::Int(1), |p, _| &p * a),
+        _ => {
+            let op = if op == '\\' { "//".to_string() } else { op.to_string() };
+            let show = |v: &Value| if matches!(v, Value::Poly(_)) { format!("({})", v) } else { v.to_string() };
+            Err(runtime("E0042", &[&format!("{} {} {}", show(a), op, show(b))]))
+        },
+    }
+}
+
+// without the zero coefficients of the highest powers, a polynomial of degree 0 is just its number
+fn polynomial(mut coefficients: Vec<Value>) -> Value {
+    while coefficients.len() > 1 && coefficients.last() == Some(&Value::Int(0)) {
+        coefficients.pop();
+    }
+    if coefficients.len() <= 1 {
+        return coefficients.pop().unwrap_or(Value::Int(0));
+    }
+    return Value::Poly(coefficients);
+}
+
+// Horner form, x^2 - 3x + 2 is (x - 3) * x + 2, which also reads back as an expression in x
+fn horner(coefficients: &[Value]) -> String {
+    let mut terms = coefficients.iter().rev();
+    let mut text = terms.next().map_or("0".to_string(), |c| c.to_string());
+    let mut sum = false;
+    for c in terms {
+        text = match text.as_str() {
+            "1" => "x".to_string(),
+            "-1" => "-x".to_string(),
+            _ if sum => format!("({}) * x", text),
+            _ => format!("{} * x", text),
+        };
+        sum = *c != Value::Int(0);
+        if c.as_f64().is_some_and(|f| f < 0.0) {
+            text += &format!(" - {}", (-c).map_or_else(|_| c.to_string(), |m| m.to_string()));
+        } else if sum {
+            text += &format!(" + {}", c);
+        }
+    }
+    return text;
+}
+
+// p(x) by Horner's rule, `x` can be a polynomial too
+fn polyval(p: &Value, x: &Value) -> Result<Value, CalcError> {
+    let Value::Poly(coefficients) = p else { return Ok(p.clone()); };
+    return coefficients.iter().rev().try_fold(Value::Int(0), |acc, c| &(&acc * x)? + c);
+}
+
+// the real roots of a polynomial of degree 1 or 2, in increasing order
+fn poly_roots(p: &Value) -> Result<Value, CalcError> {
+    let coefficients = match p {
+        Value::Poly(c) if c.len() <= 3 => c.iter().map(Value::float).collect::<Result<Vec<f64>, CalcError>>()?,
+        _ => return Err(runtime("E0038", &[&"x"])),
+    };
+    let (c, b, a) = (coefficients[0], coefficients[1], coefficients.get(2).copied().unwrap_or(0.0));
+    return Ok(Value::List(real_roots(a, b, c)));
+}
+
+// of a * x^2 + b * x + c, not all of them 0; whole roots print as integers
+fn real_roots(a: f64, b: f64, c: f64) -> Vec<Value> {
+    let root = |x: f64| if x.fract() == 0.0 && x.abs() <= i32::MAX as f64 { Value::Int(x as i32) } else { Value::Float(x) };
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![root(-c / b + 0.0)] };
+    }
+    let d = b * b - 4.0 * a * c;
+    if d < 0.0 {
+        return vec![];
+    }
+    let (x1, x2) = ((-b - d.sqrt()) / (2.0 * a), (-b + d.sqrt()) / (2.0 * a));
+    if d == 0.0 {
+        return vec![root(x1 + 0.0)];
+    }
+    return vec![root(x1.min(x2)), root(x1.max(x2))];
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::List(_), _) | (_, Value::List(_)) => false,
+            (Value::Poly(a), Value::Poly(b)) => a == b,
+            (Value::Poly(_), _) | (_, Value::Poly(_)) => false,
+            (Value::Date(a), Value::Date(b)) => a == b,
+            (Value::Date(_), _) | (_, Value::Date(_)) => false,
+            (Value::Qty(_), _) | (_, Value::Qty(_)) => match (Quantity::of(self), Quantity::of(other)) {
+                (Ok(a), Ok(b)) => a.dim == b.dim && a.si() == b.si(),
+                _ => false,
+            },
+            (Value::Float(_), _) | (_, Value::Float(_)) => self.as_f64() == other.as_f64(),
+            (Value::Dec(_), _) | (_, Value::Dec(_)) => match (Decimal::of(self), Decimal::of(other)) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.as_f64() == other.as_f64(),
+            },
+            (a, b) => a.big() == b.big(),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::List(_), _) | (_, Value::List(_)) | (Value::Poly(_), _) | (_, Value::Poly(_)) => None,
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (Value::Date(_), _) | (_, Value::Date(_)) => None,
+            // only quantities of the same dimension compare
+            (Value::Qty(_), _) | (_, Value::Qty(_)) => {
+                let (a, b) = (Quantity::of(self).ok()?, Quantity::of(other).ok()?);
+                if a.dim == b.dim { a.si().partial_cmp(&b.si()) } else { None }
+            },
+            (Value::Float(_), _) | (_, Value::Float(_)) => self.as_f64()?.partial_cmp(&other.as_f64()?),
+            (Value::Dec(_), _) | (_, Value::Dec(_)) => match (Decimal::of(self), Decimal::of(other)) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+            },
+            // a Big is beyond the i32 range, so its sign decides
+            (Value::Big(a), Value::Int(_)) => Some(if a.negative { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater }),
+            (Value::Int(_), Value::Big(b)) => Some(if b.negative { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Less }),
+            (a, b) => {
+                let d = a.big() - b.big();
+                Some(if d.limbs.is_empty() { std::cmp::Ordering::Equal } else if d.negative { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater })
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Big(b) => write!(f, "{}", b),
+            Value::Float(x) => write!(f, "{:?}", x),     // keeps the ".0" of whole numbers
+            // 13 significant digits, so 100 mph -> km/h reads 160.9344
+            Value::Qty(q) => write!(f, "{} {}", format!("{:.12e}", q.value).parse::<f64>().unwrap_or(q.value), q.unit),
+            Value::List(items) => write!(f, "[{}]", items.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", ")),
+            Value::Date(t) => write!(f, "{}", date_text(*t)),
+            Value::Dec(d) => write!(f, "{}", d),
+            Value::Poly(c) => write!(f, "{}", horner(c)),
+        }
+    }
+}
+
+impl Value {
+    // input that reads back as this value, Display but for NaN and polynomials, which print as no literal
+    pub fn literal(&self) -> String {
+        match self {
+            Value::Float(x) if x.is_nan() => "nan".to_string(),
+            Value::List(items) => format!("[{}]", items.iter().map(Value::literal).collect::<Vec<String>>().join(", ")),
+            Value::Poly(c) => format!("poly([{}])", c.iter().rev().map(Value::literal).collect::<Vec<String>>().join(", ")),
+            v => v.to_string(),
+        }
+    }
+}
+
+// same as Display, so tokens read NUM(2.5) rather than NUM(Float(2.5))
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+// `--locale NAME`: the decimal separator and the one grouping thousands, 1.234,5 in German
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Locale {
+    pub decimal: char,
+    pub group: Option<char>,
+}
+
+pub const LOCALES: &[&str] = &["c", "en", "de", "fr"];
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::C
+    }
+}
+
+impl Locale {
+    const C: Locale = Locale { decimal: '.', group: None };
+
+    pub fn from_name(name: &str) -> Option<Locale> {
+        match name {
+            "c" => Some(Locale::C),
+            "en" => Some(Locale { decimal: '.', group: Some(',') }),
+            "de" => Some(Locale { decimal: ',', group: Some('.') }),
+            // a narrow no-break space, 1 234,5
+            "fr" => Some(Locale { decimal: ',', group: Some('\u{202F}') }),
+            _ => None,
+        }
+    }
+
+    // the leading number of `text`, as Display writes it, in this locale: "-1234.5 m" -> "-1.234,5 m"
+    pub fn apply(&self, text: &str) -> String {
+        let (sign, rest) = text.split_at(if text.starts_with('-') { 1 } else { 0 });
+        let len = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let (whole, rest) = rest.split_at(len);
+        let mut out = sign.to_string();
+        for (i, c) in whole.chars().enumerate() {
+            if let Some(group) = self.group.filter(|_| i > 0 && (len - i) % 3 == 0) {
+                out.push(group);
+            }
+            out.push(c);
+        }
+        return match rest.strip_prefix('.') {
+            Some(fraction) => format!("{}{}{}", out, self.decimal, fraction),
+            None => out + rest,
+        };
+    }
+}
+
+static LOCALE: std::sync::Mutex<Locale> = std::sync::Mutex::new(Locale::C);
+
+// how numbers are read; printing takes the locale from a Notation
+pub fn set_locale(locale: Locale) {
+    *LOCALE.lock().unwrap() = locale;
+}
+
+fn locale() -> Locale {
+    *LOCALE.lock().unwrap()
+}
+
+// how a result is written out, `--eng`, `--si`, `--sigfig N` and `--locale`; all off is the same as Display
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Notation {
+    pub eng: bool,                  // the exponent a multiple of 3, 12.3e3
+    pub si: bool,                   // that exponent as an SI prefix, 12.3k
+    pub sigfig: Option<usize>,      // rounded to this many significant digits
+    pub locale: Locale,             // separators of the digits
+}
+
+const SI_PREFIXES: [&str; 17] = ["y", "z", "a", "f", "p", "n", "µ", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y"];
+
+impl Notation {
+    pub fn show(&self, v: &Value) -> String {
+        match v {
+            _ if *self == Notation::default() => v.to_string(),
+            Value::List(items) => format!("[{}]", items.iter().map(|v| self.show(v)).collect::<Vec<String>>().join(", ")),
+            Value::Date(_) | Value::Poly(_) => v.to_string(),
+            _ if !self.eng && !self.si && self.sigfig.is_none() => self.locale.apply(&v.to_string()),
+            Value::Qty(q) => format!("{} {}", self.locale.apply(&self.number(q.value)), q.unit),
+            v => self.locale.apply(&self.number(v.as_f64().unwrap_or(f64::NAN))),
+        }
+    }
+
+    fn number(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return Value::Float(x).to_string();
+        }
+        // "-1.2345e4", the significant digits and the power of ten of the first one
+        let text = match self.sigfig {
+            Some(n) => format!("{:.*e}", n.max(1) - 1, x),
+            None => format!("{:e}", x),
+        };
+        let (mantissa, exp) = text.split_once('e').unwrap();
+        let exp = exp.parse::<i32>().unwrap();
+        let sign = if x < 0.0 { "-" } else { "" };
+        let digits = mantissa.trim_start_matches('-').replace('.', "");
+        if !self.eng && !self.si {
+            return sign.to_string() + &place_point(&digits, exp + 1);
+        }
+        let e3 = exp.div_euclid(3) * 3;
+        let m = sign.to_string() + &place_point(&digits, exp - e3 + 1);
+        // beyond yotta and yocto there are no prefixes
+        match usize::try_from(e3 / 3 + 8).ok().and_then(|i| SI_PREFIXES.get(i)) {
+            Some(prefix) if self.si => m + prefix,
+            _ if e3 == 0 => m,
+            _ => format!("{}e{}", m, e3),
+        }
+    }
+}
+
+// `digits` with the point after the first `point` of them, padded with zeros on either side as needed
+fn place_point(digits: &str, point: i32) -> String {
+    let len = digits.len() as i32;
+    if point <= 0 {
+        return format!("0.{}{}", "0".repeat(-point as usize), digits);
+    }
+    if point >= len {
+        return format!("{}{}", digits, "0".repeat((point - len) as usize));
+    }
+    let (whole, fraction) = digits.split_at(point as usize);
+    return format!("{}.{}", whole, fraction);
+}
+
+// the arithmetic can fail, 1 // 0 or an overflow, so these give a Result: `(&a + &b)?`
+impl std::ops::Add for &Value {
+    type Output = Result<Value, CalcError>;
+    fn add(self, other: &Value) -> Self::Output { self.arith(other, '+') }
+}
+impl std::ops::Sub for &Value {
+    type Output = Result<Value, CalcError>;
+    fn sub(self, other: &Value) -> Self::Output { self.arith(other, '-') }
+}
+impl std::ops::Mul for &Value {
+    type Output = Result<Value, CalcError>;
+    fn mul(self, other: &Value) -> Self::Output { self.arith(other, '*') }
+}
+impl std::ops::Div for &Value {
+    type Output = Result<Value, CalcError>;
+    fn div(self, other: &Value) -> Self::Output { self.arith(other, '/') }
+}
+
+impl std::ops::Neg for &Value {
+    type Output = Result<Value, CalcError>;
+    fn neg(self) -> Self::Output {
+        let v = match self {
+            Value::Int(i) if !bignum() => Value::Int(overflow().apply(0, *i, '_')?),
+            Value::Float(f) => Value::Float(-f),
+            Value::Dec(d) => Value::Dec(Decimal::new(-d.mantissa, d.scale)),
+            Value::Qty(q) => Value::Qty(Quantity { value: -q.value, ..q.clone() }),
+            Value::List(items) => Value::List(items.iter().map(|v| -v).collect::<Result<Vec<Value>, CalcError>>()?),
+            Value::Poly(c) => Value::Poly(c.iter().map(|v| -v).collect::<Result<Vec<Value>, CalcError>>()?),
+            Value::Date(_) => return Err(runtime("E0036", &[&format!("-{}", self)])),
+            v => Value::from_big(-v.big()),
+        };
+        return Ok(v);
+    }
+}
+
+// sign and magnitude, the magnitude in base 2^32 with the least significant limb first
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,    // no leading zero limbs, empty for 0
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let mag = n.unsigned_abs();
+        return BigInt { negative: n < 0, limbs: vec![mag as u32, (mag >> 32) as u32] }.trim();
+    }
+}
+
+impl BigInt {
+    fn trim(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        self.negative &= !self.limbs.is_empty();
+        return self;
+    }
+
+    // optional '-', then decimal digits
+    pub fn parse(s: &str) -> Option<BigInt> {
+        return BigInt::parse_radix(s, 10);
+    }
+
+    pub fn parse_radix(s: &str, radix: u32) -> Option<BigInt> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+        let mut limbs: Vec<u32> = Vec::new();
+        for d in digits.chars() {
+            let mut carry = d.to_digit(radix).unwrap() as u64;
+            for limb in limbs.iter_mut() {
+                let v = *limb as u64 * radix as u64 + carry;
+                *limb = v as u32;
+                carry = v >> 32;
+            }
+            if carry > 0 {
+                limbs.push(carry as u32);
+            }
+        }
+        return Some(BigInt { negative, limbs }.trim());
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let mag = self.limbs.iter().rev().fold(0i128, |acc, &l| acc << 32 | l as i128);
+        return i64::try_from(if self.negative { -mag } else { mag }).ok();
+    }
+
+    pub fn to_i32(&self) -> Option<i32> {
+        if self.limbs.len() > 1 {
+            return None;
+        }
+        let mag = *self.limbs.first().unwrap_or(&0) as i64;
+        return i32::try_from(if self.negative { -mag } else { mag }).ok();
+    }
+
+    // square and multiply
+    pub fn pow(&self, mut exp: u32) -> BigInt {
+        let (mut base, mut result) = (self.clone(), BigInt::from(1));
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result * base.clone();
+            }
+            base = base.clone() * base;
+            exp >>= 1;
+        }
+        return result;
+    }
+
+    // digits in base 2 to 36, lowercase, with a leading '-' when negative
+    pub fn to_radix(&self, radix: u32) -> String {
+        let mut digits = Vec::new();
+        let mut mag = self.limbs.clone();
+        while !mag.is_empty() {
+            let (q, r) = BigInt::divrem_mag(&mag, &[radix]);
+            digits.push(std::char::from_digit(*r.first().unwrap_or(&0), radix).unwrap());
+            mag = BigInt { negative: false, limbs: q }.trim().limbs;
+        }
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        let sign = if self.negative { "-" } else { "" };
+        return sign.to_string() + &digits.iter().rev().collect::<String>();
+    }
+
+    // the remainder in [0, m) for m > 0
+    pub fn rem_euclid(&self, m: &BigInt) -> BigInt {
+        let r = self.clone() - self.clone() / m.clone() * m.clone();
+        return if r.negative { r + m.clone() } else { r };
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mag = self.limbs.iter().rev().fold(0.0, |acc, l| acc * 4294967296.0 + *l as f64);
+        return if self.negative { -mag } else { mag };
+    }
+
+    fn cmp_mag(a: &[u32], b: &[u32]) -> std::cmp::Ordering {
+        a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+    }
+
+    fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = Vec::new();
+        let mut carry = 0u64;
+        for i in 0..a.len().max(b.len()) {
+            let v = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+            out.push(v as u32);
+            carry = v >> 32;
+        }
+        out.push(carry as u32);
+        return out;
+    }
+
+    // a - b for a >= b
+    fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = Vec::new();
+        let mut borrow = 0i64;
+        for (i, x) in a.iter().enumerate() {
+            let mut v = *x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = (v < 0) as i64;
+            v += borrow << 32;
+            out.push(v as u32);
+        }
+        return out;
+    }
+
+    fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = vec![0u32; a.len() + b.len()];
+        for (i, x) in a.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, y) in b.iter().enumerate() {
+                let v = out[i + j] as u64 + *x as u64 * *y as u64 + carry;
+                out[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            out[i + b.len()] = carry as u32;
+        }
+        return out;
+    }
+
+    // quotient and remainder by shift and subtract, one bit at a time
+    fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+        if b.is_empty() {
+            panic!("attempt to divide by zero");
+        }
+        let mut q = vec![0u32; a.len()];
+        let mut r: Vec<u32> = Vec::new();
+        for i in (0..a.len() * 32).rev() {
+            // r = r * 2 + bit i of a
+            let mut carry = (a[i / 32] >> (i % 32)) & 1;
+            for limb in r.iter_mut() {
+                let top = *limb >> 31;
+                *limb = (*limb << 1) | carry;
+                carry = top;
+            }
+            if carry > 0 {
+                r.push(carry);
+            }
+            if BigInt::cmp_mag(&r, b) != std::cmp::Ordering::Less {
+                r = BigInt { negative: false, limbs: BigInt::sub_mag(&r, b) }.trim().limbs;
+                q[i / 32] |= 1 << (i % 32);
+            }
+        }
+        return (q, r);
+    }
+}
+
+impl std::ops::Add for BigInt {
+    type Output = BigInt;
+    fn add(self, other: BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt { negative: self.negative, limbs: BigInt::add_mag(&self.limbs, &other.limbs) }.trim();
+        }
+        // opposite signs, the larger magnitude wins
+        let (big, small) = if BigInt::cmp_mag(&self.limbs, &other.limbs) == std::cmp::Ordering::Less { (other, self) } else { (self, other) };
+        return BigInt { negative: big.negative, limbs: BigInt::sub_mag(&big.limbs, &small.limbs) }.trim();
+    }
+}
+impl std::ops::Sub for BigInt {
+    type Output = BigInt;
+    fn sub(self, other: BigInt) -> BigInt { self + -other }
+}
+impl std::ops::Mul for BigInt {
+    type Output = BigInt;
+    fn mul(self, other: BigInt) -> BigInt {
+        BigInt { negative: self.negative != other.negative, limbs: BigInt::mul_mag(&self.limbs, &other.limbs) }.trim()
+    }
+}
+// truncates toward zero
+impl std::ops::Div for BigInt {
+    type Output = BigInt;
+    fn div(self, other: BigInt) -> BigInt {
+        let (q, _) = BigInt::divrem_mag(&self.limbs, &other.limbs);
+        BigInt { negative: self.negative != other.negative, limbs: q }.trim()
+    }
+}
+// by sign, then by magnitude
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<std::cmp::Ordering> {
+        let mag = BigInt::cmp_mag(&self.limbs, &other.limbs);
+        return Some(match (self.negative, other.negative) {
+            (false, false) => mag,
+            (true, true) => mag.reverse(),
+            (false, true) => std::cmp::Ordering::Greater,
+            (true, false) => std::cmp::Ordering::Less,
+        });
+    }
+}
+
+impl std::ops::Neg for BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt { negative: !self.negative, ..self }.trim()
+    }
+}
+
+impl std::fmt::Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // peel off 9 decimal digits at a time
+        let mut chunks = Vec::new();
+        let mut mag = self.limbs.clone();
+        while !mag.is_empty() {
+            let (q, r) = BigInt::divrem_mag(&mag, &[1_000_000_000]);
+            chunks.push(*r.first().unwrap_or(&0));
+            mag = BigInt { negative: false, limbs: q }.trim().limbs;
+        }
+        let mut text = format!("{}{}", if self.negative { "-" } else { "" }, chunks.pop().unwrap_or(0));
+        for c in chunks.iter().rev() {
+            text += &format!("{:09}", c);
+        }
+        write!(f, "{}", text)
+    }
+}
+
+// `--decimal`: `mantissa` / 10^`scale`, 0.1 is (1, 1); 
+// no trailing zeros in the mantissa while there is a scale, so equal values are equal structs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub const MAX_SCALE: u32 = 28;
+    pub const MAX_EXPONENT: u32 = 1024;    // a larger power leaves an i128 or MAX_SCALE anyway, unless the base is 0 or ±1
+
+    fn new(mut mantissa: i128, mut scale: u32) -> Decimal {
+        while scale > 0 && mantissa % 10 == 0 {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        return Decimal { mantissa, scale };
+    }
+
+    // "12.50", "-3" or ".5", None for anything else or beyond MAX_SCALE or an i128
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (sign, digits) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all = whole.to_string() + fraction;
+        if all.is_empty() || !all.chars().all(|c| c.is_ascii_digit()) || fraction.len() > Decimal::MAX_SCALE as usize {
+            return None;
+        }
+        return Some(Decimal::new(sign * all.parse::<i128>().ok()?, fraction.len() as u32));
+    }
+
+    // an integer or a decimal, a Big only if it fits
+    fn of(v: &Value) -> Option<Decimal> {
+        match v {
+            Value::Int(i) => Some(Decimal::new(*i as i128, 0)),
+            Value::Big(b) => b.to_string().parse().ok().map(|m| Decimal::new(m, 0)),
+            Value::Dec(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        return self.to_string().parse().unwrap();
+    }
+
+    // both mantissas at the larger scale
+    fn aligned(self, other: Decimal) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        let a = self.mantissa.checked_mul(10i128.checked_pow(scale - self.scale)?)?;
+        let b = other.mantissa.checked_mul(10i128.checked_pow(scale - other.scale)?)?;
+        return Some((a, b, scale));
+    }
+
+    // the quotient q with remainder r of a division by d, rounded half to even
+    fn round(q: u128, r: u128, d: u128) -> u128 {
+        return if r > d - r || (r == d - r && q % 2 == 1) { q + 1 } else { q };
+    }
+
+    fn signed(negative: bool, magnitude: u128, scale: u32) -> Option<Decimal> {
+        let m = i128::try_from(magnitude).ok()?;
+        return Some(Decimal::new(if negative { -m } else { m }, scale));
+    }
+
+    fn add(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        return Some(Decimal::new(a.checked_add(b)?, scale));
+    }
+
+    fn sub(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        return Some(Decimal::new(a.checked_sub(b)?, scale));
+    }
+
+    fn mul(self, other: Decimal) -> Option<Decimal> {
+        let (m, scale) = (self.mantissa.checked_mul(other.mantissa)?, self.scale + other.scale);
+        if scale <= Decimal::MAX_SCALE {
+            return Some(Decimal::new(m, scale));
+        }
+        let d = 10u128.pow(scale - Decimal::MAX_SCALE);
+        let mag = m.unsigned_abs();
+        return Decimal::signed(m < 0, Decimal::round(mag / d, mag % d, d), Decimal::MAX_SCALE);
+    }
+
+    // long division, a digit at a time until it comes out even or MAX_SCALE digits after the point
+    fn div(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, _) = self.aligned(other)?;
+        let (x, y) = (a.unsigned_abs(), b.unsigned_abs());
+        let (mut q, mut r, mut scale) = (x / y, x % y, 0);
+        while r != 0 && scale < Decimal::MAX_SCALE {
+            let (Some(q10), Some(r10)) = (q.checked_mul(10), r.checked_mul(10)) else { break; };
+            q = q10 + r10 / y;
+            r = r10 % y;
+            scale += 1;
+        }
+        return Decimal::signed((a < 0) != (b < 0), Decimal::round(q, r, y), scale);
+    }
+
+    // rounded down to a whole number
+    fn floor_div(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, _) = self.aligned(other)?;
+        let q = a / b;
+        return Some(Decimal::new(if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }, 0));
+    }
+
+    // by squaring, the base squared only while bits of n are left
+    fn pow(self, n: i32) -> Option<Decimal> {
+        let (mut base, mut exp, mut result) = (self, n.unsigned_abs(), Decimal::new(1, 0));
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(base)?;
+            }
+        }
+        return if n < 0 { Decimal::new(1, 0).div(result) } else { Some(result) };
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> std::cmp::Ordering {
+        match self.aligned(*other) {
+            Some((a, b, _)) => a.cmp(&b),
+            None => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+}
+
+// whole ones keep a ".0" like floats do, so they don't read as integers
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let digits = format!("{:0>width$}", self.mantissa.unsigned_abs(), width = self.scale as usize + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        write!(f, "{}{}.{}", sign, whole, if fraction.is_empty() { "0" } else { fraction })
+    }
+}
+
+// variables set by `name = expr`, kept across the expressions of a session
+#[derive(Clone, Default, Debug)]
+pub struct Env {
+    vars: HashMap<String, Value>,
+    constants: HashMap<String, Value>,  // from CalculatorBuilder::constant, a variable of the same name hides one
+    journal: Vec<(String, Option<Value>)>,  // what each `name = value` replaced, for undo
+}
+
+impl Env {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    // `name = value` in an expression, which undo can take back, unlike set
+    fn assign(&mut self, name: &str, value: Value) {
+        let old = self.vars.insert(name.to_string(), value);
+        self.journal.push((name.to_string(), old));
+    }
+
+    // takes back the last assignment, giving its name and the value it had before, None if it was unset
+    pub fn undo(&mut self) -> Option<(String, Option<Value>)> {
+        let (name, old) = self.journal.pop()?;
+        match &old {
+            Some(v) => self.vars.insert(name.clone(), v.clone()),
+            None => self.vars.remove(&name),
+        };
+        return Some((name, old));
+    }
+
+    // the variables `name = value` has set, in the order of their first assignment
+    pub fn assigned(&self) -> Vec<(&str, &Value)> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _) in &self.journal {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        return names.into_iter().filter_map(|name| Some((name, self.vars.get(name)?))).collect();
+    }
+
+    // sorted by name
+    pub fn vars(&self) -> Vec<(&str, &Value)> {
+        let mut vars = self.vars.iter().map(|(k, v)| (k.as_str(), v)).collect::<Vec<_>>();
+        vars.sort_by_key(|(k, _)| *k);
+        return vars;
+    }
+
+    // a variable, or else a constant of that name, a Roman numeral included, 
+    // and an `x` that is not set is the polynomial x, so x^2 - 3x + 2 builds one
+    fn lookup(&self, name: &str) -> Result<Value, CalcError> {
+        if name == "x" && self.known(name).is_none() {
+            return Ok(Value::Poly(vec![Value::Int(0), Value::Int(1)]));
+        }
+        return self.known(name).ok_or_else(|| runtime("E0024", &[&name]));
+    }
+
+    fn known(&self, name: &str) -> Option<Value> {
+        match self.get(name).or_else(|| self.constants.get(name)) {
+            Some(v) => Some(v.clone()),
+            None => match CONSTANTS.iter().find(|(c, _)| *c == name) {
+                Some((_, c)) => Some(Value::Float(*c)),
+                None => parse_roman(name).map(Value::Int).or_else(|| unit(name).map(Value::Qty)),
+            },
+        }
+    }
+}
+
+// binary operators, `And` and `Or` skip the right operand when the left one decides
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Add, Sub, Mul, Div, FloorDiv, Pow,
+    Mod,        // `a % b`, the remainder of a // b, with the sign of b
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+    BitAnd, BitOr, Xor, Shl, Shr,
+    Convert,    // `a -> unit`
+    Seq,        // `a; b`, both evaluated, the value is b
+}
+
+impl Op {
+    pub const ALL: [Op; 22] = [
+        Op::Add, Op::Sub, Op::Mul, Op::Div, Op::FloorDiv, Op::Pow, Op::Mod, Op::Eq, Op::Ne, Op::Lt, Op::Le, Op::Gt, Op::Ge,
+        Op::And, Op::Or, Op::BitAnd, Op::BitOr, Op::Xor, Op::Shl, Op::Shr, Op::Convert, Op::Seq,
+    ];
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+", Op::Sub => "-", Op::Mul => "*", Op::Div => "/", Op::FloorDiv => "//", Op::Pow => "^", Op::Mod => "%",
+            Op::Eq => "==", Op::Ne => "!=", Op::Lt => "<", Op::Le => "<=", Op::Gt => ">", Op::Ge => ">=",
+            Op::And => "&&", Op::Or => "||",
+            Op::BitAnd => "&", Op::BitOr => "|", Op::Xor => "xor", Op::Shl => "<<", Op::Shr => ">>",
+            Op::Convert => "->",
+            Op::Seq => ";",
+        }
+    }
+
+    // `percent`: b is a percentage, a + b% adds b percent of a
+    fn apply(self, a: &Value, b: &Value, percent: bool) -> Result<Value, CalcError> {
+        let holds = |h: bool| Ok(Value::Int(h as i32));
+        match self {
+            Op::Add if percent => a + &(a * b)?,
+            Op::Sub if percent => a - &(a * b)?,
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::FloorDiv => a.floor_div(b),
+            Op::Mod if b.is_zero()? => Err(CalcError::DivisionByZero(format!("{} % {}", a, b), Span::default())),
+            Op::Mod => a - &(&a.floor_div(b)? * b)?,
+            Op::Pow => a.pow(b),
+            Op::Eq => holds(a == b),
+            Op::Ne => holds(a != b),
+            Op::Lt => holds(a < b),
+            Op::Le => holds(a <= b),
+            Op::Gt => holds(a > b),
+            Op::Ge => holds(a >= b),
+            Op::And | Op::Or => holds(!b.is_zero()?),
+            Op::Convert => Quantity::convert(a, b),
+            Op::Seq => Ok(b.clone()),
+            Op::BitAnd | Op::BitOr | Op::Xor | Op::Shl | Op::Shr => {
+                let (a, b) = (bit_operand(self.symbol(), a)?, bit_operand(self.symbol(), b)?);
+                let shift = || if (0..32).contains(&b) { Ok(b) } else { Err(runtime("E0028", &[&b])) };
+                Ok(Value::Int(match self {
+                    Op::BitAnd => a & b,
+                    Op::BitOr => a | b,
+                    Op::Xor => a ^ b,
+                    Op::Shl => a << shift()?,
+                    _ => a >> shift()?,
+                }))
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Assoc {
+    Left,       // 1 - 2 - 3 is (1 - 2) - 3
+    Right,      // 2 ^ 3 ^ 2 is 2 ^ (3 ^ 2)
+}
+
+// what a configured operator does, a built-in one or a function of two arguments
+#[derive(Clone, Copy, Debug)]
+pub enum Infix {
+    Op(Op),
+    Call(&'static Builtin),
+}
+
+// a binary operator of the precedence table, built in or from the config file and CalculatorBuilder::operator, 
+// e.g. `**` for powers; one with the symbol of a built-in operator replaces it
+#[derive(Clone, Copy, Debug)]
+pub struct Operator {
+    pub symbol: &'static str,
+    pub precedence: u8,     // as in `precedence`, from 4 for || to 14 for ^
+    pub assoc: Assoc,
+    pub apply: Infix,
+}
+
+impl Operator {
+    fn builtin(op: Op) -> Operator {
+        let assoc = if op == Op::Pow { Assoc::Right } else { Assoc::Left };
+        Operator { symbol: op.symbol(), precedence: precedence(op.symbol()), assoc, apply: Infix::Op(op) }
+    }
+
+    fn node(&self, l: Box<Expr>, r: Box<Expr>, span: Span) -> Box<Expr> {
+        match self.apply {
+            Infix::Op(op) => Box::new(Expr::Bin(op, l, r, span)),
+            Infix::Call(f) => {
+                let span = l.span().to(r.span());
+                Box::new(Expr::Call(f, vec![*l, *r], span))
+            },
+        }
+    }
+}
+
+// a lowercase word like `mod`, or symbols like `**` that are not also brackets, separators, 
+// a prefix operator or part of a number or name
+fn operator_symbol_ok(symbol: &str) -> bool {
+    let word = symbol.chars().all(|c| c.is_ascii_lowercase());
+    let symbols = symbol.chars().all(|c| !c.is_alphanumeric() && !c.is_whitespace() && !"()[],;#$\"._'".contains(c))
+        && !["-", "!", "~", "=", "?", ":", "->"].contains(&symbol);
+    return !symbol.is_empty() && (word || symbols);
+}
+
+// the precedences an operator can have, || to ^
+fn precedence_ok(level: u8) -> bool {
+    (precedence("||")..=precedence("^")).contains(&level)
+}
+
+// bitwise operators work on the 32-bit integers only
+fn bit_operand(op: &str, v: &Value) -> Result<i32, CalcError> {
+    v.int().ok_or_else(|| runtime("E0027", &[&op, v]))
+}
+
+// the parsed form of an expression
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Num(Value, Span),
+    Ref(String, Value, Span),       // a cell or `$NAME`, resolved while parsing
+    Var(String, Span),
+    Neg(Box<Expr>, Span),
+    Not(Box<Expr>, Span),
+    Inv(Box<Expr>, Span),           // ~
+    Pct(Box<Expr>, Span),           // n% is n/100, but a + n% adds n percent of a
+    Par(Box<Expr>, Span),
+    Bin(Op, Box<Expr>, Box<Expr>, Span),     // the span is that of the operator
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),      // cond ? a : b
+    Assign(Box<Expr>, Box<Expr>),   // an Expr::Var and its new value
+    Call(&'static Builtin, Vec<Expr>, Span),
+    List(Vec<Expr>, Span),          // [a, b, c]
+}
+
+// hooks called around the evaluation of every node
+pub trait EvalObserver {
+    fn enter(&mut self, _node: &Expr) {}
+    fn exit(&mut self, _node: &Expr, _operands: &[Value], _result: &Value) {}
+}
+impl EvalObserver for () {}
+
+// an observer that may be switched off, `--trace` and `--trace-json` each
+impl<T: EvalObserver> EvalObserver for Option<T> {
+    fn enter(&mut self, node: &Expr) {
+        if let Some(o) = self { o.enter(node); }
+    }
+    fn exit(&mut self, node: &Expr, operands: &[Value], result: &Value) {
+        if let Some(o) = self { o.exit(node, operands, result); }
+    }
+}
+
+// two observers of the same evaluation
+impl<A: EvalObserver, B: EvalObserver> EvalObserver for (A, B) {
+    fn enter(&mut self, node: &Expr) {
+        self.0.enter(node);
+        self.1.enter(node);
+    }
+    fn exit(&mut self, node: &Expr, operands: &[Value], result: &Value) {
+        self.0.exit(node, operands, result);
+        self.1.exit(node, operands, result);
+    }
+}
+
+impl Expr {
+    pub fn eval(&self) -> Result<Value, CalcError> { self.eval_in(&mut Env::default()) }
+    pub fn eval_in(&self, env: &mut Env) -> Result<Value, CalcError> { self.eval_with(env, &mut ()) }
+
+    // operands first, then the node itself, reporting both to the observer; 
+    // the first error stops the evaluation, the observer does not see the node it happened in exit
+    pub fn eval_with(&self, env: &mut Env, obs: &mut dyn EvalObserver) -> Result<Value, CalcError> {
+        obs.enter(self);
+        match self {
+            Expr::Num(v, _) | Expr::Ref(_, v, _) => self.done(obs, &[], v.clone()),
+            Expr::Var(name, _) => {
+                let v = env.lookup(name)?;
+                self.done(obs, &[], v)
+            },
+            Expr::Neg(c, _) | Expr::Not(c, _) | Expr::Inv(c, _) | Expr::Pct(c, _) | Expr::Par(c, _) => {
+                let a = c.eval_with(env, obs)?;
+                let v = unary(self.op(), &a)?;
+                self.done(obs, &[a], v)
+            },
+            Expr::Bin(..) => self.eval_chain(env, obs),
+            // only the chosen branch is evaluated, the observer sees the condition and its value
+            Expr::Cond(c, a, b) => {
+                let cond = c.eval_with(env, obs)?;
+                let v = if cond.is_zero()? { b.eval_with(env, obs)? } else { a.eval_with(env, obs)? };
+                self.done(obs, &[cond, v.clone()], v)
+            },
+            // only the value is evaluated, the variable may not be set yet
+            Expr::Assign(var, value) => {
+                let v = value.eval_with(env, obs)?;
+                env.assign(&var.repr(), v.clone());
+                let result = v.clone();
+                self.done(obs, &[v], result)
+            },
+            Expr::Call(f, args, _) => {
+                let values = args.iter().map(|a| a.eval_with(env, obs)).collect::<Result<Vec<Value>, CalcError>>()?;
+                let v = f.call(&values)?;
+                self.done(obs, &values, v)
+            },
+            Expr::List(items, _) => {
+                let values = items.iter().map(|a| a.eval_with(env, obs)).collect::<Result<Vec<Value>, CalcError>>()?;
+                let v = Value::List(values.clone());
+                self.done(obs, &values, v)
+            },
+        }
+    }
+
+    // a left-assoc chain, 1 + 2 + ... + n, is walked down in a loop rather than recursed into, so its length 
+    // is not limited by the stack; the observer sees its nodes in the same order as the recursion
+    fn eval_chain(&self, env: &mut Env, obs: &mut dyn EvalObserver) -> Result<Value, CalcError> {
+        let mut chain = Vec::new();
+        let mut node = self;
+        while let Expr::Bin(op, l, r, span) = node {
+            if !chain.is_empty() {
+                obs.enter(node);
+            }
+            chain.push((node, op, &**r, *span));
+            node = l;
+        }
+        let mut a = node.eval_with(env, obs)?;
+        for (node, op, r, span) in chain.into_iter().rev() {
+            // the observer only sees the left operand of a short-circuit
+            if matches!(op, Op::And | Op::Or) && a.is_zero()? == (*op == Op::And) {
+                let v = Value::Int(!a.is_zero()? as i32);
+                a = node.done(obs, &[a], v)?;
+                continue;
+            }
+            let b = r.eval_with(env, obs)?;
+            let v = op.apply(&a, &b, matches!(r, Expr::Pct(..))).map_err(|e| e.at(span))?;
+            a = node.done(obs, &[a, b], v)?;
+        }
+        return Ok(a);
+    }
+
+    fn done(&self, obs: &mut dyn EvalObserver, operands: &[Value], result: Value) -> Result<Value, CalcError> {
+        // a literal or a name, `inf`, is not a step up
+        if !matches!(self, Expr::Num(..) | Expr::Ref(..) | Expr::Var(..)) {
+            nan_policy().check(self, operands, &result)?;
+        }
+        obs.exit(self, operands, &result);
+        return Ok(result);
+    }
+
+    pub fn repr(&self) -> String {
+        match self {
+            Expr::Num(v, _) => v.to_string(),
+            Expr::Ref(name, _, _) | Expr::Var(name, _) => name.clone(),
+            Expr::Neg(c, _) => format!("<-{}>", c.repr()),
+            Expr::Not(c, _) => format!("<!{}>", c.repr()),
+            Expr::Inv(c, _) => format!("<~{}>", c.repr()),
+            Expr::Pct(c, _) => format!("<{}%>", c.repr()),
+            Expr::Par(c, _) => format!("({})", c.repr()),
+            Expr::Bin(Op::Xor, l, r, _) => format!("<{} xor {}>", l.repr(), r.repr()),
+            Expr::Bin(op, l, r, _) => format!("<{}{}{}>", l.repr(), op.symbol(), r.repr()),
+            Expr::Cond(c, a, b) => format!("<{}?{}:{}>", c.repr(), a.repr(), b.repr()),
+            Expr::Assign(var, value) => format!("<{}={}>", var.repr(), value.repr()),
+            Expr::Call(f, args, _) => format!("{}({})", f.name, args.iter().map(|a| a.repr()).collect::<Vec<String>>().join(",")),
+            Expr::List(items, _) => format!("[{}]", items.iter().map(|a| a.repr()).collect::<Vec<String>>().join(",")),
+        }
+    }
+
+    // `--simplify`: constant subtrees folded, x*1, x+0 and the like dropped, --x is x,
+    // the result evaluates to the same value
+    pub fn simplify(&self) -> Expr {
+        let span = self.span();
+        let n = match self {
+            Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => self.clone(),
+            Expr::Par(c, _) => match c.simplify() {
+                c if is_infix(&c) || matches!(c, Expr::Cond(..)) => Expr::Par(Box::new(c), span),
+                c => c,
+            },
+            Expr::Neg(c, _) => match c.simplify() {
+                Expr::Neg(c, _) => *c,
+                c => Expr::Neg(Box::new(c), span),
+            },
+            Expr::Not(c, _) => Expr::Not(Box::new(c.simplify()), span),
+            Expr::Inv(c, _) => Expr::Inv(Box::new(c.simplify()), span),
+            Expr::Pct(c, _) => Expr::Pct(Box::new(c.simplify()), span),
+            Expr::Bin(op, l, r, at) => {
+                let (l, r) = (l.simplify(), r.simplify());
+                // integer identities only, x + 0.0 would turn an integer x into a float, and so does x / 1
+                let is = |e: &Expr, i: i32| matches!(e, Expr::Num(Value::Int(v), _) if *v == i);
+                match op {
+                    Op::Add if matches!((&l, &r), (Expr::Var(a, _), Expr::Var(b, _)) if a == b) => {
+                        Expr::Bin(Op::Mul, Box::new(Expr::Num(Value::Int(2), span)), Box::new(l), *at)
+                    },
+                    Op::Add if is(&l, 0) && !matches!(r, Expr::Pct(..)) => r,
+                    Op::Add | Op::Sub if is(&r, 0) => l,
+                    Op::Mul if is(&l, 1) => r,
+                    Op::Mul | Op::Pow if is(&r, 1) => l,
+                    _ => Expr::Bin(*op, Box::new(l), Box::new(r), *at),
+                }
+            },
+            Expr::Cond(c, a, b) => match c.simplify() {
+                Expr::Num(v, _) if v.is_zero() == Ok(true) => b.simplify(),
+                Expr::Num(v, _) if v.is_zero() == Ok(false) => a.simplify(),
+                c => Expr::Cond(Box::new(c), Box::new(a.simplify()), Box::new(b.simplify())),
+            },
+            Expr::Assign(var, value) => Expr::Assign(var.clone(), Box::new(value.simplify())),
+            Expr::Call(f, args, _) => Expr::Call(f, args.iter().map(|a| a.simplify()).collect(), span),
+            Expr::List(items, _) => Expr::List(items.iter().map(|a| a.simplify()).collect(), span),
+        };
+        // every operand known, e.g. 2 * 3 or 200 * 15%, unless evaluating fails like 1 / 0
+        let known = |e: &Expr| match e {
+            Expr::Num(..) => true,
+            Expr::Pct(c, _) => matches!(**c, Expr::Num(..)),
+            _ => false,
+        };
+        if matches!(n, Expr::Num(..) | Expr::Var(..) | Expr::Pct(..) | Expr::Assign(..) | Expr::List(..)) || !n.children().into_iter().all(known) {
+            return n;
+        }
+        if let Expr::Call(f, ..) = n && !f.pure() {
+            return n;
+        }
+        match n.eval() {
+            Ok(v) => Expr::Num(v, span),
+            Err(_) => n,
+        }
+    }
+
+    // `--partial`: what `env` knows filled in and folded, the unknowns left, 2 * x + 3 * 4 is 2 * x + 12 
+    // while x is unset; a name assigned in the expression itself is left alone everywhere
+    pub fn partial_eval(&self, env: &Env) -> Expr {
+        struct Assigned(Vec<String>);
+        impl Visitor for Assigned {
+            fn visit(&mut self, n: &Expr) {
+                if let Expr::Assign(var, _) = n {
+                    self.0.push(var.repr());
+                }
+                walk(self, n);
+            }
+        }
+        let mut assigned = Assigned(Vec::new());
+        assigned.visit(self);
+        struct Known<'a>(&'a Env, Vec<String>);
+        impl Folder for Known<'_> {
+            fn fold(&mut self, n: Expr) -> Expr {
+                match n {
+                    Expr::Var(ref name, span) if !self.1.contains(name) => match self.0.known(name) {
+                        Some(v) => Expr::Num(v, span),
+                        None => n,
+                    },
+                    n => fold_children(self, n),
+                }
+            }
+        }
+        return Known(env, assigned.0).fold(self.clone()).simplify();
+    }
+
+    // `calc solve`: the real roots of self == 0, in ascending order, for a self linear or quadratic in `var`; 
+    // the coefficients are the derivatives at 0, checked against the value at a few other points
+    pub fn solve(&self, var: &str) -> Result<Vec<Value>, CalcError> {
+        // the first name other than `var` that is not known
+        struct Unknown<'a>(&'a str, Option<CalcError>);
+        impl Visitor for Unknown<'_> {
+            fn visit(&mut self, n: &Expr) {
+                if let Expr::Var(name, _) = n && name != self.0 && self.1.is_none() {
+                    self.1 = Env::default().lookup(name).err();
+                }
+                walk(self, n);
+            }
+        }
+        let mut unknown = Unknown(var, None);
+        unknown.visit(self);
+        if let Some(e) = unknown.1 {
+            return Err(e);
+        }
+        let at = |n: &Expr, x: f64| {
+            let mut env = Env::default();
+            env.set(var, Value::Float(x));
+            return n.eval_in(&mut env)?.float();
+        };
+        let coefficients = || {
+            let d1 = self.derivative(var)?;
+            let (a, b, c) = (at(&d1.derivative(var)?, 0.0)? / 2.0, at(&d1, 0.0)?, at(self, 0.0)?);
+            for x in [1.0, -2.0, 3.5] {
+                let y = at(self, x)?;
+                // written so that NaN, from 1 / x at 0, does not fit
+                let fits = (y - (a * x * x + b * x + c)).abs() <= 1e-9 * (1.0 + y.abs());
+                if !fits {
+                    return Ok((a, b, c, false));
+                }
+            }
+            return Ok::<_, CalcError>((a, b, c, true));
+        };
+        let Ok((a, b, c, true)) = coefficients() else { return Err(runtime("E0038", &[&var])); };
+        if a == 0.0 && b == 0.0 && c == 0.0 {
+            return Err(runtime("E0039", &[&var]));
+        }
+        return Ok(real_roots(a, b, c));
+    }
+
+    // `calc diff`: d/d`var`, not simplified, 0 * x and 1 * x terms are left out as they are built
+    pub fn derivative(&self, var: &str) -> Result<Expr, CalcError> {
+        let span = self.span();
+        let num = |i: i32| Expr::Num(Value::Int(i), span);
+        let bin = |op: Op, l: Expr, r: Expr| Expr::Bin(op, Box::new(l), Box::new(r), span);
+        let is = |e: &Expr, i: i32| matches!(e, Expr::Num(Value::Int(v), _) if *v == i);
+        let mul = |l: Expr, r: Expr| match (l, r) {
+            (l, r) if is(&l, 0) || is(&r, 0) => num(0),
+            (l, r) if is(&l, 1) => r,
+            (l, r) if is(&r, 1) => l,
+            (l, r) => bin(Op::Mul, l, r),
+        };
+        let div = |l: Expr, r: Expr| if is(&l, 0) { num(0) } else { bin(Op::Div, l, r) };
+        let add = |l: Expr, r: Expr| match (l, r) {
+            (l, r) if is(&l, 0) => r,
+            (l, r) if is(&r, 0) => l,
+            (l, r) => bin(Op::Add, l, r),
+        };
+        let call = |name: &str, arg: Expr| Expr::Call(builtin(name).unwrap(), vec![arg], span);
+        // log(e) is 1
+        let log = |a: Expr| if matches!(&a, Expr::Var(name, _) if name == "e") { num(1) } else { call("log", a) };
+        let d = match self {
+            Expr::Num(..) | Expr::Ref(..) => num(0),
+            Expr::Var(name, _) => num(if name == var { 1 } else { 0 }),
+            Expr::Par(c, _) => return c.derivative(var),
+            Expr::Neg(c, _) => Expr::Neg(Box::new(c.derivative(var)?), span),
+            Expr::Bin(op @ (Op::Add | Op::Sub), l, r, _) => bin(*op, l.derivative(var)?, r.derivative(var)?),
+            Expr::Bin(Op::Mul, l, r, _) => {
+                add(mul(l.derivative(var)?, (**r).clone()), mul((**l).clone(), r.derivative(var)?))
+            },
+            Expr::Bin(Op::Div, l, r, _) if !mentions(r, var) => div(l.derivative(var)?, (**r).clone()),
+            Expr::Bin(Op::Div, l, r, _) => {
+                let top = bin(Op::Sub, mul(l.derivative(var)?, (**r).clone()), mul((**l).clone(), r.derivative(var)?));
+                bin(Op::Div, top, bin(Op::Pow, (**r).clone(), num(2)))
+            },
+            // x ^ n by the power rule, c ^ x is c ^ x * log(c), anything else as e ^ (b * log(a))
+            Expr::Bin(Op::Pow, l, r, _) if !mentions(r, var) => {
+                let power = bin(Op::Pow, (**l).clone(), bin(Op::Sub, (**r).clone(), num(1)));
+                mul(mul((**r).clone(), power), l.derivative(var)?)
+            },
+            Expr::Bin(Op::Pow, l, r, _) if !mentions(l, var) => mul(mul(self.clone(), log((**l).clone())), r.derivative(var)?),
+            Expr::Bin(Op::Pow, l, r, _) => {
+                let inner = add(mul(r.derivative(var)?, log((**l).clone())),
+                    mul((**r).clone(), div(l.derivative(var)?, (**l).clone())));
+                mul(self.clone(), inner)
+            },
+            Expr::Call(f, args, _) if args.len() == 1 => {
+                let (a, da) = (args[0].clone(), args[0].derivative(var)?);
+                let outer = match f.name {
+                    "sin" => call("cos", a),
+                    "cos" => Expr::Neg(Box::new(call("sin", a)), span),
+                    "tan" => bin(Op::Div, num(1), bin(Op::Pow, call("cos", a), num(2))),
+                    "sqrt" => bin(Op::Div, num(1), bin(Op::Mul, num(2), call("sqrt", a))),
+                    "log" => bin(Op::Div, num(1), a),
+                    _ => return Err(runtime("E0029", &[&f.name])),
+                };
+                mul(outer, da)
+            },
+            _ => return Err(runtime("E0029", &[&self.op()])),
+        };
+        return Ok(d);
+    }
+
+    // `calc autodiff`: the value and the slope by `var` at `at`, in one pass over dual numbers (value, derivative), 
+    // no symbolic derivative is built and a condition picks the branch taken at `at`
+    pub fn autodiff(&self, var: &str, at: f64) -> Result<(f64, f64), CalcError> {
+        let d = |c: &Expr| c.autodiff(var, at);
+        let dual = match self {
+            Expr::Var(name, _) if name == var => (at, 1.0),
+            Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => (self.eval()?.float()?, 0.0),
+            Expr::Par(c, _) => d(c)?,
+            Expr::Neg(c, _) => {
+                let (v, dv) = d(c)?;
+                (-v, -dv)
+            },
+            Expr::Pct(c, _) => {
+                let (v, dv) = d(c)?;
+                (v / 100.0, dv / 100.0)
+            },
+            Expr::Cond(c, a, b) => {
+                let mut env = Env::default();
+                env.set(var, Value::Float(at));
+                if c.eval_in(&mut env)?.is_zero()? { d(b)? } else { d(a)? }
+            },
+            Expr::Bin(op, l, r, _) => {
+                let ((a, da), (b, db)) = (d(l)?, d(r)?);
+                match op {
+                    Op::Add => (a + b, da + db),
+                    Op::Sub => (a - b, da - db),
+                    Op::Mul => (a * b, da * b + a * db),
+                    Op::Div => (a / b, (da * b - a * db) / (b * b)),
+                    Op::Pow if db == 0.0 => (a.powf(b), b * a.powf(b - 1.0) * da),
+                    Op::Pow => (a.powf(b), a.powf(b) * (db * a.ln() + b * da / a)),
+                    _ => return Err(runtime("E0029", &[&op.symbol()])),
+                }
+            },
+            // the value as the function gives it, domain errors and --deg included
+            Expr::Call(f, args, _) if args.len() == 1 => {
+                let (a, da) = d(&args[0])?;
+                let v = f.call(&[Value::Float(a)])?.float()?;
+                let k = angle(1.0);
+                let outer = match f.name {
+                    "sin" => k * angle(a).cos(),
+                    "cos" => -k * angle(a).sin(),
+                    "tan" => k / angle(a).cos().powi(2),
+                    "sqrt" => 0.5 / v,
+                    "log" => 1.0 / a,
+                    "abs" => a.signum(),
+                    _ => return Err(runtime("E0029", &[&f.name])),
+                };
+                (v, outer * da)
+            },
+            _ => return Err(runtime("E0029", &[&self.op()])),
+        };
+        return Ok(dual);
+    }
+
+    // what `--verbose`, the debugger and the passes below match on
+    pub fn op(&self) -> &'static str {
+        match self {
+            Expr::Num(..) => "num",
+            Expr::Ref(..) => "ref",
+            Expr::Var(..) => "var",
+            Expr::Neg(..) => "neg",
+            Expr::Not(..) => "!",
+            Expr::Inv(..) => "~",
+            Expr::Pct(..) => "%",
+            Expr::Par(..) => "()",
+            Expr::Bin(op, ..) => op.symbol(),
+            Expr::Cond(..) => "?:",
+            Expr::Assign(..) => "=",
+            Expr::Call(f, _, _) => f.name,
+            Expr::List(..) => "[]",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Num(_, span) | Expr::Ref(_, _, span) | Expr::Var(_, span) | Expr::Call(_, _, span) | Expr::List(_, span) => *span,
+            Expr::Neg(_, span) | Expr::Not(_, span) | Expr::Inv(_, span) | Expr::Pct(_, span) | Expr::Par(_, span) => *span,
+            Expr::Bin(_, l, r, _) | Expr::Cond(l, _, r) | Expr::Assign(l, r) => l.span().to(r.span()),
+        }
+    }
+
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => vec![],
+            Expr::Neg(c, _) | Expr::Not(c, _) | Expr::Inv(c, _) | Expr::Pct(c, _) | Expr::Par(c, _) => vec![c],
+            Expr::Bin(_, l, r, _) | Expr::Assign(l, r) => vec![l, r],
+            Expr::Cond(c, a, b) => vec![c, a, b],
+            Expr::Call(_, args, _) | Expr::List(args, _) => args.iter().collect(),
+        }
+    }
+}
+
+// a read-only pass over an `Expr`, e.g. counting operations or collecting variables: 
+// override `visit` and call `walk` from it to go on into the children
+pub trait Visitor {
+    fn visit(&mut self, n: &Expr) {
+        walk(self, n);
+    }
+}
+
+pub fn walk<V: Visitor + ?Sized>(v: &mut V, n: &Expr) {
+    for c in n.children() {
+        v.visit(c);
+    }
+}
+
+// a pass rebuilding an `Expr`, e.g. substituting a variable: 
+// override `fold` and call `fold_children` from it to rebuild the children first
+pub trait Folder {
+    fn fold(&mut self, n: Expr) -> Expr {
+        fold_children(self, n)
+    }
+}
+
+pub fn fold_children<F: Folder + ?Sized>(f: &mut F, n: Expr) -> Expr {
+    let mut fold = |c: Box<Expr>| Box::new(f.fold(*c));
+    match n {
+        Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => n,
+        Expr::Neg(c, span) => Expr::Neg(fold(c), span),
+        Expr::Not(c, span) => Expr::Not(fold(c), span),
+        Expr::Inv(c, span) => Expr::Inv(fold(c), span),
+        Expr::Pct(c, span) => Expr::Pct(fold(c), span),
+        Expr::Par(c, span) => Expr::Par(fold(c), span),
+        Expr::Bin(op, l, r, span) => Expr::Bin(op, fold(l), fold(r), span),
+        Expr::Cond(c, a, b) => Expr::Cond(fold(c), fold(a), fold(b)),
+        Expr::Assign(var, value) => Expr::Assign(var, fold(value)),
+        Expr::Call(f, args, span) => Expr::Call(f, args.into_iter().map(|a| *fold(Box::new(a))).collect(), span),
+        Expr::List(items, span) => Expr::List(items.into_iter().map(|a| *fold(Box::new(a))).collect(), span),
+    }
+}
+
+// neg, !, ~ and %, by their Expr::op
+fn unary(op: &str, a: &Value) -> Result<Value, CalcError> {
+    match op {
+        "neg" => -a,
+        "!" => Ok(Value::Int(a.is_zero()? as i32)),
+        "~" => Ok(Value::Int(!bit_operand("~", a)?)),
+        "%" if decimal() => a / &Value::Int(100),
+        "%" => Ok(Value::Float(a.float()? / 100.0)),
+        _ => Ok(a.clone()),
+    }
+}
+
+// a compiled expression, for evaluating the same one many times, e.g. with changing variables
+#[derive(Clone, Debug)]
+pub struct Program(pub Vec<Instr>);
+
+// instructions working on a stack of values
+#[derive(Clone, Debug)]
+pub enum Instr {
+    Push(Value),
+    Load(String),
+    Store(String),          // sets the variable to the top of the stack, which stays
+    Unary(&'static str),    // neg, !, ~ or %
+    Bin(Op, bool, Span),    // pops b then a, pushes a op b, true if b is a percentage, at the operator's span
+    Call(&'static Builtin), // pops as many arguments as the function takes
+    List(usize),            // pops that many values, pushes them as one list
+    Truth,                  // replaces the top with 0 or 1
+    Jump(usize),
+    JumpIf(bool, usize),    // pops the top and jumps if its truth is the given one
+}
+
+pub fn compile(e: &Expr) -> Program {
+    let mut code = Vec::new();
+    emit(e, &mut code);
+    return Program(code);
+}
+
+fn emit(e: &Expr, code: &mut Vec<Instr>) {
+    match e {
+        Expr::Num(v, _) | Expr::Ref(_, v, _) => code.push(Instr::Push(v.clone())),
+        Expr::Var(name, _) => code.push(Instr::Load(name.clone())),
+        Expr::Par(c, _) => emit(c, code),
+        Expr::Neg(c, _) | Expr::Not(c, _) | Expr::Inv(c, _) | Expr::Pct(c, _) => {
+            emit(c, code);
+            code.push(Instr::Unary(e.op()));
+        },
+        // a && b: a, if false jump to `push 0`, b, truth, jump to the end
+        Expr::Bin(op @ (Op::And | Op::Or), l, r, _) => {
+            let decides = *op == Op::Or;
+            emit(l, code);
+            let skip = code.len();
+            code.push(Instr::JumpIf(decides, 0));
+            emit(r, code);
+            code.push(Instr::Truth);
+            let end = code.len();
+            code.push(Instr::Jump(0));
+            code[skip] = Instr::JumpIf(decides, code.len());
+            code.push(Instr::Push(Value::Int(decides as i32)));
+            code[end] = Instr::Jump(code.len());
+        },
+        Expr::Bin(op, l, r, span) => {
+            emit(l, code);
+            emit(r, code);
+            code.push(Instr::Bin(*op, matches!(**r, Expr::Pct(..)), *span));
+        },
+        Expr::Cond(c, a, b) => {
+            emit(c, code);
+            let otherwise = code.len();
+            code.push(Instr::JumpIf(false, 0));
+            emit(a, code);
+            let end = code.len();
+            code.push(Instr::Jump(0));
+            code[otherwise] = Instr::JumpIf(false, code.len());
+            emit(b, code);
+            code[end] = Instr::Jump(code.len());
+        },
+        Expr::Assign(var, value) => {
+            emit(value, code);
+            code.push(Instr::Store(var.repr()));
+        },
+        Expr::Call(f, args, _) => {
+            for a in args {
+                emit(a, code);
+            }
+            code.push(Instr::Call(f));
+        },
+        Expr::List(items, _) => {
+            for a in items {
+                emit(a, code);
+            }
+            code.push(Instr::List(items.len()));
+        },
+    }
+}
+
+impl Program {
+    // the same result, and the same errors, as Expr::eval_in, but for NanPolicy::Error, whose errors need the expression
+    pub fn run(&self, env: &mut Env) -> Result<Value, CalcError> {
+        let mut stack: Vec<Value> = Vec::new();
+        let mut pc = 0;
+        while pc < self.0.len() {
+            match &self.0[pc] {
+                Instr::Push(v) => stack.push(v.clone()),
+                Instr::Load(name) => stack.push(env.lookup(name)?),
+                Instr::Store(name) => env.assign(name, stack.last().unwrap().clone()),
+                Instr::Unary(op) => {
+                    let a = stack.pop().unwrap();
+                    stack.push(unary(op, &a)?);
+                },
+                Instr::Bin(op, percent, span) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(op.apply(&a, &b, *percent).map_err(|e| e.at(*span))?);
+                },
+                Instr::Call(f) => {
+                    let args = stack.split_off(stack.len() - f.arity);
+                    stack.push(f.call(&args)?);
+                },
+                Instr::List(n) => {
+                    let items = stack.split_off(stack.len() - n);
+                    stack.push(Value::List(items));
+                },
+                Instr::Truth => {
+                    let a = stack.pop().unwrap();
+                    stack.push(Value::Int(!a.is_zero()? as i32));
+                },
+                Instr::Jump(target) => {
+                    pc = *target;
+                    continue;
+                },
+                Instr::JumpIf(truth, target) => {
+                    if stack.pop().unwrap().is_zero()? != *truth {
+                        pc = *target;
+                        continue;
+                    }
+                },
+            }
+            pc += 1;
+        }
+        return Ok(stack.pop().unwrap());
+    }
+
+    // `calc disasm`: the pushed constants as a numbered pool, then one instruction per line after its address; 
+    // a jump may target the end, one past the last instruction
+    pub fn disassemble(&self) -> Vec<String> {
+        let mut pool = Vec::new();
+        let mut code = Vec::new();
+        for (pc, instr) in self.0.iter().enumerate() {
+            let text = match instr {
+                Instr::Push(v) => {
+                    pool.push(format!("  #{:<3} {}", pool.len(), v));
+                    format!("push    #{}", pool.len() - 1)
+                },
+                Instr::Load(name) => format!("load    {}", name),
+                Instr::Store(name) => format!("store   {}", name),
+                Instr::Unary(op) => format!("unary   {}", op),
+                Instr::Bin(op, percent, _) => format!("bin     {}{}", op.symbol(), if *percent { " %" } else { "" }),
+                Instr::Call(f) => format!("call    {}/{}", f.name, f.arity),
+                Instr::List(n) => format!("list    {}", n),
+                Instr::Truth => "truth".to_string(),
+                Instr::Jump(target) => format!("jump    -> {}", target),
+                Instr::JumpIf(truth, target) => format!("jumpif  {} -> {}", truth, target),
+            };
+            code.push(format!("{:>4}  {}", pc, text));
+        }
+        code.push(format!("{:>4}  end", self.0.len()));
+        let mut lines = vec!["constants:".to_string()];
+        lines.extend(pool);
+        lines.push("code:".to_string());
+        lines.extend(code);
+        return lines;
+    }
+}
+
+fn precedence(op: &str) -> u8 {
+    match op {
+        ";" => 0,
+        "=" => 1,
+        "->" => 2,
+        "?:" => 3,
+        "||" => 4,
+        "&&" => 5,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => 6,
+        "|" => 7,
+        "xor" => 8,
+        "&" => 9,
+        "<<" | ">>" => 10,
+        "+" | "-" => 11,
+        "*" | "/" | "//" | "%" => 12,
+        "neg" | "!" | "~" => 13,
+        "^" => 14,
+        _ => 15,
+    }
+}
+
+fn mentions(n: &Expr, var: &str) -> bool {
+    matches!(n, Expr::Var(name, _) if name == var) || n.children().into_iter().any(|c| mentions(c, var))
+}
+
+// binary operators, as opposed to function calls of two arguments
+fn is_infix(n: &Expr) -> bool {
+    n.children().len() == 2 && !matches!(n, Expr::Call(..) | Expr::List(..))
+}
+
+// functions callable as `name(arg, ...)`
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub f: Func,
+}
+
+// each fails with a CalcError for arguments outside its domain, e.g. sqrt(-1)
+pub enum Func {
+    Int(fn(&[i32]) -> Result<i32, CalcError>),         // integer arguments only, e.g. bit twiddling
+    Float(fn(&[f64]) -> Result<f64, CalcError>),       // arguments converted to floats
+    Value(fn(&[Value]) -> Result<Value, CalcError>),   // keeps integers integers
+    Custom(Box<NativeFn>),                              // registered with CalculatorBuilder::function
+}
+
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, CalcError> + Send + Sync;
+
+impl std::fmt::Debug for Func {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Func::Int(_) => write!(f, "Int"),
+            Func::Float(_) => write!(f, "Float"),
+            Func::Value(_) => write!(f, "Value"),
+            Func::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl Builtin {
+    // the same arguments always give the same result, which rand() does not
+    pub fn pure(&self) -> bool {
+        !matches!(self.name, "rand" | "randint" | "now") && !matches!(self.f, Func::Custom(_))
+    }
+
+    fn call(&self, args: &[Value]) -> Result<Value, CalcError> {
+        match &self.f {
+            Func::Int(f) => {
+                let args = args.iter().enumerate()
+                    .map(|(i, v)| v.int().ok_or_else(|| runtime("E0016", &[&(i + 1), &self.name, v])))
+                    .collect::<Result<Vec<i32>, CalcError>>()?;
+                Ok(Value::Int(f(&args)?))
+            },
+            Func::Float(f) => Ok(Value::Float(f(&args.iter().map(Value::float).collect::<Result<Vec<f64>, CalcError>>()?)?)),
+            Func::Value(f) => f(args),
+            Func::Custom(f) => f(args),
+        }
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "roundto", arity: 2, f: Func::Value(|v| roundto(&v[0], &v[1])) },
+    Builtin { name: "popcount", arity: 1, f: Func::Int(|v| Ok(v[0].count_ones() as i32)) },
+    Builtin { name: "clz", arity: 1, f: Func::Int(|v| Ok(v[0].leading_zeros() as i32)) },
+    Builtin { name: "ctz", arity: 1, f: Func::Int(|v| Ok(v[0].trailing_zeros() as i32)) },
+    Builtin { name: "bit", arity: 2, f: Func::Int(|v| Ok((v[0] >> bit_index("bit", v[1])?) & 1)) },
+    Builtin { name: "setbit", arity: 2, f: Func::Int(|v| Ok(v[0] | (1 << bit_index("setbit", v[1])?))) },
+    Builtin { name: "clearbit", arity: 2, f: Func::Int(|v| Ok(v[0] & !(1 << bit_index("clearbit", v[1])?))) },
+    Builtin { name: "rotl", arity: 3, f: Func::Value(|v| rotate("rotl", v)) },
+    Builtin { name: "rotr", arity: 3, f: Func::Value(|v| rotate("rotr", v)) },
+    Builtin { name: "isqrt", arity: 1, f: Func::Value(|v| integer_fn("isqrt", v, |v| iroot("isqrt", v[0], 2), |v| big_iroot("isqrt", &v[0], 2))) },
+    Builtin { name: "iroot", arity: 2, f: Func::Value(|v| integer_fn("iroot", v, |v| iroot("iroot", v[0], v[1]), |v| big_iroot("iroot", &v[0], small_arg(2, "iroot", &v[1])?))) },
+    Builtin { name: "fib", arity: 1, f: Func::Value(|v| integer_fn("fib", v, |v| sequence("fib", v[0]), |v| big_sequence("fib", &v[0]))) },
+    Builtin { name: "lucas", arity: 1, f: Func::Value(|v| integer_fn("lucas", v, |v| sequence("lucas", v[0]), |v| big_sequence("lucas", &v[0]))) },
+    Builtin { name: "catalan", arity: 1, f: Func::Value(|v| integer_fn("catalan", v, |v| sequence("catalan", v[0]), |v| big_sequence("catalan", &v[0]))) },
+    Builtin { name: "triangular", arity: 1, f: Func::Value(|v| integer_fn("triangular", v, |v| sequence("triangular", v[0]), |v| big_sequence("triangular", &v[0]))) },
+    Builtin { name: "powmod", arity: 3, f: Func::Value(|v| integer_fn("powmod", v, |v| powmod(v[0], v[1], v[2]), big_powmod)) },
+    Builtin { name: "invmod", arity: 2, f: Func::Value(|v| integer_fn("invmod", v, |v| invmod(v[0], v[1]), |v| big_invmod(&v[0], &v[1]))) },
+    Builtin { name: "float_bits", arity: 1, f: Func::Value(|v| Ok(float_bits(v[0].float()?))) },
+    Builtin { name: "bits_to_float", arity: 1, f: Func::Value(|v| Ok(Value::Float(bits_to_float(&v[0])?))) },
+    Builtin { name: "ulp", arity: 1, f: Func::Float(|v| Ok(v[0].abs().next_up() - v[0].abs())) },
+    Builtin { name: "nextafter", arity: 2, f: Func::Float(|v| Ok(nextafter(v[0], v[1]))) },
+    Builtin { name: "wmean", arity: 2, f: Func::Value(|v| Ok(Value::Float(weighted("wmean", v)?.0))) },
+    Builtin { name: "wvar", arity: 2, f: Func::Value(|v| Ok(Value::Float(weighted("wvar", v)?.1))) },
+    Builtin { name: "poly", arity: 1, f: Func::Value(|v| match &v[0] {
+        Value::List(c) if !c.is_empty() && c.iter().all(|c| c.as_f64().is_some()) => Ok(polynomial(c.iter().rev().cloned().collect())),
+        Value::Poly(_) => Ok(v[0].clone()),
+        p => Err(runtime("E0016", &[&1, &"poly", p])),
+    }) },
+    Builtin { name: "polyval", arity: 2, f: Func::Value(|v| polyval(&v[0], &v[1])) },
+    Builtin { name: "roots", arity: 1, f: Func::Value(|v| poly_roots(&v[0])) },
+    Builtin { name: "percentile", arity: 2, f: Func::Value(|v| Ok(Value::Float(percentile(numbers("percentile", 1, &v[0])?, v[1].float()?)?))) },
+    Builtin { name: "sqrt", arity: 1, f: Func::Float(|v| Ok(domain("sqrt", v[0], v[0] >= 0.0)?.sqrt())) },
+    Builtin { name: "log", arity: 1, f: Func::Float(|v| Ok(domain("log", v[0], v[0] > 0.0)?.ln())) },
+    Builtin { name: "sin", arity: 1, f: Func::Float(|v| Ok(angle(v[0]).sin())) },
+    Builtin { name: "cos", arity: 1, f: Func::Float(|v| Ok(angle(v[0]).cos())) },
+    Builtin { name: "tan", arity: 1, f: Func::Float(|v| Ok(angle(v[0]).tan())) },
+    Builtin { name: "rand", arity: 0, f: Func::Float(|_| Ok((random() >> 11) as f64 / (1u64 << 53) as f64)) },
+    Builtin { name: "randint", arity: 2, f: Func::Int(|v| randint(v[0], v[1])) },
+    Builtin { name: "now", arity: 0, f: Func::Value(|_| unix_time().map(|t| Value::Date(t.as_secs() as i64)).ok_or_else(|| runtime("E0044", &[&"now()"]))) },
+    Builtin { name: "deg", arity: 1, f: Func::Float(|v| Ok(v[0].to_degrees())) },
+    Builtin { name: "rad", arity: 1, f: Func::Float(|v| Ok(v[0].to_radians())) },
+    Builtin { name: "abs", arity: 1, f: Func::Value(|v| if v[0].float()? < 0.0 { -&v[0] } else { Ok(v[0].clone()) }) },
+    Builtin { name: "min", arity: 2, f: Func::Value(|v| Ok(if v[1].float()? < v[0].float()? { v[1].clone() } else { v[0].clone() })) },
+    Builtin { name: "max", arity: 2, f: Func::Value(|v| Ok(if v[1].float()? > v[0].float()? { v[1].clone() } else { v[0].clone() })) },
+];
+
+// names that read as these values unless a variable of the same name is set
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("tau", std::f64::consts::TAU),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+// names that read as one of that unit unless a variable of the same name is set: name, SI value, powers of m, kg and s
+pub const UNITS: &[(&str, f64, [i8; 3])] = &[
+    ("m", 1.0, [1, 0, 0]), ("km", 1000.0, [1, 0, 0]), ("cm", 0.01, [1, 0, 0]), ("mm", 0.001, [1, 0, 0]),
+    ("in", 0.0254, [1, 0, 0]), ("ft", 0.3048, [1, 0, 0]), ("yd", 0.9144, [1, 0, 0]), ("mi", 1609.344, [1, 0, 0]),
+    ("kg", 1.0, [0, 1, 0]), ("g", 0.001, [0, 1, 0]), ("mg", 1e-6, [0, 1, 0]), ("t", 1000.0, [0, 1, 0]),
+    ("lb", 0.45359237, [0, 1, 0]), ("oz", 0.028349523125, [0, 1, 0]),
+    ("s", 1.0, [0, 0, 1]), ("ms", 0.001, [0, 0, 1]), ("h", 3600.0, [0, 0, 1]), ("day", 86400.0, [0, 0, 1]), ("d", 86400.0, [0, 0, 1]), ("week", 604800.0, [0, 0, 1]),
+    ("l", 0.001, [3, 0, 0]), ("ml", 1e-6, [3, 0, 0]),
+    ("mph", 0.44704, [1, 0, -1]), ("knot", 1852.0 / 3600.0, [1, 0, -1]),
+];
+
+// one of the unit, 1 km
+pub fn unit(name: &str) -> Option<Quantity> {
+    let (name, factor, dim) = UNITS.iter().find(|(u, _, _)| *u == name)?;
+    return Some(Quantity { value: 1.0, unit: name.to_string(), factor: *factor, dim: *dim });
+}
+
+// `--exact-roots`: isqrt and iroot fail instead of rounding down
+static EXACT_ROOTS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_exact_roots(exact: bool) {
+    EXACT_ROOTS.store(exact, std::sync::atomic::Ordering::Relaxed);
+}
+
+// time since the epoch, None on wasm32-unknown-unknown where std has no clock to read
+fn unix_time() -> Option<std::time::Duration> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return None;
+    }
+    return std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok();
+}
+
+// `--seed`: rand() and randint() give the same numbers for the same seed, otherwise they start from the clock
+static RANDOM: std::sync::Mutex<Option<Rng>> = std::sync::Mutex::new(None);
+
+pub fn set_seed(seed: u64) {
+    *RANDOM.lock().unwrap() = Some(Rng(seed));
+}
+
+fn random() -> u64 {
+    RANDOM.lock().unwrap().get_or_insert_with(Rng::from_time).next_u64()
+}
+
+// uniform in [lo, hi], both ends included
+fn randint(lo: i32, hi: i32) -> Result<i32, CalcError> {
+    if hi < lo {
+        return Err(runtime("E0016", &[&2, &"randint", &hi]));
+    }
+    return Ok((lo as i64 + (random() % (hi as i64 - lo as i64 + 1) as u64) as i64) as i32);
+}
+
+// `--deg`: sin, cos and tan take degrees instead of radians
+static DEGREES: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_degrees(degrees: bool) {
+    DEGREES.store(degrees, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn degrees() -> bool {
+    DEGREES.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// an angle in radians, whatever the mode
+fn angle(x: f64) -> f64 {
+    if degrees() { x.to_radians() } else { x }
+}
+
+// the names an expression can use, e.g. for completion: functions, constants, units and the variables of `env`
+pub fn symbols(env: &Env) -> Vec<String> {
+    let mut names = BUILTINS.iter().map(|b| b.name).chain(CONSTANTS.iter().map(|(c, _)| *c)).chain(UNITS.iter().map(|(u, _, _)| *u))
+        .chain(env.vars().into_iter().map(|(v, _)| v))
+        .map(String::from).collect::<Vec<String>>();
+    names.sort();
+    names.dedup();
+    return names;
+}
+
+fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+// the single argument of a real function, if `ok`
+fn domain(name: &str, x: f64, ok: bool) -> Result<f64, CalcError> {
+    if !ok {
+        return Err(runtime("E0016", &[&1, &name, &x]));
+    }
+    return Ok(x);
+}
+
+// bit positions count from the least significant bit, 0 to 31
+fn bit_index(name: &str, n: i32) -> Result<i32, CalcError> {
+    if !(0..32).contains(&n) {
+        return Err(runtime("E0016", &[&2, &name, &n]));
+    }
+    return Ok(n);
+}
+
+// rotl and rotr, the arguments may be BigInts within 64 bits, 
+// and a 64-bit result past 32 bits is an error unless --bignum lets it grow
+fn rotate(name: &str, args: &[Value]) -> Result<Value, CalcError> {
+    let arg = |i: usize| match &args[i] {
+        Value::Int(n) => Some(*n as i64),
+        Value::Big(b) => b.to_i64(),
+        _ => None,
+    }.ok_or_else(|| runtime("E0016", &[&(i + 1), &name, &args[i]]));
+    let (x, n, width) = (arg(0)?, arg(1)?, arg(2)?);
+    let rotated = rotate_bits(name, x, n, width)?;
+    return match i32::try_from(rotated) {
+        Ok(i) => Ok(Value::Int(i)),
+        Err(_) if bignum() => Ok(Value::from_big(BigInt::from(rotated))),
+        Err(_) => Err(runtime("E0030", &[&format!("{}({}, {}, {})", name, x, n, width)])),
+    };
+}
+
+// rotate the low `width` bits of `x` left by `n`, right for negative `n` or `rotr`, 
+// 32 and 64-bit results are read back as two's complement
+fn rotate_bits(name: &str, x: i64, n: i64, width: i64) -> Result<i64, CalcError> {
+    if ![8, 16, 32, 64].contains(&width) {
+        return Err(runtime("E0016", &[&3, &name, &width]));
+    }
+    let mask = u64::MAX >> (64 - width);
+    let x = x as u64 & mask;
+    let n = n.rem_euclid(width);
+    let n = if name == "rotr" { (width - n) % width } else { n } as u32;
+    let rotated = ((x << n) | x.checked_shr(width as u32 - n).unwrap_or(0)) & mask;
+    return Ok(if width == 32 { rotated as u32 as i32 as i64 } else { rotated as i64 });
+}
+
+// a function of integers with a BigInt twin, used with --bignum, where arguments and the result may be any size
+fn integer_fn(name: &str, args: &[Value], small: fn(&[i32]) -> Result<i32, CalcError>, big: fn(&[BigInt]) -> Result<BigInt, CalcError>) -> Result<Value, CalcError> {
+    let wrong = |i: usize, v: &Value| runtime("E0016", &[&(i + 1), &name, v]);
+    if !bignum() {
+        let args = args.iter().enumerate().map(|(i, v)| v.int().ok_or_else(|| wrong(i, v))).collect::<Result<Vec<i32>, CalcError>>()?;
+        return Ok(Value::Int(small(&args)?));
+    }
+    let args = args.iter().enumerate()
+        .map(|(i, v)| if matches!(v, Value::Int(_) | Value::Big(_)) { Ok(v.big()) } else { Err(wrong(i, v)) })
+        .collect::<Result<Vec<BigInt>, CalcError>>()?;
+    return Ok(Value::from_big(big(&args)?));
+}
+
+// an argument that has to fit in an i32 even with --bignum, a root's degree or a sequence index
+fn small_arg(i: usize, name: &str, n: &BigInt) -> Result<i32, CalcError> {
+    n.to_i32().ok_or_else(|| runtime("E0016", &[&i, &name, n]))
+}
+
+// floor of the k-th root, by bisection so large n stay exact
+fn iroot(name: &str, n: i32, k: i32) -> Result<i32, CalcError> {
+    if k < 1 {
+        return Err(runtime("E0016", &[&2, &name, &k]));
+    }
+    if n < 0 && k % 2 == 0 {
+        return Err(runtime("E0016", &[&1, &name, &n]));
+    }
+    let pow = |r: i64| (r as i128).saturating_pow(k as u32);
+    // largest r in [lo, hi] with r^k <= n
+    let (mut lo, mut hi) = if n >= 0 { (0, n as i64) } else { (n as i64, 0) };
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if pow(mid) <= n as i128 { lo = mid; } else { hi = mid - 1; }
+    }
+    if EXACT_ROOTS.load(std::sync::atomic::Ordering::Relaxed) && pow(lo) != n as i128 {
+        return Err(runtime("E0018", &[&name, &n, &k]));
+    }
+    return Ok(lo as i32);
+}
+
+// iroot for --bignum, searching below 2^(bits of n / k + 1)
+fn big_iroot(name: &str, n: &BigInt, k: i32) -> Result<BigInt, CalcError> {
+    if k < 1 {
+        return Err(runtime("E0016", &[&2, &name, &k]));
+    }
+    if n.negative && k % 2 == 0 {
+        return Err(runtime("E0016", &[&1, &name, n]));
+    }
+    let bits = n.limbs.len() * 32;
+    let root = if k as usize >= bits {
+        // |n| < 2^k, so the root is within 1 of 0
+        match n {
+            n if !n.negative => BigInt::from(!n.limbs.is_empty() as i64),
+            n if *n == BigInt::from(-1) => n.clone(),
+            _ => BigInt::from(-2),
+        }
+    } else {
+        let bound = BigInt::from(2).pow((bits / k as usize + 1) as u32);
+        // largest r in [lo, hi] with r^k <= n
+        let (mut lo, mut hi) = if n.negative { (-bound, BigInt::default()) } else { (BigInt::default(), bound) };
+        while lo < hi {
+            let mid = lo.clone() + (hi.clone() - lo.clone() + BigInt::from(1)) / BigInt::from(2);
+            if mid.pow(k as u32) <= *n { lo = mid; } else { hi = mid - BigInt::from(1); }
+        }
+        lo
+    };
+    if EXACT_ROOTS.load(std::sync::atomic::Ordering::Relaxed) && root.pow(k as u32) != *n {
+        return Err(runtime("E0018", &[&name, n, &k]));
+    }
+    return Ok(root);
+}
+
+// n-th term of an integer sequence, starting from n = 0
+fn sequence(name: &str, n: i32) -> Result<i32, CalcError> {
+    if n < 0 {
+        return Err(runtime("E0016", &[&1, &name, &n]));
+    }
+    let n = n as i128;
+    let term = match name {
+        "triangular" => Some(n * (n + 1) / 2),
+        // C(k + 1) = C(k) * 2(2k + 1) / (k + 2), stopping early once out of range
+        "catalan" => (0..n).try_fold(1i128, |c, k| Some(c * 2 * (2 * k + 1) / (k + 2)).filter(|c| *c <= i32::MAX as i128)),
+        _ => {
+            let (mut a, mut b) = if name == "fib" { (0i128, 1i128) } else { (2, 1) };
+            for _ in 0..n {
+                (a, b) = (b, a + b);
+                if a > i32::MAX as i128 { break; }
+            }
+            Some(a)
+        },
+    };
+    return term.and_then(|t| i32::try_from(t).ok()).ok_or_else(|| runtime("E0019", &[&name, &n]));
+}
+
+// base^exp mod m by square-and-multiply, a negative exponent uses the inverse
+fn powmod(base: i32, exp: i32, m: i32) -> Result<i32, CalcError> {
+    if m < 1 {
+        return Err(runtime("E0016", &[&3, &"powmod", &m]));
+    }
+    let m = m as i64;
+    let mut base = if exp < 0 { invmod(base, m as i32)? as i64 } else { (base as i64).rem_euclid(m) };
+    let mut exp = (exp as i64).abs();
+    let mut result = 1 % m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % m;
+        }
+        base = base * base % m;
+        exp >>= 1;
+    }
+    return Ok(result as i32);
+}
+
+// the sequences for --bignum, without the 32 bit limit
+fn big_sequence(name: &str, n: &BigInt) -> Result<BigInt, CalcError> {
+    if n.negative {
+        return Err(runtime("E0016", &[&1, &name, n]));
+    }
+    if name == "triangular" {
+        return Ok(n.clone() * (n.clone() + BigInt::from(1)) / BigInt::from(2));
+    }
+    let n = small_arg(1, name, n)?;
+    if name == "catalan" {
+        return Ok((0..n as i64).fold(BigInt::from(1), |c, k| c * BigInt::from(2 * (2 * k + 1)) / BigInt::from(k + 2)));
+    }
+    let (mut a, mut b) = if name == "fib" { (BigInt::from(0), BigInt::from(1)) } else { (BigInt::from(2), BigInt::from(1)) };
+    for _ in 0..n {
+        (a, b) = (b.clone(), a + b);
+    }
+    return Ok(a);
+}
+
+// powmod for --bignum, the exponent a bit at a time from the lowest
+fn big_powmod(v: &[BigInt]) -> Result<BigInt, CalcError> {
+    let (base, exp, m) = (&v[0], &v[1], &v[2]);
+    if *m < BigInt::from(1) {
+        return Err(runtime("E0016", &[&3, &"powmod", m]));
+    }
+    let mut base = if exp.negative { big_invmod(base, m)? } else { base.rem_euclid(m) };
+    let mut result = BigInt::from(1).rem_euclid(m);
+    for i in 0..exp.limbs.len() * 32 {
+        if (exp.limbs[i / 32] >> (i % 32)) & 1 == 1 {
+            result = (result * base.clone()).rem_euclid(m);
+        }
+        base = (base.clone() * base).rem_euclid(m);
+    }
+    return Ok(result);
+}
+
+// invmod for --bignum
+fn big_invmod(a: &BigInt, m: &BigInt) -> Result<BigInt, CalcError> {
+    if *m < BigInt::from(1) {
+        return Err(runtime("E0016", &[&2, &"invmod", m]));
+    }
+    // invariant: old_s * a = old_r (mod m)
+    let (mut old_r, mut r) = (a.rem_euclid(m), m.clone());
+    let (mut old_s, mut s) = (BigInt::from(1), BigInt::default());
+    while r != BigInt::default() {
+        let q = old_r.clone() / r.clone();
+        (old_r, r) = (r.clone(), old_r - q.clone() * r);
+        (old_s, s) = (s.clone(), old_s - q * s);
+    }
+    if old_r != BigInt::from(1) && *m != BigInt::from(1) {
+        return Err(runtime("E0020", &[a, m]));
+    }
+    return Ok(old_s.rem_euclid(m));
+}
+
+// x with a * x = 1 (mod m), by the extended Euclidean algorithm
+fn invmod(a: i32, m: i32) -> Result<i32, CalcError> {
+    if m < 1 {
+        return Err(runtime("E0016", &[&2, &"invmod", &m]));
+    }
+    // invariant: old_s * a = old_r (mod m)
+    let (mut old_r, mut r) = ((a as i64).rem_euclid(m as i64), m as i64);
+    let (mut old_s, mut s) = (1i64, 0i64);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_s, s) = (s, old_s - q * s);
+    }
+    if old_r != 1 && m != 1 {
+        return Err(runtime("E0020", &[&a, &m]));
+    }
+    return Ok(old_s.rem_euclid(m as i64) as i32);
+}
+
+// the 64 bits of an IEEE 754 double as an unsigned integer, float_bits(1) is 0x3FF0000000000000
+fn float_bits(x: f64) -> Value {
+    let bits = x.to_bits();
+    return Value::from_big(BigInt { negative: false, limbs: vec![bits as u32, (bits >> 32) as u32] }.trim());
+}
+
+fn bits_to_float(n: &Value) -> Result<f64, CalcError> {
+    let bits = match n {
+        Value::Int(i) if *i >= 0 => Some(*i as u64),
+        Value::Big(b) if !b.negative && b.limbs.len() <= 2 => Some(b.limbs.iter().rev().fold(0, |acc, l| acc << 32 | *l as u64)),
+        _ => None,
+    };
+    return bits.map(f64::from_bits).ok_or_else(|| runtime("E0016", &[&1, &"bits_to_float", n]));
+}
+
+// the next float after `x` in the direction of `y`, `y` itself if they are equal, as in C
+fn nextafter(x: f64, y: f64) -> f64 {
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+    return if x < y { x.next_up() } else if x > y { x.next_down() } else { y };
+}
+
+// argument `i` of `name`, a list of at least one number
+fn numbers(name: &str, i: usize, v: &Value) -> Result<Vec<f64>, CalcError> {
+    match v {
+        Value::List(items) if !items.is_empty() => items.iter().map(Value::float).collect(),
+        _ => Err(runtime("E0016", &[&i, &name, v])),
+    }
+}
+
+// mean and variance of the list v[0] weighted by the list v[1], the weights need not add up to 1
+fn weighted(name: &str, v: &[Value]) -> Result<(f64, f64), CalcError> {
+    let (xs, ws) = (numbers(name, 1, &v[0])?, numbers(name, 2, &v[1])?);
+    if xs.len() != ws.len() {
+        return Err(runtime("E0033", &[&format!("{}({}, {})", name, v[0], v[1])]));
+    }
+    let total: f64 = ws.iter().sum();
+    if ws.iter().any(|w| *w < 0.0) || total <= 0.0 {
+        return Err(runtime("E0016", &[&2, &name, &v[1]]));
+    }
+    let mean = xs.iter().zip(&ws).map(|(x, w)| x * w).sum::<f64>() / total;
+    let var = xs.iter().zip(&ws).map(|(x, w)| w * (x - mean).powi(2)).sum::<f64>() / total;
+    return Ok((mean, var));
+}
+
+// the p-th percentile, 0 to 100, interpolating between the two nearest values
+fn percentile(mut xs: Vec<f64>, p: f64) -> Result<f64, CalcError> {
+    if !(0.0..=100.0).contains(&p) {
+        return Err(runtime("E0016", &[&2, &"percentile", &p]));
+    }
+    xs.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (xs.len() - 1) as f64;
+    let (lo, hi) = (xs[rank.floor() as usize], xs[rank.ceil() as usize]);
+    return Ok(lo + (hi - lo) * rank.fract());
+}
+
+// nearest multiple of `step`, halves round up; exact for integers and decimals, 
+// and an integer result past 32 bits is an error unless --bignum lets it grow
+pub fn roundto(x: &Value, step: &Value) -> Result<Value, CalcError> {
+    let number = |v: &Value| matches!(v, Value::Int(_) | Value::Big(_) | Value::Float(_) | Value::Dec(_));
+    if !number(x) {
+        return Err(runtime("E0016", &[&1, &"roundto", x]));
+    }
+    if !number(step) || step.is_zero()? {
+        return Err(runtime("E0016", &[&2, &"roundto", step]));
+    }
+    if let (&Value::Int(x), &Value::Int(step)) = (x, step) {
+        let step = (step as i64).abs();
+        let down = x as i64 - (x as i64).rem_euclid(step);
+        let nearest = if (x as i64 - down) * 2 >= step { down + step } else { down };
+        return match i32::try_from(nearest) {
+            Ok(i) => Ok(Value::Int(i)),
+            Err(_) if bignum() => Ok(Value::from_big(BigInt::from(nearest))),
+            Err(_) => Err(runtime("E0030", &[&format!("roundto({}, {})", x, step)])),
+        };
+    }
+    let step = if *step < Value::Int(0) { (-step)? } else { step.clone() };
+    let down = (&x.floor_div(&step)? * &step)?;
+    let rest = (x - &down)?;
+    return if (&rest * &Value::Int(2))? >= step { &down + &step } else { Ok(down) };
+}
+
+// whether evaluating the node does any arithmetic worth showing
+pub fn is_reduction(node: &Expr) -> bool {
+    match node.op() {
+        "num" | "ref" | "var" | "()" => false,
+        "neg" => node.children()[0].op() != "num",    // negative literal
+        _ => true,
+    }
+}
+
+// "56 / 7 → 8"
+pub fn reduction(node: &Expr, operands: &[Value], result: &Value) -> String {
+    match (node.op(), operands) {
+        ("neg", [a]) => format!("-({}) → {}", a, result),
+        ("!", [a]) => format!("!{} → {}", a, result),
+        ("~", [a]) => format!("~{} → {}", a, result),
+        ("%", [a]) => format!("{}% → {}", a, result),
+        (op @ ("+" | "-"), [a, _]) if matches!(node.children()[1], Expr::Pct(..)) => format!("{} {} {} → {}", a, op, grouping(node.children()[1]), result),
+        ("&&" | "||", [a]) => format!("{} {} … → {}", a, node.op(), result),
+        ("?:", [c, _]) => format!("{} ? … → {}", c, result),
+        ("=", [a]) => format!("{} = {}", node.children()[0].repr(), a),
+        (op, [a, b]) if !matches!(node, Expr::Call(..)) => format!("{} {} {} → {}", a, op, b, result),
+        (name, args) => {
+            let args = args.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+            format!("{}({}) → {}", name, args.join(", "), result)
+        },
+    }
+}
+
+// `--explain`: reductions in the order one would do them by hand, 
+// innermost parentheses first, then by operator precedence, then left to right
+#[derive(Default)]
+struct Explain {
+    depth: usize,                       // parentheses and calls around the current node
+    steps: Vec<(usize, u8, String)>,
+}
+
+impl EvalObserver for Explain {
+    fn enter(&mut self, node: &Expr) {
+        if matches!(node, Expr::Par(..) | Expr::Call(..)) { self.depth += 1; }
+    }
+    fn exit(&mut self, node: &Expr, operands: &[Value], result: &Value) {
+        if matches!(node, Expr::Par(..) | Expr::Call(..)) { self.depth -= 1; }
+        if is_reduction(node) {
+            self.steps.push((self.depth, level(node), reduction(node, operands, result)));
+        }
+    }
+}
+
+pub fn explain(n: &Expr) -> Result<Vec<String>, CalcError> {
+    let mut ex = Explain::default();
+    n.eval_with(&mut Env::default(), &mut ex)?;
+    ex.steps.sort_by_key(|(depth, prec, _)| (std::cmp::Reverse(*depth), std::cmp::Reverse(*prec)));
+    return Ok(ex.steps.into_iter().map(|(_, _, step)| step).collect());
+}
+
+// parser and evaluator messages, the codes stay the same in every language
+pub const LANGS: &[&str] = &["en", "zh", "es"];
+const ERROR_HEADER: [&str; 3] = ["Error", "错误", "Error"];
+const MESSAGES: &[(&str, [&str; 3])] = &[
+    ("E0001", ["Invalid token '{}' at index {}", "无效的符号 '{}'，位于索引 {}", "Símbolo no válido '{}' en el índice {}"]),
+    ("E0002", ["Invalid cell reference '{}' at index {}", "无效的单元格引用 '{}'，位于索引 {}", "Referencia de celda no válida '{}' en el índice {}"]),
+    ("E0003", ["Extra token after expression: {}", "表达式后有多余的符号: {}", "Símbolo sobrante tras la expresión: {}"]),
+    ("E0004", ["Unexpected end of input", "输入意外结束", "Fin de la entrada inesperado"]),
+    ("E0005", ["Unknown cell reference: {}", "未知的单元格引用: {}", "Referencia de celda desconocida: {}"]),
+    ("E0008", ["Open parenthesis.", "括号未闭合。", "Paréntesis sin cerrar."]),
+    ("E0009", ["Illegal factor: {}", "非法的因子: {}", "Factor no válido: {}"]),
+    ("E0010", ["Circular reference: {}", "循环引用: {}", "Referencia circular: {}"]),
+    ("E0011", ["Cell {} is not numeric: {}", "单元格 {} 不是数字: {}", "La celda {} no es numérica: {}"]),
+    ("E0012", ["Environment variable ${} is not set", "环境变量 ${} 未设置", "La variable de entorno ${} no está definida"]),
+    ("E0013", ["Environment variable ${} is not a number: {}", "环境变量 ${} 不是数字: {}", "La variable de entorno ${} no es un número: {}"]),
+    ("E0014", ["Unknown function: {}", "未知的函数: {}", "Función desconocida: {}"]),
+    ("E0015", ["{}() takes {} argument(s), got {}", "{}() 需要 {} 个参数，实际为 {}", "{}() recibe {} argumento(s), se dieron {}"]),
+    ("E0016", ["Argument {} of {}() out of range: {}", "第 {} 个参数（{}()）超出范围: {}", "El argumento {} de {}() está fuera de rango: {}"]),
+    ("E0017", ["Expected '(' after {}", "{} 后应为 '('", "Se esperaba '(' después de {}"]),
+    ("E0018", ["{}(): {} has no exact root of degree {}", "{}(): {} 没有精确的 {} 次方根", "{}(): {} no tiene raíz exacta de grado {}"]),
+    ("E0019", ["{}({}) does not fit in 32 bits", "{}({}) 超出 32 位整数范围", "{}({}) no cabe en 32 bits"]),
+    ("E0020", ["{} has no inverse modulo {}", "{} 在模 {} 下没有逆元", "{} no tiene inverso módulo {}"]),
+    ("E0021", ["Empty expression", "表达式为空", "Expresión vacía"]),
+    ("E0022", ["Invalid number '{}' at index {}", "无效的数字 '{}'，位于索引 {}", "Número no válido '{}' en el índice {}"]),
+    ("E0023", ["Cannot assign to {}", "无法赋值给 {}", "No se puede asignar a {}"]),
+    ("E0024", ["Unknown variable: {}", "未知的变量: {}", "Variable desconocida: {}"]),
+    ("E0025", ["Misplaced '_' in number '{}' at index {}", "数字 '{}' 中的 '_' 位置不对，位于索引 {}", "'_' mal colocado en el número '{}' en el índice {}"]),
+    ("E0026", ["Expected ':' after the first branch of ?", "? 的第一个分支后应为 ':'", "Se esperaba ':' tras la primera rama de ?"]),
+    ("E0027", ["Operator {} needs integers, got {}", "运算符 {} 需要整数，实际为 {}", "El operador {} necesita enteros, se dio {}"]),
+    ("E0028", ["Shift count out of range: {}", "移位位数超出范围: {}", "Desplazamiento fuera de rango: {}"]),
+    ("E0029", ["Cannot differentiate {}", "无法求导: {}", "No se puede derivar {}"]),
+    ("E0030", ["Integer overflow: {}", "整数溢出: {}", "Desbordamiento de entero: {}"]),
+    ("E0031", ["Division by zero: {}", "除以零: {}", "División por cero: {}"]),
+    ("E0032", ["Incompatible units: {}", "单位不兼容: {}", "Unidades incompatibles: {}"]),
+    ("E0033", ["Shapes do not match: {}", "形状不匹配: {}", "Las dimensiones no coinciden: {}"]),
+    ("E0034", ["Expected a number, got {}", "应为数字，实际为 {}", "Se esperaba un número, se dio {}"]),
+    ("E0035", ["Open bracket.", "括号 [ 未闭合。", "Corchete abierto."]),
+    ("E0036", ["Not defined for dates: {}", "不适用于日期: {}", "No definido para fechas: {}"]),
+    ("E0037", ["Nested more than {} levels deep", "嵌套超过 {} 层", "Anidado a más de {} niveles"]),
+    ("E0038", ["Not a linear or quadratic equation in {}", "不是关于 {} 的一次或二次方程", "No es una ecuación lineal o cuadrática en {}"]),
+    ("E0039", ["Every value of {} is a solution", "{} 取任何值都是解", "Todo valor de {} es solución"]),
+    ("E0040", ["Decimal overflow: {}", "十进制数溢出: {}", "Desbordamiento decimal: {}"]),
+    ("E0041", ["Environment variables are not allowed here: ${}", "此处不允许使用环境变量: ${}", "Las variables de entorno no están permitidas aquí: ${}"]),
+    ("E0042", ["Not defined for polynomials: {}", "不适用于多项式: {}", "No definido para polinomios: {}"]),
+    ("E0043", ["Not a finite number: {} is {}", "不是有限数: {} 为 {}", "No es un número finito: {} es {}"]),
+    ("E0044", ["Not available on this platform: {}", "此平台不支持: {}", "No disponible en esta plataforma: {}"]),
+    ("E0045", ["Decimal exponent out of range: {}, at most {}", "十进制数指数超出范围: {}，最大为 {}", "Exponente decimal fuera de rango: {}, como máximo {}"]),
+];
+
+// index into LANGS, chosen once by `--lang` or the locale
+static LANG: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+pub fn set_lang(lang: &str) {
+    let idx = LANGS.iter().position(|l| *l == lang).unwrap_or(0);
+    LANG.store(idx, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn localize(lang: usize, code: &str, args: &[&dyn std::fmt::Display]) -> String {
+    return format!("{}[{}]: {}", ERROR_HEADER[lang], code, localized_message(lang, code, args));
+}
+
+fn localized_message(lang: usize, code: &str, args: &[&dyn std::fmt::Display]) -> String {
+    let (_, texts) = MESSAGES.iter().find(|(c, _)| *c == code).unwrap();
+    let mut parts = texts[lang].split("{}");
+    let mut text = parts.next().unwrap().to_string();
+    for (part, arg) in parts.zip(args) {
+        text += &format!("{}{}", arg, part);
+    }
+    return text;
+}
+
+// "Error[E0008]: Open parenthesis." in the current language
+pub fn error(code: &str, args: &[&dyn std::fmt::Display]) -> String {
+    localize(LANG.load(std::sync::atomic::Ordering::Relaxed), code, args)
+}
+
+// "Open parenthesis.", the same without its header
+pub fn message(code: &str, args: &[&dyn std::fmt::Display]) -> String {
+    localized_message(LANG.load(std::sync::atomic::Ordering::Relaxed), code, args)
+}
+
+// shown next to the caret under an error's span, for the errors about something missing
+const HINTS: &[(&str, [&str; 3])] = &[
+    ("E0003", ["expected an operator here", "此处应为运算符", "se esperaba un operador aquí"]),
+    ("E0004", ["expected a number, a name or '(' here", "此处应为数字、名称或 '('", "se esperaba un número, un nombre o '(' aquí"]),
+    ("E0008", ["expected ')' here", "此处应为 ')'", "se esperaba ')' aquí"]),
+    ("E0009", ["expected a number, a name or '(' here", "此处应为数字、名称或 '('", "se esperaba un número, un nombre o '(' aquí"]),
+    ("E0017", ["expected '(' here", "此处应为 '('", "se esperaba '(' aquí"]),
+    ("E0026", ["expected ':' here", "此处应为 ':'", "se esperaba ':' aquí"]),
+    ("E0035", ["expected ']' here", "此处应为 ']'", "se esperaba ']' aquí"]),
+];
+
+pub fn hint(code: &str) -> Option<&'static str> {
+    let lang = LANG.load(std::sync::atomic::Ordering::Relaxed);
+    return HINTS.iter().find(|(c, _)| *c == code).map(|(_, texts)| texts[lang]);
+}
+
+// what can go wrong while tokenizing or parsing, displayed as the localized error message
+#[derive(Clone, PartialEq, Debug)]
+pub enum CalcError {
+    InvalidToken(char, usize),
+    InvalidCell(String, usize),
+    InvalidNumber(String, usize),
+    MisplacedSeparator(String, usize),
+    UnexpectedToken(Token),
+    UnbalancedParen,
+    TrailingInput(Token),
+    EmptyInput,
+    UnexpectedEnd,
+    UnknownCell(String),
+    UnknownFunction(String),
+    MissingCallParen(String),
+    ArgumentCount(String, usize, usize),
+    EnvNotSet(String),
+    EnvNotNumber(String, String),
+    EnvDisabled(String),
+    InvalidAssignment(String),
+    MissingColon,
+    UnbalancedBracket,
+    TooDeep(usize),
+    DivisionByZero(String, Span),           // e.g. "1 / 0", at the operator, which evaluating the Bin fills in
+    Runtime(&'static str, Vec<String>),     // from evaluating, its code and the arguments of the message
+}
+
+impl CalcError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CalcError::InvalidToken(..) => "E0001",
+            CalcError::InvalidCell(..) => "E0002",
+            CalcError::TrailingInput(_) => "E0003",
+            CalcError::UnexpectedEnd => "E0004",
+            CalcError::UnknownCell(_) => "E0005",
+            CalcError::UnbalancedParen => "E0008",
+            CalcError::UnexpectedToken(_) => "E0009",
+            CalcError::EnvNotSet(_) => "E0012",
+            CalcError::EnvNotNumber(..) => "E0013",
+            CalcError::EnvDisabled(_) => "E0041",
+            CalcError::UnknownFunction(_) => "E0014",
+            CalcError::ArgumentCount(..) => "E0015",
+            CalcError::MissingCallParen(_) => "E0017",
+            CalcError::EmptyInput => "E0021",
+            CalcError::InvalidNumber(..) => "E0022",
+            CalcError::InvalidAssignment(_) => "E0023",
+            CalcError::MisplacedSeparator(..) => "E0025",
+            CalcError::MissingColon => "E0026",
+            CalcError::UnbalancedBracket => "E0035",
+            CalcError::TooDeep(_) => "E0037",
+            CalcError::DivisionByZero(..) => "E0031",
+            CalcError::Runtime(code, _) => code,
+        }
+    }
+
+    // char index of the offending input, for the errors the tokenizer reports and a division by zero
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            CalcError::InvalidToken(_, idx) | CalcError::InvalidCell(_, idx) | CalcError::InvalidNumber(_, idx) | CalcError::MisplacedSeparator(_, idx) => Some(*idx),
+            CalcError::DivisionByZero(_, span) => Some(span.start),
+            _ => None,
+        }
+    }
+
+    // the operator a division by zero happened at
+    fn at(self, span: Span) -> CalcError {
+        match self {
+            CalcError::DivisionByZero(text, _) => CalcError::DivisionByZero(text, span),
+            e => e,
+        }
+    }
+}
+
+impl CalcError {
+    // the localized message without its "Error[E0008]: " header, for --json and the C interface
+    pub fn message(&self) -> String {
+        match self {
+            CalcError::InvalidToken(c, idx) => message(self.code(), &[c, idx]),
+            CalcError::InvalidCell(name, idx) | CalcError::InvalidNumber(name, idx) | CalcError::MisplacedSeparator(name, idx) => message(self.code(), &[name, idx]),
+            CalcError::UnexpectedToken(t) | CalcError::TrailingInput(t) => message(self.code(), &[&format!("{:?}", t)]),
+            CalcError::UnknownCell(name) | CalcError::UnknownFunction(name) | CalcError::InvalidAssignment(name) | CalcError::MissingCallParen(name) | CalcError::EnvNotSet(name)
+                | CalcError::EnvDisabled(name) => message(self.code(), &[name]),
+            CalcError::ArgumentCount(name, expected, got) => message(self.code(), &[name, expected, got]),
+            CalcError::EnvNotNumber(name, value) => message(self.code(), &[name, value]),
+            CalcError::TooDeep(depth) => message(self.code(), &[depth]),
+            CalcError::DivisionByZero(text, _) => message(self.code(), &[text]),
+            CalcError::Runtime(code, args) => message(code, &args.iter().map(|a| a as &dyn std::fmt::Display).collect::<Vec<_>>()),
+            CalcError::UnbalancedParen | CalcError::UnbalancedBracket | CalcError::EmptyInput | CalcError::UnexpectedEnd | CalcError::MissingColon => message(self.code(), &[]),
+        }
+    }
+}
+
+impl std::fmt::Display for CalcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let lang = LANG.load(std::sync::atomic::Ordering::Relaxed);
+        write!(f, "{}[{}]: {}", ERROR_HEADER[lang], self.code(), self.message())
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+// an error while evaluating, e.g. runtime("E0016", &[&1, &"sqrt", &x]), also for the functions of CalculatorBuilder
+pub fn runtime(code: &'static str, args: &[&dyn std::fmt::Display]) -> CalcError {
+    CalcError::Runtime(code, args.iter().map(|a| a.to_string()).collect())
+}
+
+// operator aliases from the config file, e.g. `alias x = *`, set once at startup, an error after that
+static ALIASES: std::sync::OnceLock<HashMap<String, Token>> = std::sync::OnceLock::new();
+
+pub fn set_aliases(aliases: HashMap<String, Token>) -> Result<(), String> {
+    ALIASES.set(aliases).map_err(|_| "operator aliases are set already".to_string())
+}
+
+fn alias(word: &str) -> Option<Token> {
+    ALIASES.get()?.get(word).cloned()
+}
+
+// operators from the config file, e.g. `infix ** 14 right = ^`, set once at startup, an error after that
+static OPERATORS: std::sync::OnceLock<Vec<Operator>> = std::sync::OnceLock::new();
+
+pub fn set_operators(operators: Vec<Operator>) -> Result<(), String> {
+    OPERATORS.set(operators).map_err(|_| "operators are set already".to_string())
+}
+
+fn operators() -> &'static [Operator] {
+    OPERATORS.get().map_or(&[], |o| o.as_slice())
+}
+
+// what a config file sets
+#[derive(Debug, Default)]
+pub struct Config {
+    pub aliases: HashMap<String, Token>,
+    pub operators: Vec<Operator>,
+}
+
+// one `alias NAME = OP` or `infix SYMBOL PRECEDENCE left|right = OP|FUNCTION` per line, NAME a lowercase word 
+// or a single symbol, blank and `#` lines skipped
+pub fn parse_config(text: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("infix ") {
+            config.operators.push(parse_infix(rest).map_err(|e| format!("line {}: {}", i + 1, e))?);
+            continue;
+        }
+        let Some((name, op)) = line.strip_prefix("alias ").and_then(|rest| rest.split_once('=')) else {
+            return Err(format!("line {}: expected `alias NAME = OP` or `infix SYMBOL PRECEDENCE left|right = OP`, got '{}'", i + 1, line));
+        };
+        let (name, op) = (name.trim(), op.trim());
+        let word = !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase());
+        let symbol = name.chars().count() == 1 && !name.chars().all(|c| c.is_ascii_alphanumeric() || c.is_whitespace());
+        if !word && !symbol {
+            return Err(format!("line {}: alias name must be a lowercase word or one symbol, got '{}'", i + 1, name));
+        }
+        // any binary operator, as the lexer reads it; `mod = %` reads `7 mod 3` as 7 % 3
+        let token = match TokenParser::new(op.to_string()).collect::<Result<Vec<Token>, CalcError>>().as_deref() {
+            Ok([token]) if Op::ALL.iter().any(|o| o.symbol() == op) => token.clone(),
+            _ => return Err(format!("line {}: cannot alias unsupported operator '{}'", i + 1, op)),
+        };
+        config.aliases.insert(name.to_string(), token);
+    }
+    return Ok(config);
+}
+
+// "** 14 right = ^", words separated by spaces since the symbol may have an '=' of its own; 
+// the operator stays allocated for the rest of the program
+fn parse_infix(text: &str) -> Result<Operator, String> {
+    let [symbol, level, assoc, "=", action] = text.split_whitespace().collect::<Vec<&str>>()[..] else {
+        return Err(format!("expected `infix SYMBOL PRECEDENCE left|right = OP`, got 'infix {}'", text));
+    };
+    if !operator_symbol_ok(symbol) {
+        return Err(format!("operator must be a lowercase word or symbols other than ()[],;#$\"._', got '{}'", symbol));
+    }
+    let precedence = level.parse::<u8>().ok().filter(|l| precedence_ok(*l))
+        .ok_or_else(|| format!("precedence must be 4 (like ||) to 14 (like ^), got '{}'", level))?;
+    let assoc = match assoc {
+        "left" => Assoc::Left,
+        "right" => Assoc::Right,
+        _ => return Err(format!("associativity must be left or right, got '{}'", assoc)),
+    };
+    let apply = match Op::ALL.iter().find(|op| op.symbol() == action && **op != Op::Seq) {
+        Some(op) => Infix::Op(*op),
+        None => match builtin(action).filter(|f| f.arity == 2) {
+            Some(f) => Infix::Call(f),
+            None => return Err(format!("'{}' is neither a binary operator nor a function of 2 arguments", action)),
+        },
+    };
+    let symbol: &'static str = Box::leak(symbol.to_string().into_boxed_str());
+    return Ok(Operator { symbol, precedence, assoc, apply });
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    ADD, SUB, 
+    MUL, DIV, FLOORDIV, POW,
+    NUM(Value), LPR, RPR, LBR, RBR,
+    SUP(Value),     // a superscript exponent, x² 
+    REF(String),
+    ENV(String),
+    IDENT(String), COMMA,
+    ASSIGN,
+    EQ, NE, LT, LE, GT, GE,
+    AND, OR, NOT,
+    QUESTION, COLON, SEMI, TO,
+    BITAND, BITOR, XOR, SHL, SHR, INV,
+    PCT,
+    MOD,            // a '%' with an operand after it, the parser tells it from PCT
+    OP(String),     // an operator from the config file or CalculatorBuilder::operator
+}
+
+// `$NAME` and `env("NAME")` read the process environment only when allowed, as the calc program does; 
+// off in a library, where the input may come from someone else
+static ENV_VARS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_env_vars(allow: bool) {
+    ENV_VARS.store(allow, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn env_vars() -> bool {
+    ENV_VARS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// reads its input a character at a time, so a stream can be tokenized without holding it in memory
+pub struct TokenParser {
+    chars: std::iter::Peekable<Box<dyn Iterator<Item = char>>>,
+    pos: usize,                     // index of the next char
+    end: usize,                     // just past the last non-whitespace char read
+    failed: bool,                   // stops after the first error
+    cells: HashMap<String, Value>,  // values visible to REF tokens
+    sheet: bool,                    // A1 is a REF token rather than a name
+    span: Span,                     // span of the last token returned
+    pushed: Option<(Token, Span)>,  // returned again by the next call to next()
+    functions: Vec<&'static Builtin>,   // callable besides BUILTINS, see Calculator
+    depth: usize,                   // of the nested rules in progress
+    locale: Locale,                 // separators read in numbers
+    env_vars: bool,                 // ENV tokens are read rather than an error
+    operators: Vec<Operator>,       // configured binary operators, see Operator
+}
+
+impl TokenParser {
+    pub fn new(input: String) -> Self {
+        TokenParser::from_chars(input.chars().collect::<Vec<char>>().into_iter())
+    }
+
+    pub fn from_chars(chars: impl Iterator<Item = char> + 'static) -> Self {
+        let chars: Box<dyn Iterator<Item = char>> = Box::new(chars);
+        TokenParser {
+            chars: chars.peekable(),
+            pos: 0,
+            end: 0,
+            failed: false,
+            cells: HashMap::new(),
+            sheet: false,
+            span: Span::default(),
+            pushed: None,
+            functions: Vec::new(),
+            depth: 0,
+            locale: locale(),
+            env_vars: env_vars(),
+            operators: operators().to_vec(),
+        }
+    }
+
+    // UTF-8 text, e.g. a pipe; invalid bytes read as U+FFFD
+    pub fn from_reader(reader: impl std::io::Read + 'static) -> Self {
+        let mut bytes = std::io::Read::bytes(std::io::BufReader::new(reader)).map_while(Result::ok).peekable();
+        let chars = std::iter::from_fn(move || {
+            let first = bytes.next()?;
+            let len = match first { 0xF0.. => 4, 0xE0.. => 3, 0xC0.. => 2, _ => 1 };
+            let mut buf = vec![first];
+            while buf.len() < len && bytes.peek().is_some_and(|b| b & 0xC0 == 0x80) {
+                buf.push(bytes.next().unwrap());
+            }
+            return Some(std::str::from_utf8(&buf).map_or('\u{FFFD}', |s| s.chars().next().unwrap()));
+        });
+        TokenParser::from_chars(chars)
+    }
+
+    // a sheet formula, the only input with cell references
+    pub fn with_cells(input: String, cells: HashMap<String, Value>) -> Self {
+        TokenParser { cells, sheet: true, ..TokenParser::new(input) }
+    }
+
+    pub fn with_functions(input: String, functions: Vec<&'static Builtin>) -> Self {
+        TokenParser { functions, ..TokenParser::new(input) }
+    }
+
+    // a registered function first, so it can replace a built-in one
+    fn function(&self, name: &str) -> Option<&'static Builtin> {
+        self.functions.iter().copied().find(|f| f.name == name).or_else(|| builtin(name))
+    }
+
+    // the binary operator `t` stands for, a configured one with the same symbol first
+    fn infix(&self, t: &Token) -> Option<Operator> {
+        let op = match t {
+            Token::OP(symbol) => return self.operators.iter().find(|o| o.symbol == symbol).copied(),
+            Token::ADD => Op::Add,
+            Token::SUB => Op::Sub,
+            Token::MUL => Op::Mul,
+            Token::DIV => Op::Div,
+            Token::FLOORDIV => Op::FloorDiv,
+            Token::POW => Op::Pow,
+            Token::MOD => Op::Mod,
+            Token::EQ => Op::Eq,
+            Token::NE => Op::Ne,
+            Token::LT => Op::Lt,
+            Token::LE => Op::Le,
+            Token::GT => Op::Gt,
+            Token::GE => Op::Ge,
+            Token::AND => Op::And,
+            Token::OR => Op::Or,
+            Token::BITAND => Op::BitAnd,
+            Token::BITOR => Op::BitOr,
+            Token::XOR => Op::Xor,
+            Token::SHL => Op::Shl,
+            Token::SHR => Op::Shr,
+            _ => return None,
+        };
+        return Some(self.operators.iter().find(|o| o.symbol == op.symbol()).copied().unwrap_or(Operator::builtin(op)));
+    }
+
+    // of the last token returned
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // one token of lookahead for the parser
+    fn push_back(&mut self, token: Token) {
+        self.pushed = Some((token, self.span));
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.pos += 1;
+        if !is_space(c) {
+            self.end = self.pos;
+        }
+        return Some(c);
+    }
+
+    // whitespace and `#` comments
+    fn skip_space(&mut self) {
+        loop {
+            match self.peek() {
+                Some('#') => self.skip_comment(false),
+                Some(c) if is_space(c) => { self.bump(); },
+                _ => return,
+            }
+        }
+    }
+
+    // to the end of the line, or past the `*/` of a block comment (to the end of input if there is none); 
+    // a comment counts as whitespace, `end` stays at the last token
+    fn skip_comment(&mut self, block: bool) {
+        let mut last = ' ';
+        while let Some(c) = self.peek() {
+            self.chars.next();
+            self.pos += 1;
+            if (block && last == '*' && c == '/') || (!block && c == '\n') {
+                return;
+            }
+            last = c;
+        }
+    }
+
+    // appends the chars matching `pattern` ('9' for any digit) to `text`, 
+    // or if they do not match puts back what was read and leaves `text` as it was
+    fn take_pattern(&mut self, text: &mut String, pattern: &str) -> bool {
+        let (pos, end) = (self.pos, self.end);
+        let mut read = Vec::new();
+        for p in pattern.chars() {
+            match self.peek() {
+                Some(c) if c == p || (p == '9' && c.is_ascii_digit()) => {
+                    read.push(c);
+                    self.bump();
+                },
+                _ => {
+                    self.put_back(read, pos, end);
+                    return false;
+                },
+            }
+        }
+        text.extend(read);
+        return true;
+    }
+
+    // `read` comes again before the rest of the input, from position `pos`
+    fn put_back(&mut self, read: Vec<char>, pos: usize, end: usize) {
+        if !read.is_empty() {
+            let rest: Box<dyn Iterator<Item = char>> = Box::new(std::iter::empty());
+            let rest = std::mem::replace(&mut self.chars, rest.peekable());
+            let chars: Box<dyn Iterator<Item = char>> = Box::new(read.into_iter().chain(rest));
+            self.chars = chars.peekable();
+        }
+        (self.pos, self.end) = (pos, end);
+    }
+
+    // the longest configured operator of symbols starting with `c`, just read, e.g. `**` rather than two `*`; 
+    // what was read past it is put back
+    fn symbol_operator(&mut self, c: char) -> Option<Token> {
+        let symbols = self.operators.iter().map(|o| o.symbol).filter(|s| s.starts_with(c) && !s.starts_with(|c: char| c.is_ascii_lowercase())).collect::<Vec<&str>>();
+        if symbols.is_empty() {
+            return None;
+        }
+        let mut text = c.to_string();
+        while let Some(next) = self.peek() && symbols.iter().any(|s| s.starts_with(&format!("{}{}", text, next))) {
+            text.push(next);
+            self.bump();
+        }
+        let symbol = symbols.into_iter().filter(|s| text.starts_with(s)).max_by_key(|s| s.len());
+        // none of the chars read is whitespace, so `end` moves back with `pos`
+        let rest = text[symbol.map_or(c.len_utf8(), |s| s.len())..].chars().collect::<Vec<char>>();
+        let (pos, end) = (self.pos - rest.len(), self.end - rest.len());
+        self.put_back(rest, pos, end);
+        return symbol.map(|s| Token::OP(s.to_string()));
+    }
+
+    // appends chars to `text` as long as they match
+    fn take_while(&mut self, text: &mut String, f: impl Fn(char) -> bool) {
+        while let Some(c) = self.peek().filter(|c| f(*c)) {
+            text.push(c);
+            self.bump();
+        }
+    }
+
+    // nothing but whitespace before the first token
+    fn is_blank(&mut self) -> bool {
+        self.skip_space();
+        return self.pushed.is_none() && self.end == 0 && self.peek().is_none();
+    }
+
+    fn fail(&mut self, e: CalcError) -> Option<Result<Token, CalcError>> {
+        self.failed = true;
+        return Some(Err(e));
+    }
+}
+
+// also zero-width spaces and byte order marks, which come along with pasted text
+fn is_space(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}')
+}
+
+// '²' -> '2', '⁻' -> '-'
+fn superscript(c: char) -> Option<char> {
+    match c {
+        '⁰' => Some('0'),
+        '¹' => Some('1'),
+        '²' => Some('2'),
+        '³' => Some('3'),
+        '⁴'..='⁹' => char::from_digit(c as u32 - '⁴' as u32 + 4, 10),
+        '⁻' => Some('-'),
+        _ => None,
+    }
+}
+
+// `_` only between two digits
+fn separators_ok(digits: &str) -> bool {
+    let chars = digits.chars().collect::<Vec<char>>();
+    return chars.iter().enumerate().all(|(i, &c)| {
+        c != '_' || (i > 0 && i + 1 < chars.len() && chars[i - 1].is_ascii_alphanumeric() && chars[i + 1].is_ascii_alphanumeric())
+    });
+}
+
+// 1.234.567 where the locale groups with '.', three digits to a group after the first
+fn groups_ok(text: &str, locale: Locale) -> bool {
+    let whole = text.split(locale.decimal).next().unwrap();
+    let Some(group) = locale.group.filter(|g| whole.contains(*g)) else {
+        return true;
+    };
+    let groups = whole.split(group).collect::<Vec<&str>>();
+    return (1..=3).contains(&groups[0].len()) && groups[1..].iter().all(|g| g.len() == 3);
+}
+
+impl Iterator for TokenParser {
+    type Item = Result<Token, CalcError>;
+
+    // stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((token, span)) = self.pushed.take() {
+            self.span = span;
+            return Some(Ok(token));
+        }
+        self.skip_space();
+        let (start, end) = (self.pos, self.end);
+        let c = match self.peek() {
+            Some(c) if !self.failed => c,
+            _ => {
+                // just past the last character, where a missing operand or ')' would go
+                self.span = Span { start: self.end, end: self.end + 1 };
+                return None;
+            },
+        };
+        self.bump();
+        if let Some(token) = self.symbol_operator(c) {
+            self.span = Span { start, end: self.end };
+            return Some(Ok(token));
+        }
+
+        let token = match c {
+            '+' => Token::ADD, 
+            '-' if self.peek() == Some('>') => {
+                self.bump();
+                Token::TO
+            },
+            '-' => Token::SUB, 
+            '*' => Token::MUL,
+            '/' if self.peek() == Some('*') => {
+                self.end = end;
+                self.skip_comment(true);
+                return self.next();
+            },
+            '/' if self.peek() == Some('/') => {
+                self.bump();
+                Token::FLOORDIV
+            },
+            '/' => Token::DIV, 
+            '(' => Token::LPR, 
+            ')' => Token::RPR, 
+            '[' => Token::LBR, 
+            ']' => Token::RBR, 
+            ',' => Token::COMMA,
+            // two-character operators: == != <= >= && || << >> ^^
+            '=' | '!' | '<' | '>' if self.peek() == Some('=') => {
+                self.bump();
+                match c { '=' => Token::EQ, '!' => Token::NE, '<' => Token::LE, _ => Token::GE }
+            },
+            '&' | '|' | '<' | '>' | '^' if self.peek() == Some(c) => {
+                self.bump();
+                match c { '&' => Token::AND, '|' => Token::OR, '<' => Token::SHL, '>' => Token::SHR, _ => Token::XOR }
+            },
+            '^' => Token::POW,
+            '&' => Token::BITAND,
+            '|' => Token::BITOR,
+            '~' => Token::INV,
+            '%' => Token::PCT,
+            '=' => Token::ASSIGN, 
+            '?' => Token::QUESTION,
+            ':' => Token::COLON,
+            ';' => Token::SEMI,
+            '!' => Token::NOT,
+            '<' => Token::LT,
+            '>' => Token::GT,
+            '0' if matches!(self.peek(), Some('x' | 'o' | 'b')) => {
+                // 0xFF, 0o17 or 0b1010
+                let mut text = String::from("0");
+                let radix = match self.peek() { Some('x') => 16, Some('o') => 8, _ => 2 };
+                self.take_while(&mut text, |c| c.is_ascii_alphanumeric() || c == '_');
+                if !separators_ok(&text[2..]) {
+                    return self.fail(CalcError::MisplacedSeparator(text, start));
+                }
+                match Value::parse_radix(&text[2..].replace('_', ""), radix) {
+                    Some(value) => Token::NUM(value),
+                    None => return self.fail(CalcError::InvalidNumber(text, start)),
+                }
+            },
+            '0'..='9' | '.' => {
+                // 42, or a decimal like 2.5 or .5, digits may be grouped as 1_000_000, or 1.000.000 in German and 1,000,000 in English
+                let Locale { decimal, group } = self.locale;
+                let point = decimal == '.' || group == Some('.');
+                let mut text = c.to_string();
+                loop {
+                    self.skip_space();
+                    match self.peek() {
+                        Some(next) if next.is_ascii_digit() || next == '_' || (next == '.' && point && !text.contains(decimal)) => {
+                            text.push(next);
+                            self.bump();
+                        },
+                        // a decimal comma only with a digit right after it, so `max(1, 5)` keeps two arguments
+                        Some(',') if decimal == ',' && !text.contains(',') => {
+                            if !self.take_pattern(&mut text, ",9") {
+                                break;
+                            }
+                        },
+                        // a grouping comma, 1,234 in English, with three digits right after it, so `max(1, 234)` keeps two arguments
+                        Some(',') if group == Some(',') && !text.contains(decimal) => {
+                            if !self.take_pattern(&mut text, ",999") {
+                                break;
+                            }
+                        },
+                        _ => break,
+                    }
+                }
+                // a date, 2024-03-01 or 2024-03-01T12:30, when written without spaces
+                let plain = text.len() == 4 && self.pos == start + 4 && self.end == self.pos && text.chars().all(|c| c.is_ascii_digit());
+                if plain && self.take_pattern(&mut text, "-99-99") {
+                    if self.take_pattern(&mut text, "T99:99") {
+                        self.take_pattern(&mut text, ":99");
+                    }
+                    match parse_date(&text) {
+                        Some(t) => Token::NUM(Value::Date(t)),
+                        None => return self.fail(CalcError::InvalidNumber(text, start)),
+                    }
+                } else {
+                    if !separators_ok(&text) {
+                        return self.fail(CalcError::MisplacedSeparator(text, start));
+                    }
+                    if !groups_ok(&text, self.locale) {
+                        return self.fail(CalcError::InvalidNumber(text, start));
+                    }
+                    let mut digits = text.chars().filter(|&c| c != '_' && Some(c) != group).map(|c| if c == decimal { '.' } else { c }).collect::<String>();
+                    // 1e-16 or 2.5E3, a float, the exponent right after the digits as floats are printed
+                    let mut exponent = String::new();
+                    if self.pos == self.end && ["e9", "e-9", "e+9", "E9", "E-9", "E+9"].iter().any(|p| self.take_pattern(&mut exponent, p)) {
+                        self.take_while(&mut exponent, |c| c.is_ascii_digit());
+                        text += &exponent;
+                        digits = format!("{}{}", digits, exponent.to_lowercase());
+                    }
+                    let value = if !exponent.is_empty() {
+                        digits.parse().ok().map(Value::Float)
+                    } else if digits.contains('.') {
+                        Value::parse_fraction(&digits)
+                    } else {
+                        Value::parse_int(&digits)
+                    };
+                    match value {
+                        Some(value) => Token::NUM(value),
+                        None => return self.fail(CalcError::InvalidNumber(text, start)),
+                    }
+                }
+            }, 
+            'A'..='Z' => {
+                // a name like Total or X, a Roman numeral like MCMXCIV is one too and reads as its value unless 
+                // it is assigned; in a sheet a cell reference, e.g. A1, AB12
+                let mut word = c.to_string();
+                self.take_while(&mut word, |c| c.is_ascii_alphanumeric() || c == '_');
+                let letters = word.chars().take_while(|c| c.is_ascii_uppercase()).count();
+                let cell = letters < word.len() && word[letters..].chars().all(|c| c.is_ascii_digit());
+                if self.sheet && cell {
+                    if parse_cell_name(&word).is_none() {
+                        return self.fail(CalcError::InvalidCell(word, start));
+                    }
+                    Token::REF(word)
+                } else if self.operators.iter().any(|o| o.symbol == word) {
+                    Token::OP(word)
+                } else {
+                    Token::IDENT(word)
+                }
+            }, 
+            'a'..='z' | '_' => {
+                // function name, e.g. roundto, or a word alias like `x` in `2x3`
+                let mut word = c.to_string();
+                self.take_while(&mut word, |c| c.is_ascii_lowercase());
+                if let Some(token) = alias(&word) {
+                    token
+                } else {
+                    self.take_while(&mut word, |c| c.is_ascii_alphanumeric() || c == '_');
+                    if self.operators.iter().any(|o| o.symbol == word) {
+                        Token::OP(word)
+                    } else if word == "env" && self.take_pattern(&mut word, "(\"") {
+                        // env("COLUMNS"), the same as $COLUMNS
+                        let mut name = String::new();
+                        self.take_while(&mut name, |c| c.is_ascii_alphanumeric() || c == '_');
+                        if name.is_empty() || !self.take_pattern(&mut name, "\")") {
+                            return self.fail(CalcError::InvalidToken('"', start + 4));
+                        }
+                        name.truncate(name.len() - 2);
+                        Token::ENV(name)
+                    } else if word == "xor" { Token::XOR } else { Token::IDENT(word) }
+                }
+            }, 
+            '$' => {
+                // environment variable, e.g. $COLUMNS
+                let mut name = String::new();
+                self.take_while(&mut name, |c| c.is_ascii_alphanumeric() || c == '_');
+                if name.is_empty() {
+                    return self.fail(CalcError::InvalidToken(c, start));
+                }
+                Token::ENV(name)
+            }, 
+            c if superscript(c).is_some() => {
+                // x², 10⁻³
+                let mut digits = superscript(c).unwrap().to_string();
+                while let Some(d) = self.peek().and_then(superscript).filter(|d| d.is_ascii_digit()) {
+                    digits.push(d);
+                    self.bump();
+                }
+                match Value::parse_int(&digits) {
+                    Some(value) => Token::SUP(value),
+                    None => return self.fail(CalcError::InvalidNumber(digits, start)),
+                }
+            },
+            // pasted from documents, unless the config gives them another meaning
+            _ => match (alias(&c.to_string()), c) {
+                (Some(token), _) => token,
+                (None, '×' | '·' | '⋅') => Token::MUL,
+                (None, '÷' | '∕') => Token::DIV,
+                (None, '−' | '–') => Token::SUB,
+                (None, _) => return self.fail(CalcError::InvalidToken(c, start)),
+            },
+        };
+        self.span = Span { start, end: self.end };
+        return Some(Ok(token));
+    }
+}
+
+// cell references in spreadsheet formulas
+// "AB12" -> (row 11, col 27), both 0-based
+pub fn parse_cell_name(name: &str) -> Option<(usize, usize)> {
+    let letters = name.chars().take_while(|c| c.is_ascii_uppercase()).count();
+    let (col_part, row_part) = name.split_at(letters);
+    if col_part.is_empty() || row_part.is_empty() || !row_part.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let col = col_part.chars().fold(0, |acc, c| acc * 26 + (c as usize - 'A' as usize + 1));
+    let row: usize = row_part.parse().ok()?;
+    if row == 0 {
+        return None;
+    }
+    return Some((row - 1, col - 1));
+}
+
+pub fn cell_name(row: usize, col: usize) -> String {
+    let mut letters = Vec::new();
+    let mut c = col + 1;
+    while c > 0 {
+        letters.push((b'A' + ((c - 1) % 26) as u8) as char);
+        c = (c - 1) / 26;
+    }
+    letters.iter().rev().collect::<String>() + &(row + 1).to_string()
+}
+
+// main entry point
+pub fn evaluate(p: TokenParser) -> Result<Expr, CalcError> {
+    return evaluate_spanned(p).map_err(|(e, _)| e);
+}
+
+// errors come with the span of the input they point at, the offending token 
+// or the end of input for what is missing there
+pub fn evaluate_spanned(mut p: TokenParser) -> Result<Expr, (CalcError, Span)> {
+    if p.is_blank() {
+        return Err((CalcError::EmptyInput, Span { start: 0, end: 1 }));
+    }
+    let at = |e: CalcError, p: &TokenParser| {
+        let span = match &e {
+            CalcError::InvalidCell(text, idx) | CalcError::InvalidNumber(text, idx) | CalcError::MisplacedSeparator(text, idx) => {
+                Span { start: *idx, end: idx + text.chars().count() }
+            },
+            e => e.position().map_or(p.span, |idx| Span { start: idx, end: idx + 1 }),
+        };
+        return (e, span);
+    };
+    let (n, t) = parse_seq(&mut p).map_err(|e| at(e, &p))?;
+    if let Some(t) = t {
+        return Err(at(CalcError::TrailingInput(t), &p));
+    }
+    if depth(&n) > max_depth().saturating_mul(CHAIN_LINKS) {
+        return Err((CalcError::TooDeep(max_depth()), n.span()));
+    }
+    return Ok(*n);
+}
+
+// `--max-depth`: deeper input is a parse error rather than a stack overflow; 
+// the default leaves room to spare on a 2MB thread stack in a debug build
+static MAX_DEPTH: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(100);
+
+pub fn set_max_depth(depth: usize) {
+    MAX_DEPTH.store(depth, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn max_depth() -> usize {
+    MAX_DEPTH.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// links of a left-assoc chain, 1 + 2 + ..., that count as one level: the parser reads a chain in a loop 
+// and the evaluation walks it in one, but lint, the output formats and dropping the tree recurse into it
+const CHAIN_LINKS: usize = 15;
+
+// the nesting of the tree in 1/CHAIN_LINKS levels, with a stack of its own so any tree can be measured
+fn depth(n: &Expr) -> usize {
+    let mut deepest = 0;
+    let mut stack = vec![(n, CHAIN_LINKS)];
+    while let Some((n, d)) = stack.pop() {
+        deepest = deepest.max(d);
+        match n {
+            Expr::Bin(_, l, r, _) => stack.extend([(&**l, d + 1), (&**r, d + CHAIN_LINKS)]),
+            n => stack.extend(n.children().into_iter().map(|c| (c, d + CHAIN_LINKS))),
+        }
+    }
+    return deepest;
+}
+
+// <a>; <a>; ..., evaluated left to right, one trailing ';' is allowed
+fn parse_seq(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let (mut n0, mut t) = parse_a(p)?;
+    let mut links = 0;
+    while t == Some(Token::SEMI) {
+        let span = p.span;
+        match p.next() {
+            None => return Ok((n0, None)),
+            Some(next) => p.push_back(next?),
+        }
+        let (n1, tn) = parse_a(p)?;
+        link(&mut links)?;
+        n0 = Box::new(Expr::Bin(Op::Seq, n0, n1, span));
+        t = tn;
+    }
+    return Ok((n0, t));
+}
+
+// <name> = <a>, <to>
+fn parse_a(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let (n, t) = parse_to(p)?;
+    if t != Some(Token::ASSIGN) {
+        return Ok((n, t));
+    }
+    if n.op() != "var" {
+        p.span = n.span();
+        return Err(CalcError::InvalidAssignment(n.repr()));
+    }
+    let (value, t) = nested(p, parse_a)?;
+    return Ok((Box::new(Expr::Assign(n, value)), t));
+}
+
+// <q> -> <q>, a quantity in other units, 100 mph -> km/h
+fn parse_to(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let (mut n0, mut t) = parse_q(p)?;
+    let mut links = 0;
+    while t == Some(Token::TO) {
+        let span = p.span;
+        let (n1, tn) = parse_q(p)?;
+        link(&mut links)?;
+        n0 = Box::new(Expr::Bin(Op::Convert, n0, n1, span));
+        t = tn;
+    }
+    return Ok((n0, t));
+}
+
+// what `parse` returns, the tree can be matched on directly
+pub type Ast = Expr;
+
+pub fn parse(input: &str) -> Result<Ast, CalcError> {
+    return evaluate(TokenParser::new(input.to_string()));
+}
+
+pub fn parse_spanned(input: &str) -> Result<Ast, (CalcError, Span)> {
+    return evaluate_spanned(TokenParser::new(input.to_string()));
+}
+
+// `calc solve`: "2*x + 3 = 11" as 2*x + 3 - (11), without '=' the equation is `input` = 0; 
+// spans are those of the whole input
+pub fn parse_equation(input: &str) -> Result<Ast, (CalcError, Span)> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let eq = (0..chars.len()).find(|&i| {
+        chars[i] == '=' && !matches!(chars.get(i + 1), Some('=')) && (i == 0 || !matches!(chars[i - 1], '=' | '<' | '>' | '!'))
+    });
+    let Some(eq) = eq else { return parse_spanned(input); };
+    let lhs = parse_spanned(&chars[..eq].iter().collect::<String>())?;
+    let rhs = parse_spanned(&(" ".repeat(eq + 1) + &chars[eq + 1..].iter().collect::<String>()))?;
+    let span = rhs.span();
+    return Ok(Expr::Bin(Op::Sub, Box::new(lhs), Box::new(Expr::Par(Box::new(rhs), span)), Span { start: eq, end: eq + 1 }));
+}
+
+// every syntax error at once rather than only the first: after each one the input is repaired, 
+// the offending token dropped or a missing operand, ')' or ':' supplied, and parsed again
+pub fn parse_recovering(input: &str) -> Result<Ast, Vec<(CalcError, Span)>> {
+    const MAX_ERRORS: usize = 10;
+    let mut text = input.chars().collect::<Vec<char>>();
+    let mut inserted: Vec<(usize, usize)> = Vec::new();    // (index in the repaired text, length)
+    let mut errors = Vec::new();
+    // an index in the repaired text as one in the input
+    let original = |inserted: &[(usize, usize)], mut idx: usize| {
+        for &(at, len) in inserted.iter().rev() {
+            if idx >= at + len {
+                idx -= len;
+            } else if idx >= at {
+                idx = at;
+            }
+        }
+        return idx;
+    };
+    loop {
+        let (e, span) = match evaluate_spanned(TokenParser::new(text.iter().collect())) {
+            Ok(n) if errors.is_empty() => return Ok(n),
+            Ok(_) => return Err(errors),
+            Err(error) => error,
+        };
+        let (start, end) = (span.start.min(text.len()), span.end.min(text.len()));
+        let before = text.clone();
+        // overwritten in place, so later indexes stay the same
+        let replace = |text: &mut Vec<char>, start: usize, end: usize, with: &str| {
+            for (i, c) in text[start..end].iter_mut().enumerate() {
+                *c = with.chars().nth(i).unwrap_or(' ');
+            }
+        };
+        let mut insert = |text: &mut Vec<char>, with: &str| {
+            text.splice(start..start, with.chars());
+            inserted.push((start, with.chars().count()));
+        };
+        match &e {
+            CalcError::InvalidToken(..) | CalcError::UnexpectedToken(_) | CalcError::TrailingInput(_) | CalcError::UnknownFunction(_) => {
+                replace(&mut text, start, end, "");
+            },
+            CalcError::InvalidNumber(..) | CalcError::MisplacedSeparator(..) | CalcError::InvalidCell(..) | CalcError::UnknownCell(_)
+                | CalcError::EnvNotSet(_) | CalcError::EnvNotNumber(..) | CalcError::EnvDisabled(_) | CalcError::ArgumentCount(..) => {
+                replace(&mut text, start, end, "0");
+            },
+            // `sqrt 4` is read as `4`
+            CalcError::MissingCallParen(name) => {
+                let head = text[..start].iter().collect::<String>();
+                if let Some(at) = head.rfind(name.as_str()) {
+                    let at = head[..at].chars().count();
+                    replace(&mut text, at, at + name.chars().count(), "");
+                }
+            },
+            CalcError::UnexpectedEnd => insert(&mut text, " 0"),
+            CalcError::UnbalancedParen => insert(&mut text, ")"),
+            CalcError::UnbalancedBracket => insert(&mut text, "]"),
+            CalcError::MissingColon if text[start..].iter().all(|c| c.is_whitespace()) => insert(&mut text, " : 0"),
+            CalcError::MissingColon => insert(&mut text, ":"),
+            CalcError::EmptyInput | CalcError::InvalidAssignment(_) | CalcError::TooDeep(_) | CalcError::DivisionByZero(..) | CalcError::Runtime(..) => {},
+        }
+        let from = original(&inserted, start);
+        let to = original(&inserted, end).max(from + 1);
+        errors.push((e, Span { start: from, end: to }));
+        if text == before || errors.len() == MAX_ERRORS {
+            return Err(errors);
+        }
+    }
+}
+
+// a syntax error as `validate` reports it, the message without its "Error[E0008]: " prefix
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+// `--check`: every syntax error of `input` without evaluating it, so nothing can fail at run time here
+pub fn validate(input: &str) -> Result<(), Vec<Diagnostic>> {
+    let errors = parse_recovering(input).err().unwrap_or_default();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    return Err(errors.into_iter().map(|(e, span)| {
+        Diagnostic { code: e.code(), message: e.message(), span }
+    }).collect());
+}
+
+// evaluation errors, e.g. an argument out of range, still panic
+pub fn eval(input: &str) -> Result<Value, CalcError> {
+    return parse(input)?.eval();
+}
+
+// the next token, None at the end of input
+fn next_token(p: &mut TokenParser) -> Result<Option<Token>, CalcError> {
+    return p.next().transpose();
+}
+
+// <o> ? <q> : <q>, right-assoc so a ? b : c ? d : e is a ? b : (c ? d : e)
+fn parse_q(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let (cond, t) = parse_binary(p, 0)?;
+    if t != Some(Token::QUESTION) {
+        return Ok((cond, t));
+    }
+    let (a, t) = nested(p, parse_q)?;
+    if t != Some(Token::COLON) {
+        return Err(CalcError::MissingColon);
+    }
+    let (b, t) = nested(p, parse_q)?;
+    return Ok((Box::new(Expr::Cond(cond, a, b)), t));
+}
+
+// a parsed node and the token after it
+type Parsed = Result<(Box<Expr>, Option<Token>), CalcError>;
+
+// <u> op <u> op ..., the binary operators by the precedence table, || (4) the loosest and ^ (14) the tightest; 
+// stops at one looser than `min`. The right operand of a left-assoc operator only takes tighter ones, 
+// so 1 - 2 - 3 is (1 - 2) - 3, that of a right-assoc one also its own, 2^3^2 is 2^(3^2)
+fn parse_binary(p: &mut TokenParser, min: u8) -> Parsed {
+    let (mut n0, mut t) = parse_u(p)?;
+    let mut links = 0;
+    while let Some(op) = t.as_ref().and_then(|t| p.infix(t)).filter(|op| op.precedence >= min) {
+        let span = p.span;
+        let tighter = if op.assoc == Assoc::Right { op.precedence } else { op.precedence + 1 };
+        let (n1, tn) = nested(p, |p| parse_binary(p, tighter))?;
+        link(&mut links)?;
+        n0 = op.node(n0, n1, span);
+        t = tn;
+    }
+    return Ok((n0, t));
+}
+
+// -<u>, !<u>, ~<u>, <p>; binds looser than ^ so -2^2 is -(2^2)
+// every level of parentheses, brackets, calls and prefix operators goes through here once
+fn parse_u(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    return nested(p, parse_unary);
+}
+
+// a rule that recurses, one level deeper; with the right operands, ternary branches and assigned values 
+// this counts every recursion of the parser, left-assoc chains are read in a loop and do not nest
+fn nested(p: &mut TokenParser, rule: impl FnOnce(&mut TokenParser) -> Parsed) -> Parsed {
+    if p.depth == max_depth() {
+        return Err(CalcError::TooDeep(max_depth()));
+    }
+    p.depth += 1;
+    let parsed = rule(p);
+    p.depth -= 1;
+    return parsed;
+}
+
+// one more link of a chain; one too deep on its own is cut short rather than built in full 
+// only to be rejected, dropping it would recurse as deep
+fn link(links: &mut usize) -> Result<(), CalcError> {
+    *links += 1;
+    if *links > max_depth().saturating_mul(CHAIN_LINKS) {
+        return Err(CalcError::TooDeep(max_depth()));
+    }
+    return Ok(());
+}
+
+fn parse_unary(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let Some(t0) = next_token(p)? else { return Err(CalcError::UnexpectedEnd); };
+    if t0 != Token::SUB && t0 != Token::NOT && t0 != Token::INV {
+        p.push_back(t0);
+        return parse_p(p);
+    }
+    let span = p.span;
+    let (n, t) = parse_binary(p, precedence("neg") + 1)?;
+    let span = span.to(n.span());
+    if t0 == Token::NOT {
+        return Ok((Box::new(Expr::Not(n, span)), t));
+    }
+    if t0 == Token::INV {
+        return Ok((Box::new(Expr::Inv(n, span)), t));
+    }
+    return Ok((Box::new(Expr::Neg(n, span)), t));
+}
+
+// <f>%, <f>²; ^ is one of the operators of parse_binary, and so is % with an operand after it, 7 % 3
+fn parse_p(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let (mut base, mut t1) = parse_f(p)?;
+    if t1 == Some(Token::PCT) {
+        // a look at the token after it, put back for whichever rule reads on
+        let at = p.span;
+        let t2 = next_token(p)?;
+        let operand = matches!(t2, Some(Token::NUM(_) | Token::IDENT(_) | Token::REF(_) | Token::ENV(_) | Token::LPR | Token::LBR));
+        if let Some(t2) = t2 {
+            p.push_back(t2);
+        }
+        p.span = at;
+        if operand {
+            return Ok((base, Some(Token::MOD)));
+        }
+        let span = base.span().to(p.span);
+        base = Box::new(Expr::Pct(base, span));
+        t1 = next_token(p)?;
+    }
+    if let Some(Token::SUP(exp)) = t1 {
+        base = Box::new(Expr::Bin(Op::Pow, base, Box::new(Expr::Num(exp, p.span)), p.span));
+        t1 = next_token(p)?;
+    }
+    return Ok((base, t1));
+}
+
+// num, -<num>, (<expr>)
+fn parse_f(p: &mut TokenParser) -> Result<(Box<Expr>, Option<Token>), CalcError> {
+    let t0 = next_token(p)?.ok_or(CalcError::UnexpectedEnd)?;
+    let span = p.span;
+    match t0 {
+        Token::NUM(num) => {
+            let n = Box::new(Expr::Num(num, span));
+            // 3 km or 9.8 m/s^2, a unit right after a number multiplies it, its power included, and so does x in 3x^2
+            let t1 = next_token(p)?;
+            if let Some(Token::IDENT(name)) = &t1 && (unit(name).is_some() || name == "x") {
+                p.push_back(t1.unwrap());
+                // no operator of its own, the unit stands for one
+                let (u, tn) = parse_binary(p, precedence("^"))?;
+                let span = u.span();
+                return Ok((Box::new(Expr::Bin(Op::Mul, n, u, span)), tn));
+            }
+            return Ok((n, t1));
+        }
+        Token::REF(name) => {
+            let Some(value) = p.cells.get(&name).cloned() else { return Err(CalcError::UnknownCell(name)); };
+            return Ok((Box::new(Expr::Ref(name, value, span)), next_token(p)?));
+        }
+        Token::IDENT(name) => return parse_call(p, name, span),
+        Token::ENV(name) => return parse_env(p, name, span),
+        Token::LBR => return parse_list(p, span),
+        Token::LPR => {
+            let (expr, t1) = parse_q(p)?;
+            match t1 {
+                Some(Token::RPR) => {
+                    return Ok((Box::new(Expr::Par(expr, span.to(p.span))), next_token(p)?));
+                },
+                _ => return Err(CalcError::UnbalancedParen),
+            }
+        }
+        _ => {
+            return Err(CalcError::UnexpectedToken(t0));
+        }
+    }
+}
+
+// <name>, <name>(<q>, ...)
+fn parse_call(p: &mut TokenParser, name: String, span: Span) -> Parsed {
+    let t1 = next_token(p)?;
+    if t1 != Some(Token::LPR) {
+        if p.function(&name).is_some() {
+            return Err(CalcError::MissingCallParen(name));
+        }
+        return Ok((Box::new(Expr::Var(name, span)), t1));
+    }
+    let Some(f) = p.function(&name) else {
+        p.span = span;
+        return Err(CalcError::UnknownFunction(name));
+    };
+    let mut args = Vec::new();
+    // rand(), no arguments
+    match next_token(p)? {
+        Some(Token::RPR) => {},
+        t => {
+            if let Some(t) = t {
+                p.push_back(t);
+            }
+            loop {
+                let (arg, t) = parse_q(p)?;
+                args.push(*arg);
+                match t {
+                    Some(Token::COMMA) => continue,
+                    Some(Token::RPR) => break,
+                    _ => return Err(CalcError::UnbalancedParen),
+                }
+            }
+        },
+    }
+    // poly(1, -3, 2) is poly([1, -3, 2])
+    if f.name == "poly" && args.len() > 1 {
+        args = vec![Expr::List(args, span.to(p.span))];
+    }
+    if args.len() != f.arity {
+        p.span = span.to(p.span);
+        return Err(CalcError::ArgumentCount(name, f.arity, args.len()));
+    }
+    return Ok((Box::new(Expr::Call(f, args, span.to(p.span))), next_token(p)?));
+}
+
+// $NAME, read when parsed
+fn parse_env(p: &mut TokenParser, name: String, span: Span) -> Parsed {
+    if !p.env_vars {
+        return Err(CalcError::EnvDisabled(name));
+    }
+    let Ok(value) = std::env::var(&name) else { return Err(CalcError::EnvNotSet(name)); };
+    let Some(value) = Value::parse(&value) else { return Err(CalcError::EnvNotNumber(name, value)); };
+    return Ok((Box::new(Expr::Ref(format!("${}", name), value, span)), next_token(p)?));
+}
+
+// [<q>, ...]
+fn parse_list(p: &mut TokenParser, span: Span) -> Parsed {
+    let mut items = Vec::new();
+    let mut t = next_token(p)?;
+    if t != Some(Token::RBR) {
+        p.push_back(t.ok_or(CalcError::UnbalancedBracket)?);
+        loop {
+            let (item, tn) = parse_q(p)?;
+            items.push(*item);
+            if tn != Some(Token::COMMA) {
+                t = tn;
+                break;
+            }
+        }
+    }
+    if t != Some(Token::RBR) {
+        return Err(CalcError::UnbalancedBracket);
+    }
+    return Ok((Box::new(Expr::List(items, span.to(p.span))), next_token(p)?));
+}
+
+
+// Roman numerals in canonical form, 1 to 3999
+const ROMAN: &[(i32, &str)] = &[
+    (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+    (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+];
+
+pub fn to_roman(mut n: i32) -> Option<String> {
+    if !(1..=3999).contains(&n) {
+        return None;
+    }
+    let mut out = String::new();
+    for (value, digits) in ROMAN {
+        while n >= *value {
+            out += digits;
+            n -= value;
+        }
+    }
+    return Some(out);
+}
+
+// only canonical numerals are accepted, so "IIII" or "VX" stay invalid
+pub fn parse_roman(s: &str) -> Option<i32> {
+    let mut rest = s;
+    let mut n = 0;
+    for (value, digits) in ROMAN {
+        while let Some(r) = rest.strip_prefix(digits) {
+            n += value;
+            rest = r;
+        }
+    }
+    if !rest.is_empty() || to_roman(n).as_deref() != Some(s) {
+        return None;
+    }
+    return Some(n);
+}
+
+// `--trace`: one line per evaluated node, innermost first
+pub struct Trace {
+    pub source: Vec<char>,
+    pub out: Box<dyn std::io::Write>,
+}
+
+impl EvalObserver for Trace {
+    fn exit(&mut self, node: &Expr, operands: &[Value], result: &Value) {
+        let span = node.span();
+        let text: String = self.source[span.start..span.end].iter().collect();
+        let operands = operands.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", ");
+        writeln!(self.out, "{:>3}..{:<3} {:<4} [{}] => {} | {}", span.start, span.end, node.op(), operands, result, text)
+            .expect("Error: Cannot write trace");
+    }
+}
+
+// `--trace-json`: the evaluation as nested
+// {"op", "text", "span", "operands", "result", "duration_ns", "children"} objects, 
+// the nodes an error stopped with "result": null and the "error" {"code", "message"}
+#[derive(Default)]
+pub struct JsonTrace {
+    pub source: Vec<char>,
+    stack: Vec<(std::time::Instant, &'static str, Span, Vec<String>)>,    // nodes being evaluated, with their finished children
+    pub root: String,
+}
+
+impl JsonTrace {
+    pub fn new(source: &str) -> Self {
+        return JsonTrace { source: source.chars().collect(), ..JsonTrace::default() };
+    }
+
+    // the nodes still being evaluated when `e` stopped the evaluation, so the trace shows where it failed
+    pub fn fail(&mut self, e: &CalcError) {
+        let error = format!("{{\"code\": {}, \"message\": {}}}", json_string(e.code()), json_string(&e.message()));
+        while !self.stack.is_empty() {
+            self.close("", "null", &format!(", \"error\": {}", error));
+        }
+    }
+
+    fn close(&mut self, operands: &str, result: &str, error: &str) {
+        let (started, op, span, children) = self.stack.pop().unwrap();
+        let text: String = self.source[span.start..span.end].iter().collect();
+        let json = format!(
+            "{{\"op\": {}, \"text\": {}, \"span\": [{}, {}], \"operands\": [{}], \"result\": {}{}, \"duration_ns\": {}, \"children\": [{}]}}",
+            json_string(op), json_string(&text), span.start, span.end, operands, result, error, started.elapsed().as_nanos(), children.join(", "),
+        );
+        match self.stack.last_mut() {
+            Some((_, _, _, siblings)) => siblings.push(json),
+            None => self.root = json,
+        }
+    }
+}
+
+impl EvalObserver for JsonTrace {
+    fn enter(&mut self, node: &Expr) {
+        self.stack.push((std::time::Instant::now(), node.op(), node.span(), Vec::new()));
+    }
+    fn exit(&mut self, _node: &Expr, operands: &[Value], result: &Value) {
+        let operands = operands.iter().map(json_number).collect::<Vec<String>>().join(", ");
+        self.close(&operands, &json_number(result), "");
+    }
+}
+
+// JSON has no infinity or NaN
+pub fn json_number(v: &Value) -> String {
+    match v {
+        Value::Float(f) if !f.is_finite() => "null".to_string(),
+        Value::Qty(_) | Value::Date(_) | Value::Poly(_) => json_string(&v.to_string()),
+        v => v.to_string(),
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+// a parsed JSON document, for reading back what `to_json` writes
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let chars = text.chars().collect::<Vec<char>>();
+        let mut i = 0;
+        let json = Json::value(&chars, &mut i)?;
+        Json::skip_space(&chars, &mut i);
+        if i < chars.len() {
+            return Err(format!("Unexpected '{}' at {} in JSON", chars[i], i));
+        }
+        return Ok(json);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn skip_space(chars: &[char], i: &mut usize) {
+        while chars.get(*i).is_some_and(|c| c.is_whitespace()) {
+            *i += 1;
+        }
+    }
+
+    fn value(chars: &[char], i: &mut usize) -> Result<Json, String> {
+        Json::skip_space(chars, i);
+        let expect = |i: &mut usize, word: &str, json: Json| {
+            if chars[*i..].starts_with(&word.chars().collect::<Vec<char>>()) {
+                *i += word.len();
+                return Ok(json);
+            }
+            return Err(format!("Unexpected '{}' at {} in JSON", chars[*i], i));
+        };
+        match chars.get(*i) {
+            None => Err("Unexpected end of JSON".to_string()),
+            Some('n') => expect(i, "null", Json::Null),
+            Some('t') => expect(i, "true", Json::Bool(true)),
+            Some('f') => expect(i, "false", Json::Bool(false)),
+            Some('"') => Json::string(chars, i).map(Json::Str),
+            Some(c @ ('[' | '{')) => {
+                let (close, object) = if *c == '[' { (']', false) } else { ('}', true) };
+                *i += 1;
+                let (mut items, mut fields) = (Vec::new(), Vec::new());
+                Json::skip_space(chars, i);
+                if chars.get(*i) == Some(&close) {
+                    *i += 1;
+                } else {
+                    loop {
+                        if object {
+                            Json::skip_space(chars, i);
+                            if chars.get(*i) != Some(&'"') {
+                                return Err(format!("Expected a key at {} in JSON", i));
+                            }
+                            let key = Json::string(chars, i)?;
+                            Json::skip_space(chars, i);
+                            if chars.get(*i) != Some(&':') {
+                                return Err(format!("Expected ':' at {} in JSON", i));
+                            }
+                            *i += 1;
+                            fields.push((key, Json::value(chars, i)?));
+                        } else {
+                            items.push(Json::value(chars, i)?);
+                        }
+                        Json::skip_space(chars, i);
+                        match chars.get(*i) {
+                            Some(',') => *i += 1,
+                            Some(c) if *c == close => { *i += 1; break; },
+                            _ => return Err(format!("Expected ',' or '{}' at {} in JSON", close, i)),
+                        }
+                    }
+                }
+                Ok(if object { Json::Obj(fields) } else { Json::Arr(items) })
+            },
+            Some(_) => {
+                let start = *i;
+                while chars.get(*i).is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                    *i += 1;
+                }
+                let number = chars[start..*i].iter().collect::<String>();
+                number.parse().map(Json::Num).map_err(|_| format!("Unexpected '{}' at {} in JSON", chars[start], start))
+            },
+        }
+    }
+
+    // starting at the opening quote
+    fn string(chars: &[char], i: &mut usize) -> Result<String, String> {
+        let mut out = String::new();
+        *i += 1;
+        loop {
+            let c = *chars.get(*i).ok_or("Unterminated string in JSON")?;
+            *i += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let e = *chars.get(*i).ok_or("Unterminated string in JSON")?;
+                    *i += 1;
+                    out.push(match e {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let hex = chars.get(*i..*i + 4).map(|h| h.iter().collect::<String>()).unwrap_or_default();
+                            *i += 4;
+                            u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                                .ok_or_else(|| format!("Invalid escape \\u{} in JSON", hex))?
+                        },
+                        e => e,
+                    });
+                },
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+impl Token {
+    // {"token": "ADD"}, {"token": "NUM", "value": "2.5"}, {"token": "IDENT", "name": "x"}
+    pub fn to_json(&self) -> String {
+        let debug = format!("{:?}", self);
+        let kind = debug.split('(').next().unwrap();
+        match self {
+            Token::NUM(v) | Token::SUP(v) => format!("{{\"token\": {}, \"value\": {}}}", json_string(kind), json_string(&v.to_string())),
+            Token::REF(name) | Token::ENV(name) | Token::IDENT(name) | Token::OP(name) => {
+                format!("{{\"token\": {}, \"name\": {}}}", json_string(kind), json_string(name))
+            },
+            _ => format!("{{\"token\": {}}}", json_string(kind)),
+        }
+    }
+
+    pub fn from_json(text: &str) -> Result<Token, String> {
+        let json = Json::parse(text)?;
+        let field = |key: &str| json.get(key).and_then(Json::as_str).ok_or_else(|| format!("Missing \"{}\" in token {}", key, text));
+        let name = || field("name").map(|n| n.to_string());
+        let token = match field("token")? {
+            "NUM" => Token::NUM(value_from_text(field("value")?)?),
+            "SUP" => Token::SUP(value_from_text(field("value")?)?),
+            "REF" => Token::REF(name()?),
+            "ENV" => Token::ENV(name()?),
+            "IDENT" => Token::IDENT(name()?),
+            "OP" => Token::OP(name()?),
+            kind => {
+                use Token::*;
+                let units = [ADD, SUB, MUL, DIV, FLOORDIV, POW, LPR, RPR, LBR, RBR, COMMA, ASSIGN, EQ, NE, LT, LE, GT, GE, AND, OR, NOT,
+                    QUESTION, COLON, SEMI, TO, BITAND, BITOR, XOR, SHL, SHR, INV, PCT];
+                units.into_iter().find(|t| format!("{:?}", t) == kind).ok_or_else(|| format!("Unknown token {}", kind))?
+            },
+        };
+        return Ok(token);
+    }
+}
+
+// what Value's Display wrote, "-7", "2.5", "inf" or the digits of a big integer
+fn value_from_text(s: &str) -> Result<Value, String> {
+    if let Some(b) = BigInt::parse(s) {
+        return Ok(Value::from_big(b));
+    }
+    if let Some(t) = parse_date(s) {
+        return Ok(Value::Date(t));
+    }
+    return s.parse().map(Value::Float).map_err(|_| format!("Invalid number \"{}\"", s));
+}
+
+impl Expr {
+    // the tree as nested {"op", "args", "span"} objects, leaves with their "value" or "name"
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"op\": {}", json_string(self.op()))];
+        match self {
+            Expr::Num(v, _) => fields.push(format!("\"value\": {}", json_string(&v.to_string()))),
+            Expr::Ref(name, v, _) => {
+                fields.push(format!("\"name\": {}", json_string(name)));
+                fields.push(format!("\"value\": {}", json_string(&v.to_string())));
+            },
+            Expr::Var(name, _) => fields.push(format!("\"name\": {}", json_string(name))),
+            _ => {
+                let args = self.children().iter().map(|c| c.to_json()).collect::<Vec<String>>();
+                fields.push(format!("\"args\": [{}]", args.join(", ")));
+            },
+        }
+        let span = self.span();
+        fields.push(format!("\"span\": [{}, {}]", span.start, span.end));
+        if let Expr::Bin(.., at) = self {
+            fields.push(format!("\"op_span\": [{}, {}]", at.start, at.end));
+        }
+        return format!("{{{}}}", fields.join(", "));
+    }
+
+    // reads back `to_json`, the spans are optional
+    pub fn from_json(text: &str) -> Result<Expr, String> {
+        return Expr::from_json_value(&Json::parse(text)?);
+    }
+
+    fn from_json_value(json: &Json) -> Result<Expr, String> {
+        let field = |key: &str| json.get(key).and_then(Json::as_str).ok_or_else(|| format!("Missing \"{}\" in an AST node", key));
+        let op = field("op")?;
+        let range = |key: &str| match json.get(key) {
+            Some(Json::Arr(range)) => match range.as_slice() {
+                [Json::Num(start), Json::Num(end)] => Ok(Some(Span { start: *start as usize, end: *end as usize })),
+                _ => Err(format!("Invalid \"{}\" of {}", key, op)),
+            },
+            _ => Ok(None),
+        };
+        let span = range("span")?.unwrap_or_default();
+        let args = match json.get("args") {
+            Some(Json::Arr(args)) => args.iter().map(Expr::from_json_value).collect::<Result<Vec<Expr>, String>>()?,
+            None => Vec::new(),
+            Some(_) => return Err(format!("Invalid \"args\" of {}", op)),
+        };
+        let arity = |n: usize| {
+            if args.len() != n {
+                return Err(format!("{} expects {} operand(s), got {}", op, n, args.len()));
+            }
+            return Ok(args.iter().cloned().map(Box::new).collect::<Vec<Box<Expr>>>());
+        };
+        let expr = match op {
+            "num" => Expr::Num(value_from_text(field("value")?)?, span),
+            "ref" => Expr::Ref(field("name")?.to_string(), value_from_text(field("value")?)?, span),
+            "var" => Expr::Var(field("name")?.to_string(), span),
+            "neg" | "!" | "~" | "()" => {
+                let [c] = <[Box<Expr>; 1]>::try_from(arity(1)?).unwrap();
+                match op {
+                    "neg" => Expr::Neg(c, span),
+                    "!" => Expr::Not(c, span),
+                    "~" => Expr::Inv(c, span),
+                    _ => Expr::Par(c, span),
+                }
+            },
+            // n% has one operand, a % b two
+            "%" if args.len() == 1 => Expr::Pct(Box::new(args[0].clone()), span),
+            "?:" => {
+                let [c, a, b] = <[Box<Expr>; 3]>::try_from(arity(3)?).unwrap();
+                Expr::Cond(c, a, b)
+            },
+            "=" => {
+                let [var, value] = <[Box<Expr>; 2]>::try_from(arity(2)?).unwrap();
+                Expr::Assign(var, value)
+            },
+            name if builtin(name).is_some() => {
+                let f = builtin(name).unwrap();
+                arity(f.arity)?;
+                Expr::Call(f, args, span)
+            },
+            "[]" => Expr::List(args, span),
+            symbol => {
+                let op = Op::ALL.into_iter().find(|o| o.symbol() == symbol).ok_or_else(|| format!("Unknown op \"{}\"", symbol))?;
+                let [l, r] = <[Box<Expr>; 2]>::try_from(arity(2)?).unwrap();
+                Expr::Bin(op, l, r, range("op_span")?.unwrap_or(span))
+            },
+        };
+        return Ok(expr);
+    }
+}
+
+// `--show-grouping`: infix with every implicit grouping parenthesized, 
+// except left-assoc chains of the same precedence like `1 + 2 - 3`
+pub fn grouping(n: &Expr) -> String {
+    let children = n.children();
+    match (n.op(), children.as_slice()) {
+        (name, args) if builtin(name).is_some() => {
+            format!("{}({})", name, args.iter().map(|a| grouping(a)).collect::<Vec<String>>().join(", "))
+        },
+        ("neg", [c]) => format!("-{}", grouping(c)),
+        ("!", [c]) => format!("!{}", grouping(c)),
+        ("~", [c]) => format!("~{}", grouping(c)),
+        ("%", [c]) => format!("{}%", grouping(c)),
+        ("?:", [c, a, b]) => {
+            let wrap = |c: &Expr| if precedence(c.op()) <= precedence("?:") { format!("({})", grouping(c)) } else { grouping(c) };
+            format!("{} ? {} : {}", wrap(c), wrap(a), grouping(b))
+        },
+        ("()", [c]) => format!("({})", grouping(c)),
+        ("[]", items) => format!("[{}]", items.iter().map(|a| grouping(a)).collect::<Vec<String>>().join(", ")),
+        (op, [l, r]) => {
+            let wrap = |c: &Expr, left: bool| {
+                if is_infix(c) && !(left && precedence(c.op()) == precedence(op)) {
+                    format!("({})", grouping(c))
+                } else {
+                    grouping(c)
+                }
+            };
+            format!("{} {} {}", wrap(l, true), op, wrap(r, false))
+        },
+        _ => n.repr(),
+    }
+}
+
+// `--format`: the ways an expression can be printed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Repr,       // <1*(<2+3>)>
+    Rpn,        // 1 2 3 + *
+    Latex,      // 1 \cdot (2 + 3)
+    Sexpr,      // (* 1 (+ 2 3))
+    Infix,      // 1 * (2 + 3)
+}
+
+pub const FORMATS: &[&str] = &["repr", "rpn", "latex", "sexpr", "infix"];
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        let all = [Format::Repr, Format::Rpn, Format::Latex, Format::Sexpr, Format::Infix];
+        return FORMATS.iter().position(|f| *f == name).map(|i| all[i]);
+    }
+
+    pub fn render(self, n: &Expr) -> String {
+        match self {
+            Format::Repr => n.repr(),
+            Format::Rpn => rpn(n),
+            Format::Latex => latex(n),
+            Format::Sexpr => sexpr(n),
+            Format::Infix => infix(n),
+        }
+    }
+}
+
+// operands first, then the operator: `neg`, `?:` and function names take their operands off the stack
+pub fn rpn(n: &Expr) -> String {
+    match n {
+        Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => n.repr(),
+        Expr::Par(c, _) => rpn(c),
+        n => n.children().into_iter().map(rpn).chain([n.op().to_string()]).collect::<Vec<String>>().join(" "),
+    }
+}
+
+// lisp style, `(neg x)`, `(?: c a b)`, `(max 1 2)`
+pub fn sexpr(n: &Expr) -> String {
+    match n {
+        Expr::Num(..) | Expr::Ref(..) | Expr::Var(..) => n.repr(),
+        Expr::Par(c, _) => sexpr(c),
+        n => format!("({})", [n.op().to_string()].into_iter().chain(n.children().into_iter().map(sexpr)).collect::<Vec<String>>().join(" ")),
+    }
+}
+
+// `calc diff`: infix with only the parentheses precedence needs, `2 * x + 3`
+pub fn infix(n: &Expr) -> String {
+    render_infix(n, false)
+}
+
+// for pasting into a document, `\frac{x}{2}`, `\sqrt{x}`, `x^{2}`
+pub fn latex(n: &Expr) -> String {
+    render_infix(n, true)
+}
+
+// the precedence an operand is printed with in infix: a negative literal groups like a negation, `(-1) ^ 2`, 
+// a polynomial like a sum, and n% tighter than anything, unlike a % b
+fn level(c: &Expr) -> u8 {
+    match c {
+        Expr::Pct(..) => precedence("other"),
+        Expr::Num(Value::Poly(_), _) => precedence("+"),
+        Expr::Num(v, _) if v.to_string().starts_with('-') => precedence("neg"),
+        Expr::Par(c, _) => level(c),
+        c => precedence(c.op()),
+    }
+}
+
+fn render_infix(n: &Expr, latex: bool) -> String {
+    let show = |c: &Expr| render_infix(c, latex);
+    let wrap = |c: &Expr, loose: bool| if loose { format!("({})", show(c)) } else { show(c) };
+    let children = n.children();
+    match (n.op(), children.as_slice()) {
+        ("var", _) if latex => match n.repr().as_str() {
+            "pi" | "tau" => format!("\\{}", n.repr()),
+            "inf" => "\\infty".to_string(),
+            name if name.chars().count() > 1 => format!("\\mathrm{{{}}}", name.replace('_', "\\_")),
+            name => name.to_string(),
+        },
+        ("sqrt", [c]) if latex => format!("\\sqrt{{{}}}", show(c)),
+        ("abs", [c]) if latex => format!("\\left|{}\\right|", show(c)),
+        (name, args) if builtin(name).is_some() => {
+            let name = match name {
+                _ if !latex => name.to_string(),
+                "sin" | "cos" | "tan" | "log" | "min" | "max" => format!("\\{}", name),
+                _ => format!("\\operatorname{{{}}}", name),
+            };
+            format!("{}({})", name, args.iter().map(|a| show(a)).collect::<Vec<String>>().join(", "))
+        },
+        (op @ ("neg" | "!" | "~"), [c]) => {
+            let sign = match op {
+                "neg" => "-",
+                "!" if latex => "\\lnot ",
+                "~" if latex => "\\sim ",
+                _ => op,
+            };
+            format!("{}{}", sign, wrap(c, level(c) < precedence(op)))
+        },
+        ("%", [c]) => format!("{}{}", wrap(c, level(c) < precedence("other")), if latex { "\\%" } else { "%" }),
+        ("?:", [c, a, b]) => {
+            format!("{} ? {} : {}", wrap(c, level(c) <= precedence("?:")), wrap(a, level(a) < precedence("?:")), show(b))
+        },
+        ("()", [c]) => show(c),
+        ("[]", items) if latex => format!("\\left[{}\\right]", items.iter().map(|a| show(a)).collect::<Vec<String>>().join(", ")),
+        ("[]", items) => format!("[{}]", items.iter().map(|a| show(a)).collect::<Vec<String>>().join(", ")),
+        (";", [l, r]) => format!("{}; {}", show(l), show(r)),
+        ("/", [l, r]) if latex => format!("\\frac{{{}}}{{{}}}", show(l), show(r)),
+        ("//", [l, r]) if latex => format!("\\left\\lfloor\\frac{{{}}}{{{}}}\\right\\rfloor", show(l), show(r)),
+        ("^", [l, r]) if latex => format!("{}^{{{}}}", wrap(l, level(l) <= precedence("^")), show(r)),
+        (op, [l, r]) => {
+            // `^` and `=` group to the right, the rest to the left
+            let right = op == "^" || op == "=";
+            let (p, lp, rp) = (precedence(op), level(l), level(r));
+            let symbol = match op {
+                _ if !latex => op,
+                "*" => "\\cdot",
+                "%" => "\\bmod",
+                "==" => "=",
+                "=" => ":=",
+                "!=" => "\\neq",
+                "<=" => "\\leq",
+                ">=" => "\\geq",
+                "&&" => "\\land",
+                "||" => "\\lor",
+                "&" => "\\mathbin{\\&}",
+                "|" => "\\mathbin{|}",
+                "xor" => "\\oplus",
+                "<<" => "\\ll",
+                "->" => "\\to",
+                ">>" => "\\gg",
+                _ => op,
+            };
+            format!("{} {} {}", wrap(l, lp < p || (lp == p && right)), symbol, wrap(r, rp < p || (rp == p && !right)))
+        },
+        _ => n.repr(),
+    }
+}
+
+// `--width`: the expression as `format` prints it, or as `grouping` for None, broken before the operators 
+// of the outermost chain when a line would be longer than `width`, each operator starting an indented line; 
+// repr and infix break, the other formats stay on one line
+pub fn pretty(n: &Expr, width: usize, format: Option<Format>) -> Vec<String> {
+    let flat = format.map_or_else(|| grouping(n), |f| f.render(n));
+    if flat.chars().count() <= width || !matches!(format, None | Some(Format::Repr | Format::Infix)) {
+        return vec![flat];
+    }
+    let children = n.children();
+    match (n.op(), children.as_slice()) {
+        ("()", [c]) if format == Some(Format::Infix) => pretty(c, width, format),
+        ("()", [c]) => parenthesize(pretty(c, width.saturating_sub(1), format)),
+        (op, [_, _]) if is_infix(n) => {
+            // a left-assoc chain of the same precedence, like `a + b - c`
+            let mut chain = vec![];
+            let mut node = n;
+            while precedence(node.op()) == precedence(op) && is_infix(node) {
+                let children = node.children();
+                chain.push((node.op(), children[1]));
+                node = children[0];
+            }
+            // in parentheses where the one-line form has them, `^` and `=` grouping to the right in infix
+            let operand = |c: &Expr, indent: usize, left: bool| {
+                let width = width.saturating_sub(indent).max(1);
+                let wrap = match format {
+                    None => is_infix(c),
+                    Some(Format::Infix) => level(c) < precedence(op) || (level(c) == precedence(op) && (op == "^" || op == "=") == left),
+                    _ => false,
+                };
+                if wrap { parenthesize(pretty(c, width - 1, format)) } else { pretty(c, width, format) }
+            };
+            let mut lines = operand(node, 0, true);
+            // a repr opens an angle bracket for each link of the chain and closes it after the right operand
+            let repr = format == Some(Format::Repr);
+            if repr {
+                lines[0] = "<".repeat(chain.len()) + &lines[0];
+            }
+            for (op, c) in chain.into_iter().rev() {
+                let hang = " ".repeat(op.len() + 3);
+                for (i, line) in operand(c, hang.len(), false).into_iter().enumerate() {
+                    lines.push(if i == 0 { format!("  {} {}", op, line) } else { format!("{}{}", hang, line) });
+                }
+                if repr {
+                    lines.last_mut().unwrap().push('>');
+                }
+            }
+            lines
+        },
+        _ => vec![flat],
+    }
+}
+
+fn parenthesize(mut lines: Vec<String>) -> Vec<String> {
+    let last = lines.len() - 1;
+    for (i, line) in lines.iter_mut().enumerate() {
+        *line = format!("{}{}", if i == 0 { "(" } else { " " }, line);
+    }
+    lines[last].push(')');
+    return lines;
+}
+
+// `--verbose`: one node per line with the source it was parsed from
+pub fn tree(n: &Expr, source: &[char], depth: usize, lines: &mut Vec<String>) {
+    let span = n.span();
+    let text: String = source[span.start..span.end].iter().collect();
+    lines.push(format!("{}{} {}", "  ".repeat(depth), n.op(), text));
+    for c in n.children() {
+        tree(c, source, depth + 1, lines);
+    }
+}
+
+// a suspicious but valid construct
+pub struct Lint {
+    pub span: Span,
+    pub message: String,
+}
+
+// no variables, so the value is known without an environment
+fn is_constant(n: &Expr) -> bool {
+    n.op() != "var" && n.children().into_iter().all(is_constant)
+}
+
+pub fn lint(n: &Expr, lints: &mut Vec<Lint>) {
+    let children = n.children();
+    match (n.op(), children.as_slice()) {
+        ("//", [l, r]) => {
+            // checked, i32::MIN // -1 is left for the evaluation to report as an overflow
+            if is_constant(l) && is_constant(r) && let (Ok(Value::Int(a)), Ok(Value::Int(b))) = (l.eval(), r.eval())
+                && let (Some(q), Some(rem)) = (a.checked_div(b), a.checked_rem(b)) && rem != 0 {
+                // in i64, q * b may leave an i32 when the signs differ
+                let q = q as i64 - ((a < 0) != (b < 0)) as i64;
+                let message = format!("floor division drops the remainder: {} // {} = {} (remainder {})", a, b, q, a as i64 - q * b as i64);
+                lints.push(Lint { span: n.span(), message });
+            }
+        },
+        ("neg", [c]) if c.op() == "neg" || (c.op() == "()" && c.children()[0].op() == "neg") => {
+            lints.push(Lint { span: n.span(), message: "redundant double negation".to_string() });
+        },
+        // known operands, or the same one on both sides; x == x is taken as true although NaN is not equal to itself
+        (op @ ("==" | "!=" | "<" | "<=" | ">" | ">="), [l, r]) if is_pure(n) => {
+            let always = if is_constant(l) && is_constant(r) {
+                n.eval().ok().and_then(|v| v.is_zero().ok()).map(|zero| !zero)
+            } else if infix(l) == infix(r) {
+                Some(matches!(op, "==" | "<=" | ">="))
+            } else {
+                None
+            };
+            if let Some(always) = always {
+                let text = format!("{} {} {}", infix(l), op, infix(r));
+                lints.push(Lint { span: n.span(), message: format!("comparison is always {}: {}", always, text) });
+            }
+        },
+        _ => {},
+    }
+    for c in children {
+        lint(c, lints);
+    }
+}
+
+// no call to rand() or the like, so evaluating twice gives the same
+fn is_pure(n: &Expr) -> bool {
+    !matches!(n, Expr::Call(f, ..) if !f.pure()) && n.children().into_iter().all(is_pure)
+}
+
+// `x = 1; x = 2; x + 1`: a value assigned and then overwritten or never read before the end, 
+// unless it is the result of the whole expression; the variables of an expression go away with it, 
+// so only an expression given on its own should be checked, not a line of a session
+pub fn lint_assignments(n: &Expr, lints: &mut Vec<Lint>) {
+    // reads and assignments in the order they are evaluated
+    enum Event<'a> { Read(String), Assign(String, &'a Expr) }
+    fn events<'a>(n: &'a Expr, out: &mut Vec<Event<'a>>) {
+        match n {
+            Expr::Var(name, _) => out.push(Event::Read(name.clone())),
+            Expr::Assign(var, value) => {
+                events(value, out);
+                out.push(Event::Assign(var.repr(), n));
+            },
+            n => n.children().into_iter().for_each(|c| events(c, out)),
+        }
+    }
+    // the last of `a; b; c`, whose value is printed
+    let mut result = n;
+    while let Expr::Bin(Op::Seq, _, r, _) | Expr::Par(r, _) = result {
+        result = r;
+    }
+    let mut list = Vec::new();
+    events(n, &mut list);
+    for (i, e) in list.iter().enumerate() {
+        let Event::Assign(name, node) = e else { continue; };
+        if std::ptr::eq(*node, result) {
+            continue;
+        }
+        let read = list[i + 1..].iter()
+            .take_while(|later| !matches!(later, Event::Assign(other, _) if other == name))
+            .any(|later| matches!(later, Event::Read(other) if other == name));
+        if !read {
+            lints.push(Lint { span: node.span(), message: format!("the value assigned to {} is never used", name) });
+        }
+    }
+}
+
+// parse and evaluation errors alike
+pub fn eval_str(input: &str) -> Result<Value, CalcError> {
+    eval_str_in(input, &mut Env::default())
+}
+
+pub fn eval_str_in(input: &str, env: &mut Env) -> Result<Value, CalcError> {
+    parse(input.trim())?.eval_in(env)
+}
+
+// an application's own functions and constants on top of the built-in ones:
+// Calculator::builder().function("tax", 1, |v| &v[0] * &Value::Float(0.19)).constant("vat", 0.19).build(). 
+// Functions, operators, constants, variables and env_vars belong to the one Calculator; the modes of 
+// set_bignum, set_decimal, set_overflow, set_nan_policy, set_degrees, set_exact_roots, set_locale, set_lang, 
+// set_seed and set_max_depth, and the aliases and operators of the config file, are global to the process 
+// and apply to every Calculator, on every thread, as they do to eval_str
+pub struct Calculator {
+    functions: Vec<&'static Builtin>,
+    operators: Vec<Operator>,
+    env_vars: bool,
+    pub env: Env,   // variables set by the expressions evaluated so far, and the constants
+}
+
+// a function or operator is kept here until build, a later one of the same name replacing it
+#[derive(Default)]
+pub struct CalculatorBuilder {
+    functions: Vec<(String, usize, Box<NativeFn>)>,
+    operators: Vec<(String, u8, Assoc, Box<NativeFn>)>,
+    env_vars: bool,
+    env: Env,
+}
+
+impl CalculatorBuilder {
+    // calls with another number of arguments are a parse error, as for the built-in functions
+    pub fn function(mut self, name: &str, arity: usize, f: impl Fn(&[Value]) -> Result<Value, CalcError> + Send + Sync + 'static) -> Self {
+        self.functions.retain(|(other, ..)| other != name);
+        self.functions.push((name.to_string(), arity, Box::new(f)));
+        return self;
+    }
+
+    // a binary operator like `**`, see Operator; it replaces a configured or built-in one with the same symbol. 
+    // An error, as for `infix` in the config file, for a symbol that could not be read as an operator 
+    // or a precedence outside 4 (like ||) to 14 (like ^)
+    pub fn operator(mut self, symbol: &str, precedence: u8, assoc: Assoc, f: impl Fn(&Value, &Value) -> Result<Value, CalcError> + Send + Sync + 'static) -> Result<Self, String> {
+        if !operator_symbol_ok(symbol) {
+            return Err(format!("operator must be a lowercase word or symbols other than ()[],;#$\"._', got '{}'", symbol));
+        }
+        if !precedence_ok(precedence) {
+            return Err(format!("precedence must be 4 (like ||) to 14 (like ^), got '{}'", precedence));
+        }
+        self.operators.retain(|(other, ..)| other != symbol);
+        self.operators.push((symbol.to_string(), precedence, assoc, Box::new(move |args| f(&args[0], &args[1]))));
+        return Ok(self);
+    }
+
+    pub fn constant(mut self, name: &str, value: f64) -> Self {
+        self.env.constants.insert(name.to_string(), Value::Float(value));
+        return self;
+    }
+
+    // `$NAME` reads the environment instead of being an error, whatever set_env_vars says
+    pub fn env_vars(mut self, allow: bool) -> Self {
+        self.env_vars = allow;
+        return self;
+    }
+
+    // the functions and operators stay allocated for the rest of the program from here on, Expr::Call refers to them
+    pub fn build(self) -> Calculator {
+        let leak = |name: String| -> &'static str { Box::leak(name.into_boxed_str()) };
+        let shapes = self.operators.iter().map(|(_, precedence, assoc, _)| (*precedence, *assoc)).collect::<Vec<_>>();
+        let builtins = self.functions.into_iter().map(|(name, arity, f)| Builtin { name: leak(name), arity, f: Func::Custom(f) })
+            .chain(self.operators.into_iter().map(|(symbol, _, _, f)| Builtin { name: leak(symbol), arity: 2, f: Func::Custom(f) }))
+            .collect::<Vec<Builtin>>();
+        let builtins: &'static [Builtin] = Box::leak(builtins.into_boxed_slice());
+        let (functions, operators) = builtins.split_at(builtins.len() - shapes.len());
+        let operators = operators.iter().zip(shapes)
+            .map(|(f, (precedence, assoc))| Operator { symbol: f.name, precedence, assoc, apply: Infix::Call(f) })
+            .collect();
+        Calculator { functions: functions.iter().collect(), operators, env_vars: self.env_vars, env: self.env }
+    }
+}
+
+impl Calculator {
+    pub fn builder() -> CalculatorBuilder {
+        CalculatorBuilder::default()
+    }
+
+    pub fn parse(&self, input: &str) -> Result<Ast, CalcError> {
+        return evaluate(TokenParser {
+            env_vars: self.env_vars,
+            operators: self.operators.iter().copied().chain(operators().iter().copied()).collect(),
+            ..TokenParser::with_functions(input.to_string(), self.functions.clone())
+        });
+    }
+
+    // like eval_str, variables are kept for the next call
+    pub fn eval(&mut self, input: &str) -> Result<Value, CalcError> {
+        self.parse(input.trim())?.eval_in(&mut self.env)
+    }
+}
+
+// the C interface of the cdylib, laid out for cbindgen: 
+// the strings in a CalcResult belong to the library until calc_result_free is called on it
+#[repr(C)]
+pub struct CalcResult {
+    pub ok: i32,                                // 1 if `value` is set, 0 on error
+    pub value: *mut std::ffi::c_char,           // the result as printed, NULL on error
+    pub code: *mut std::ffi::c_char,            // "E0031", NULL on success
+    pub message: *mut std::ffi::c_char,         // NULL on success
+    pub position: i64,                          // index of a parse error or a division by zero in the input, -1 otherwise
+}
+
+fn c_string(s: &str) -> *mut std::ffi::c_char {
+    std::ffi::CString::new(s.replace('\0', "")).unwrap().into_raw()
+}
+
+/// # Safety
+/// `input` is a NUL-terminated UTF-8 string and `out` points to a CalcResult the caller owns, 
+/// whose strings are freed with calc_result_free. Returns `ok`, or -1 if a pointer is NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calc_eval(input: *const std::ffi::c_char, out: *mut CalcResult) -> i32 {
+    if input.is_null() || out.is_null() {
+        return -1;
+    }
+    let input = unsafe { std::ffi::CStr::from_ptr(input) }.to_string_lossy();
+    let result = match parse_spanned(input.trim()) {
+        Ok(n) => n.eval().map_err(|e| {
+            let position = e.position().map_or(-1, |idx| idx as i64);
+            (e, position)
+        }),
+        Err((e, span)) => Err((e, span.start as i64)),
+    };
+    let null = std::ptr::null_mut();
+    let result = match result {
+        Ok(v) => CalcResult { ok: 1, value: c_string(&v.to_string()), code: null, message: null, position: -1 },
+        Err((e, position)) => CalcResult { ok: 0, value: null, code: c_string(e.code()), message: c_string(&e.message()), position },
+    };
+    let ok = result.ok;
+    unsafe { out.write(result) };
+    return ok;
+}
+
+/// # Safety
+/// `result` was filled by calc_eval and is not used again until the next calc_eval, NULL is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calc_result_free(result: *mut CalcResult) {
+    let Some(result) = (unsafe { result.as_mut() }) else { return; };
+    for s in [&mut result.value, &mut result.code, &mut result.message] {
+        if !s.is_null() {
+            drop(unsafe { std::ffi::CString::from_raw(*s) });
+            *s = std::ptr::null_mut();
+        }
+    }
+}
+
+// the page's `evaluate(input)` in calc.js, one string in and one out through the exports below: 
+// the `--json` object with the repr, and on failure every diagnostic, a syntax error's with its span
+pub fn evaluate_json(input: &str) -> String {
+    let diagnostic = |e: &CalcError, span: Option<Span>| {
+        let span = span.map_or("null".to_string(), |s| format!("[{}, {}]", s.start, s.end));
+        format!("{{\"code\": {}, \"message\": {}, \"span\": {}}}", json_string(e.code()), json_string(&e.message()), span)
+    };
+    let failure = |repr: Option<String>, diagnostics: Vec<String>| {
+        format!("{{\"input\": {}, \"ok\": false, \"repr\": {}, \"diagnostics\": [{}]}}",
+            json_string(input), repr.map_or("null".to_string(), |r| json_string(&r)), diagnostics.join(", "))
+    };
+    let n = match parse_recovering(input.trim()) {
+        Ok(n) => n,
+        Err(errors) => return failure(None, errors.iter().map(|(e, span)| diagnostic(e, Some(*span))).collect()),
+    };
+    match n.eval() {
+        Ok(v) => format!("{{\"input\": {}, \"ok\": true, \"result\": {}, \"repr\": {}}}", json_string(input), json_number(&v), json_string(&n.repr())),
+        Err(e) => {
+            let span = e.position().map(|start| Span { start, end: start + 1 });
+            failure(Some(n.repr()), vec![diagnostic(&e, span)])
+        },
+    }
+}
+
+/// # Safety
+/// `input` is a NUL-terminated UTF-8 string. The JSON returned belongs to the caller until calc_string_free, 
+/// NULL if `input` is NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calc_evaluate_json(input: *const std::ffi::c_char) -> *mut std::ffi::c_char {
+    if input.is_null() {
+        return std::ptr::null_mut();
+    }
+    let input = unsafe { std::ffi::CStr::from_ptr(input) }.to_string_lossy();
+    return c_string(&evaluate_json(&input));
+}
+
+/// # Safety
+/// `s` came from calc_evaluate_json and is not used again, NULL is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calc_string_free(s: *mut std::ffi::c_char) {
+    if !s.is_null() {
+        drop(unsafe { std::ffi::CString::from_raw(s) });
+    }
+}
+
+// linear memory for calc.js to write the input into, a wasm32 module has no other allocator to offer
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub extern "C" fn calc_alloc(len: usize) -> *mut u8 {
+    let mut buf = std::mem::ManuallyDrop::new(Vec::<u8>::with_capacity(len));
+    return buf.as_mut_ptr();
+}
+
+/// # Safety
+/// `ptr` came from calc_alloc with the same `len`.
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calc_dealloc(ptr: *mut u8, len: usize) {
+    drop(unsafe { Vec::from_raw_parts(ptr, 0, len) });
+}
+
+// splitmix64, small and good enough for generating test expressions
+#[derive(Clone)]
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn from_time() -> Self {
+        Rng(unix_time().map_or(0, |t| t.as_nanos() as u64))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        return z ^ (z >> 31);
+    }
+
+    // uniform in [0, n)
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+pub const GEN_OPS: &[char] = &['+', '-', '*', '/'];
+
+// random tree of at most `depth` operator levels over `ops`, literals in 0..=max
+pub fn random_expr(rng: &mut Rng, depth: usize, ops: &[char], max: u64) -> Expr {
+    let span = Span::default();
+    if depth == 0 || rng.below(4) == 0 {
+        return Expr::Num(Value::Int(rng.below(max + 1) as i32), span);
+    }
+    let l = random_expr(rng, depth - 1, ops, max);
+    let mut r = random_expr(rng, depth - 1, ops, max);
+    let op = ops[rng.below(ops.len() as u64) as usize];
+    while op == '/' && r.eval() == Ok(Value::Int(0)) {
+        r = random_expr(rng, depth - 1, ops, max);
+    }
+    match op {
+        '+' => Expr::Bin(Op::Add, Box::new(l), Box::new(r), span),
+        '-' => Expr::Bin(Op::Sub, Box::new(l), Box::new(r), span),
+        '*' => Expr::Bin(Op::Mul, Box::new(l), Box::new(r), span),
+        '/' => Expr::Bin(Op::Div, Box::new(l), Box::new(r), span),
+        _ => unreachable!(),
+    }
+}
+
+// `calc gen`: expressions whose evaluation succeeds (no overflow or division by zero)
+pub fn generate(rng: &mut Rng, depth: usize, ops: &[char], count: usize) -> Vec<(String, Value)> {
+    let mut exprs = Vec::new();
+    while exprs.len() < count {
+        let n = random_expr(rng, depth, ops, 99);
+        if let Ok(v) = n.eval() {
+            exprs.push((grouping(&n), v));
+        }
+    }
+    return exprs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api(){
+        let ast = parse("2 * (3 + 4)").unwrap();
+        assert_eq!(ast.repr(), "<2*(<3+4>)>");
+        assert_eq!(ast.eval(), Ok(Value::Int(14)));
+        let Expr::Bin(Op::Mul, _, r, _) = &ast else { panic!("{}", ast.repr()) };
+        assert!(matches!(&**r, Expr::Par(inner, _) if matches!(**inner, Expr::Bin(Op::Add, ..))));
+        assert_eq!(eval("10 // 3 - 1"), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_errors(){
+        assert_eq!(parse("(1 + 2) 3").err(), Some(CalcError::TrailingInput(Token::NUM(Value::Int(3)))));
+        assert_eq!(parse("(1 + 2").err(), Some(CalcError::UnbalancedParen));
+        assert_eq!(parse("1 + * 2").err(), Some(CalcError::UnexpectedToken(Token::MUL)));
+        assert_eq!(parse("  ").err(), Some(CalcError::EmptyInput));
+        assert_eq!(parse("1 +").err(), Some(CalcError::UnexpectedEnd));
+        assert_eq!(parse("1 @ 2").err(), Some(CalcError::InvalidToken('@', 2)));
+        assert_eq!(CalcError::UnbalancedParen.to_string(), "Error[E0008]: Open parenthesis.");
+        let tokens: Vec<_> = TokenParser::new("1 @ 2".to_string()).collect();
+        assert_eq!(tokens, vec![Ok(Token::NUM(Value::Int(1))), Err(CalcError::InvalidToken('@', 2))]);
+    }
+
+    #[test]
+    fn test_floats(){
+        assert_eq!(eval_str("2.5 * 4"), Ok(Value::Float(10.0)));
+        assert_eq!(eval_str("1.0 / 2 + .25"), Ok(Value::Float(0.75)));
+        assert_eq!(eval_str("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(eval_str("6 / 2").map(|v| v.to_string()), Ok("3.0".to_string()));
+        assert_eq!(eval_str("7 // 2 + -7 // 2"), Ok(Value::Int(-1)));
+        assert_eq!(eval_str("7.5 // 2"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("-1.5 - 1").map(|v| v.to_string()), Ok("-2.5".to_string()));
+        assert_eq!(eval_str("3.0").map(|v| v.to_string()), Ok("3.0".to_string()));
+        assert_eq!(eval_str("1.2.3").map_err(|e| e.to_string()), Err("Error[E0003]: Extra token after expression: NUM(0.3)".to_string()));
+        assert_eq!(eval_str("1 + .").map_err(|e| e.to_string()), Err("Error[E0022]: Invalid number '.' at index 4".to_string()));
+        // an exponent, as floats print
+        assert_eq!(eval_str("2.220446049250313e-16"), Ok(Value::Float(f64::EPSILON)));
+        assert_eq!(eval_str("1.5E3 + 2e+1 - 1e300 * 10").map(|v| v.to_string()), Ok("-1e301".to_string()));
+        assert_eq!(eval_str("e3 = 2; 2e3 - 2 * e3").map(|v| v.to_string()), Ok("1996.0".to_string()));
+        for x in [f64::EPSILON, 1e-300, -6.02e23, f64::MAX, f64::NAN] {
+            let v = Value::Float(x);
+            assert_eq!(eval_str(&v.literal()).map(|v| v.to_string()), Ok(v.to_string()));
+        }
+        assert_eq!(eval_str("popcount(2.5)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of popcount() out of range: 2.5".to_string()));
+    }
+
+    #[test]
+    fn test_bignum(){
+        let big = |s: &str| BigInt::parse(s).unwrap();
+        assert_eq!((big("99999999") * big("99999999")).to_string(), "9999999800000001");
+        assert_eq!((big("-18446744073709551616") / big("4294967296")).to_string(), "-4294967296");
+        assert_eq!((big("12345678901234567890") - big("12345678901234567891")).to_string(), "-1");
+        assert_eq!(big("-0"), BigInt::default());
+        assert_eq!(big("2147483647").to_i32(), Some(i32::MAX));
+        assert_eq!(big("2147483648").to_i32(), None);
+        // mixed with a BigInt, i32 operands are widened and results that fit shrink back
+        assert_eq!(&Value::Big(big("2147483648")) - &Value::Int(1), Ok(Value::Int(i32::MAX)));
+        assert_eq!((&Value::Big(big("-2147483649")) * &Value::Int(-2)).unwrap().to_string(), "4294967298");
+        assert_eq!(&Value::Big(big("4294967296")) / &Value::Float(2.0), Ok(Value::Float(2147483648.0)));
+        assert_eq!(Value::Big(big("-4294967297")).floor_div(&Value::Int(2)).unwrap().to_string(), "-2147483649");
+        assert_eq!(Value::Big(big("-4294967296")).to_radix(16), "-0x100000000");
+        assert_eq!((Value::Int(255).to_radix(2), Value::Int(0).to_radix(8), Value::Float(0.5).to_radix(16)), ("0b11111111".to_string(), "0o0".to_string(), "0.5".to_string()));
+        assert_eq!(eval_str("100000000000000000000").map_err(|e| e.to_string()), Err("Error[E0022]: Invalid number '100000000000000000000' at index 0".to_string()));
+    }
+
+    #[test]
+    fn test_decimal(){
+        let dec = |s: &str| Value::Dec(Decimal::parse(s).unwrap());
+        assert_eq!(&dec("0.1") + &dec("0.2"), Ok(dec("0.3")));
+        assert_eq!((&dec("0.1") + &dec("0.2")).unwrap().to_string(), "0.3");
+        assert_eq!((&dec("19.99") * &Value::Int(3)).unwrap().to_string(), "59.97");
+        assert_eq!((&dec("2.50") * &Value::Int(4)).unwrap().to_string(), "10.0");
+        assert_eq!((&dec("2.0") / &Value::Int(3)).unwrap().to_string(), "0.6666666666666666666666666667");
+        assert_eq!((&dec("-1") / &dec("8")).unwrap().to_string(), "-0.125");
+        assert_eq!(dec("-7.5").floor_div(&Value::Int(2)).unwrap().to_string(), "-4.0");
+        assert_eq!(dec("1.5").pow(&Value::Int(-2)).unwrap().to_string(), "0.4444444444444444444444444444");
+        assert_eq!(dec("1.1").pow(&Value::Int(13)).unwrap().to_string(), "3.4522712143931");
+        assert_eq!(dec("-1.0").pow(&Value::Int(1023)), Ok(dec("-1")));
+        assert_eq!(dec("1.0").pow(&Value::Int(-100000)).map_err(|e| e.to_string()), Err("Error[E0045]: Decimal exponent out of range: 1.0 ^ -100000, at most 1024".to_string()));
+        assert_eq!(dec("2.25").pow(&dec("0.5")), Ok(Value::Float(1.5)));
+        assert_eq!(&dec("0.1") + &Value::Float(0.2), Ok(Value::Float(0.1 + 0.2)));
+        assert!(dec("2.0") == Value::Int(2) && dec("0.30") < dec("0.31") && dec("-0.5") < Value::Int(0));
+        assert_eq!(dec("1.5").floor_div(&Value::Int(0)).map_err(|e| e.to_string()), Err("Error[E0031]: Division by zero: 1.5 // 0".to_string()));
+        assert_eq!((&dec(&"9".repeat(20)) * &dec(&"9".repeat(20))).map_err(|e| e.to_string()).err(),
+            Some(format!("Error[E0040]: Decimal overflow: {0}.0 * {0}.0", "9".repeat(20))));
+        assert_eq!([Decimal::parse("1.2.3"), Decimal::parse(""), Decimal::parse("1e3")], [None, None, None]);
+    }
+
+    #[test]
+    fn test_notation(){
+        let (eng, si) = (Notation { eng: true, ..Notation::default() }, Notation { si: true, ..Notation::default() });
+        let sigfig = |n: usize, base: Notation| Notation { sigfig: Some(n), ..base };
+        assert_eq!(eng.show(&Value::Int(12300)), "12.3e3");
+        assert_eq!(eng.show(&Value::Float(-0.000045)), "-45e-6");
+        assert_eq!(eng.show(&Value::Float(999.5)), "999.5");
+        assert_eq!(si.show(&Value::Float(12300.0)), "12.3k");
+        assert_eq!(si.show(&Value::Float(4.7e-9)), "4.7n");
+        assert_eq!(si.show(&Value::Float(1e30)), "1e30");
+        assert_eq!(sigfig(3, Notation::default()).show(&Value::Float(std::f64::consts::PI * 1000.0)), "3140");
+        assert_eq!(sigfig(3, Notation::default()).show(&Value::Float(0.000123456)), "0.000123");
+        assert_eq!(sigfig(3, Notation::default()).show(&Value::Int(7)), "7.00");
+        assert_eq!(sigfig(2, si).show(&Value::Float(999_999.0)), "1.0M");
+        assert_eq!(sigfig(3, eng).show(&eval_str("[1500, 2 km]").unwrap()), "[1.50e3, 2.00 km]");
+        assert_eq!(Notation::default().show(&Value::Float(0.1)), "0.1");
+        // an exponent reads back in, 12.3e3 is 12300
+        assert_eq!(eval_str(&eng.show(&Value::Int(12300))), Ok(Value::Float(12300.0)));
+        assert_eq!(eval_str(&eng.show(&Value::Float(-0.000045))), Ok(Value::Float(-0.000045)));
+        assert_eq!(eval_str(&sigfig(3, eng).show(&Value::Int(1500))), Ok(Value::Float(1500.0)));
+    }
+
+    #[test]
+    fn test_locale(){
+        let de = Locale::from_name("de").unwrap();
+        let tokens = |input: &str| TokenParser { locale: de, ..TokenParser::new(input.to_string()) }.collect::<Result<Vec<Token>, CalcError>>();
+        assert_eq!(tokens("1.234,56"), Ok(vec![Token::NUM(Value::Float(1234.56))]));
+        assert_eq!(tokens("max(1, 5)").unwrap().len(), 6);
+        assert_eq!(tokens("1.23,4"), Err(CalcError::InvalidNumber("1.23,4".to_string(), 0)));
+        let en = Locale::from_name("en").unwrap();
+        let tokens = |input: &str| TokenParser { locale: en, ..TokenParser::new(input.to_string()) }.collect::<Result<Vec<Token>, CalcError>>();
+        assert_eq!(tokens("1,234,567.5"), Ok(vec![Token::NUM(Value::Float(1234567.5))]));
+        assert_eq!(tokens("max(1, 234)").unwrap().len(), 6);
+        assert_eq!(tokens("max(1,2)").unwrap().len(), 6);
+        assert_eq!(tokens("1,2345"), Err(CalcError::InvalidNumber("1,2345".to_string(), 0)));
+        let show = |name: &str, v: Value| Notation { locale: Locale::from_name(name).unwrap(), ..Notation::default() }.show(&v);
+        assert_eq!(show("de", Value::Float(-1234567.5)), "-1.234.567,5");
+        assert_eq!(show("en", eval_str("[1234, 2.5 km]").unwrap()), "[1,234, 2.5 km]");
+        assert_eq!(show("fr", Value::Int(12345)), "12\u{202F}345");
+        assert_eq!(Notation { locale: de, sigfig: Some(3), ..Notation::default() }.show(&Value::Float(0.12345)), "0,123");
+    }
+
+    #[test]
+    fn test_mod(){
+        assert_eq!(eval_str("7 % 3"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("-7 % 3"), Ok(Value::Int(2)));
+        assert_eq!(eval_str("7 % (-3)"), Ok(Value::Int(-2)));
+        assert_eq!(eval_str("7.5 % 2"), Ok(Value::Float(1.5)));
+        assert_eq!(eval_str("2 * 7 % 4 + 1"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("x = 10; x % (2 + 1)"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("7 % 0").map_err(|e| e.to_string()), Err("Error[E0031]: Division by zero: 7 % 0".to_string()));
+        // a percentage when no operand follows, 7 % -3 included
+        assert_eq!(eval_str("7 % -3"), Ok(Value::Float(0.07 - 3.0)));
+        assert_eq!(eval_str("200 * 15%"), Ok(Value::Float(30.0)));
+        assert_eq!(eval_str("200 + 15% - 10"), Ok(Value::Float(220.0)));
+        let n = parse("2 * (7 % 4) + 50%").unwrap();
+        assert_eq!((n.repr(), infix(&n), latex(&n)), ("<<2*(<7%4>)>+<50%>>".to_string(), "2 * (7 % 4) + 50%".to_string(), "2 \\cdot (7 \\bmod 4) + 50\\%".to_string()));
+        assert_eq!(sexpr(&n), "(+ (* 2 (% 7 4)) (% 50))");
+    }
+
+    #[test]
+    fn test_pow(){
+        let n = parse("2 ^ 3 ^ 2").unwrap();
+        assert_eq!((n.repr(), n.eval()), ("<2^<3^2>>".to_string(), Ok(Value::Int(512))));
+        assert_eq!(eval_str("-2 ^ 2 * 3 + 1"), Ok(Value::Int(-11)));
+        assert_eq!(eval_str("2 ^ -1"), Ok(Value::Float(0.5)));
+        assert_eq!(eval_str("4 ^ 0.5"), Ok(Value::Float(2.0)));
+        assert_eq!(BigInt::from(3).pow(40).to_string(), "12157665459056928801");
+        assert_eq!(grouping(&parse("1 + 2 * 3 ^ 2 ^ 2").unwrap()), "1 + (2 * (3 ^ (2 ^ 2)))");
+    }
+
+    #[test]
+    fn test_unary_minus(){
+        let n = parse("-(2 + 3) * --4").unwrap();
+        assert_eq!((n.repr(), n.eval()), ("<<-(<2+3>)>*<-<-4>>>".to_string(), Ok(Value::Int(-20))));
+        assert_eq!(n.children()[0].span(), Span { start: 0, end: 8 });
+        assert_eq!(eval_str("2 ^ -1 - -roundto(7, 5)"), Ok(Value::Float(5.5)));
+        assert_eq!(grouping(&parse("-2 ^ 2").unwrap()), "-2 ^ 2");
+        assert_eq!(eval_str("3 * -").map_err(|e| e.to_string()), Err("Error[E0004]: Unexpected end of input".to_string()));
+    }
+
+    #[test]
+    fn test_variables(){
+        let mut env = Env::default();
+        assert_eq!(eval_str_in("x = y = 3 + 4", &mut env), Ok(Value::Int(7)));
+        assert_eq!(eval_str_in("x * 2 - y", &mut env), Ok(Value::Int(7)));
+        assert_eq!(eval_str_in("x = x + 1", &mut env), Ok(Value::Int(8)));
+        assert_eq!(env.get("x"), Some(&Value::Int(8)));
+        env.set("ans", Value::Int(8));
+        assert_eq!(env.undo(), Some(("x".to_string(), Some(Value::Int(7)))));
+        assert_eq!(env.undo(), Some(("x".to_string(), None)));
+        assert_eq!((env.get("x"), env.get("y"), env.get("ans")), (None, Some(&Value::Int(7)), Some(&Value::Int(8))));
+        assert_eq!(compile(&parse("y = 1").unwrap()).run(&mut env), Ok(Value::Int(1)));
+        assert_eq!(env.undo(), Some(("y".to_string(), Some(Value::Int(7)))));
+        assert_eq!(env.undo(), Some(("y".to_string(), None)));
+        assert_eq!(env.undo(), None);
+        assert_eq!(eval_str("z + 1").map_err(|e| e.to_string()), Err("Error[E0024]: Unknown variable: z".to_string()));
+        assert_eq!(parse("x + 1 = 2").err(), Some(CalcError::InvalidAssignment("<x+1>".to_string())));
+        let n = parse("total = 2 * (a / 3)").unwrap();
+        assert_eq!((n.repr(), grouping(&n)), ("<total=<2*(<a/3>)>>".to_string(), "total = (2 * (a / 3))".to_string()));
+        let mut lints = Vec::new();
+        lint(&n, &mut lints);
+        assert!(lints.is_empty());
+    }
+
+    #[test]
+    fn test_math_functions(){
+        assert_eq!(eval_str("sqrt(16) + abs(-2.5)"), Ok(Value::Float(6.5)));
+        assert_eq!(eval_str("abs(-3) * max(2, 7) - min(4, 2.5)"), Ok(Value::Float(18.5)));
+        assert_eq!(eval_str("max(2, 7)"), Ok(Value::Int(7)));
+        assert_eq!(eval_str("sin(0) + cos(0) + log(1)"), Ok(Value::Float(1.0)));
+        assert!((eval_str("tan(1) - sin(1) / cos(1)").unwrap().float().unwrap()).abs() < 1e-12);
+        assert_eq!(eval_str("sqrt(-1)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of sqrt() out of range: -1".to_string()));
+        assert_eq!(eval_str("log(0)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of log() out of range: 0".to_string()));
+    }
+
+    #[test]
+    fn test_radix_literals(){
+        assert_eq!(eval_str("0xFF + 0b1 * 2"), Ok(Value::Int(257)));
+        assert_eq!(eval_str("0o17 - 0x7fffffff"), Ok(Value::Int(-2147483632)));
+        assert_eq!(eval_str("0x").map_err(|e| e.to_string()), Err("Error[E0022]: Invalid number '0x' at index 0".to_string()));
+        assert_eq!(eval_str("1 + 0b102").map_err(|e| e.to_string()), Err("Error[E0022]: Invalid number '0b102' at index 4".to_string()));
+        assert_eq!(BigInt::parse_radix("ffffffffff", 16).unwrap().to_string(), "1099511627775");
+    }
+
+    #[test]
+    fn test_digit_separators(){
+        assert_eq!(eval_str("1_000_000 * 3"), Ok(Value::Int(3000000)));
+        assert_eq!(eval_str("0xFF_FF + 1_0.2_5"), Ok(Value::Float(65545.25)));
+        assert_eq!(eval_str("1__000").map_err(|e| e.to_string()), Err("Error[E0025]: Misplaced '_' in number '1__000' at index 0".to_string()));
+        assert_eq!(eval_str("2 * 100_").map_err(|e| e.to_string()), Err("Error[E0025]: Misplaced '_' in number '100_' at index 4".to_string()));
+        assert_eq!(eval_str("1_.5").map_err(|e| e.to_string()), Err("Error[E0025]: Misplaced '_' in number '1_.5' at index 0".to_string()));
+        assert_eq!(eval_str("0b_1").map_err(|e| e.to_string()), Err("Error[E0025]: Misplaced '_' in number '0b_1' at index 0".to_string()));
+    }
+
+    #[test]
+    fn test_constants(){
+        assert_eq!(eval_str("2 * pi * 10"), Ok(Value::Float(std::f64::consts::PI * 20.0)));
+        assert_eq!(eval_str("tau - 2 * pi + log(e)"), Ok(Value::Float(1.0)));
+        assert_eq!(eval_str("-inf"), Ok(Value::Float(f64::NEG_INFINITY)));
+        assert_eq!(parse("2 * pi").unwrap().repr(), "<2*pi>");
+        let mut env = Env::default();
+        assert_eq!(eval_str_in("e = 3", &mut env), Ok(Value::Int(3)));
+        assert_eq!(eval_str_in("e * 2", &mut env), Ok(Value::Int(6)));
+    }
+
+    #[test]
+    fn test_comparisons(){
+        assert_eq!(eval_str("1 + 2 == 3"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("2 * 3 < 5 || 7 // 2 >= 3.5"), Ok(Value::Int(0)));
+        assert_eq!(eval_str("1 < 2 && 2 <= 2 && 3 != 4 && !(5 > 6)"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("!0 + !7"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("2 == 2.0"), Ok(Value::Int(1)));
+        // short-circuit, the division is never evaluated
+        assert_eq!(eval_str("0 && 1 / 0"), Ok(Value::Int(0)));
+        assert_eq!(eval_str("2 || 1 / 0"), Ok(Value::Int(1)));
+        assert_eq!(parse("a = 1 || 2 && !3 == 4").unwrap().repr(), "<a=<1||<2&&<<!3>==4>>>>");
+        assert!(Value::Big(BigInt::from(-1 << 40)) < Value::Int(0));
+        assert!(Value::Big(BigInt::from(1 << 41)) > Value::Big(BigInt::from(1 << 40)));
+        assert_eq!(explain(&parse("0 && 1 + 2").unwrap()).unwrap(), vec!["0 && … → 0"]);
+    }
+
+    #[test]
+    fn test_conditional(){
+        let mut env = Env::default();
+        assert_eq!(eval_str_in("x = -4", &mut env), Ok(Value::Int(-4)));
+        assert_eq!(eval_str_in("x > 0 ? x : -x", &mut env), Ok(Value::Int(4)));
+        assert_eq!(eval_str_in("x < -5 ? 1 : x < 0 ? 2 : 3", &mut env), Ok(Value::Int(2)));
+        assert_eq!(eval_str_in("y = x ? 10 / 2 : 1 / 0", &mut env), Ok(Value::Int(5)));
+        assert_eq!(eval_str("max(1 ? 2 : 3, 0)"), Ok(Value::Int(2)));
+        assert_eq!(parse("a ? b : c ? d : e").unwrap().repr(), "<a?b:<c?d:e>>");
+        assert_eq!(grouping(&parse("1 + 1 ? (0 ? 2 : 3) : 4").unwrap()), "1 + 1 ? (0 ? 2 : 3) : 4");
+        assert_eq!(eval_str("1 ? 2").map_err(|e| e.to_string()), Err("Error[E0026]: Expected ':' after the first branch of ?".to_string()));
+    }
+
+    #[test]
+    fn test_bitwise(){
+        assert_eq!(eval_str("0xF0 | 0x0F & 0b0110"), Ok(Value::Int(0xF6)));
+        assert_eq!(eval_str("1 << 4 + 1"), Ok(Value::Int(32)));
+        assert_eq!(eval_str("-16 >> 2"), Ok(Value::Int(-4)));
+        assert_eq!(eval_str("6 xor 3 == 5 ^^ 0"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("~0 & 0xFF"), Ok(Value::Int(255)));
+        assert_eq!(eval_str("1 < 2 < 3"), Ok(Value::Int(1)));
+        assert_eq!(parse("a & b | c xor d << 1").unwrap().repr(), "<<a&b>|<c xor <d<<1>>>");
+        assert_eq!(eval_str("1.5 & 1").map_err(|e| e.to_string()), Err("Error[E0027]: Operator & needs integers, got 1.5".to_string()));
+        assert_eq!(eval_str("1 << 32").map_err(|e| e.to_string()), Err("Error[E0028]: Shift count out of range: 32".to_string()));
+    }
+
+    #[test]
+    fn test_percent(){
+        assert_eq!(eval_str("200 * 15%"), Ok(Value::Float(30.0)));
+        assert_eq!(eval_str("100 + 10%"), Ok(Value::Float(110.0)));
+        assert_eq!(eval_str("80 - 25% - 10"), Ok(Value::Float(50.0)));
+        assert_eq!(eval_str("(1 + 4)%"), Ok(Value::Float(0.05)));
+        assert_eq!(parse("100 + 10%").unwrap().repr(), "<100+<10%>>");
+        assert_eq!(explain(&parse("100 + 10%").unwrap()).unwrap(), vec!["10% → 0.1", "100 + 10% → 110.0"]);
+    }
+
+    #[test]
+    fn test_compile(){
+        let mut rng = Rng(7);
+        for _ in 0..200 {
+            let e = random_expr(&mut rng, 5, &['+', '-', '*', '/'], 50);
+            assert_eq!(compile(&e).run(&mut Env::default()), e.eval(), "{}", e.repr());
+        }
+        let e = parse("r = (x > 0 ? x : -x) * 2 + max(x, 10%)").unwrap();
+        let program = compile(&e);
+        for x in [-3, 0, 4] {
+            let mut env = Env::default();
+            env.set("x", Value::Int(x));
+            let mut expected = env.clone();
+            assert_eq!(program.run(&mut env), e.eval_in(&mut expected));
+            assert_eq!(env.get("r"), expected.get("r"));
+        }
+        let mut env = Env::default();
+        assert_eq!(compile(&parse("0 && 1 / 0 || !(2 < 1) && 5").unwrap()).run(&mut env), Ok(Value::Int(1)));
+        assert_eq!(compile(&parse("100 - 10% + ~0 + (1 << 3)").unwrap()).run(&mut env), Ok(Value::Float(97.0)));
+        assert_eq!(compile(&parse("y + 1").unwrap()).run(&mut env).map_err(|e| e.to_string()), Err("Error[E0024]: Unknown variable: y".to_string()));
+        assert_eq!(compile(&parse("x = 0 || max(2, 5%)").unwrap()).disassemble(), vec![
+            "constants:", "  #0   0", "  #1   2", "  #2   5", "  #3   1", "code:",
+            "   0  push    #0", "   1  jumpif  true -> 8", "   2  push    #1", "   3  push    #2", "   4  unary   %",
+            "   5  call    max/2", "   6  truth", "   7  jump    -> 9", "   8  push    #3", "   9  store   x", "  10  end",
+        ]);
+    }
+
+    #[test]
+    fn test_simplify(){
+        let simplified = |input: &str| parse(input).unwrap().simplify().repr();
+        assert_eq!(simplified("2*3+x"), "<6+x>");
+        assert_eq!(simplified("(x * 1 + 0) * (4 - 3)"), "x");
+        assert_eq!(simplified("-(-x) - 0"), "x");
+        assert_eq!(simplified("a = 2 ^ 3 * b"), "<a=<8*b>>");
+        assert_eq!(simplified("max(2, 3) * 2 - 6 ? 1 / 0 : y"), "y");
+        assert_eq!(simplified("1 / 0 + x"), "<<1/0>+x>");
+        assert_eq!(simplified("2 * pi"), "<2*pi>");
+        assert_eq!(simplified("100 + 10% + x"), "<110.0+x>");
+        assert_eq!(simplified("0 + 10% + x * 1.0"), "<0.0+<x*1.0>>");
+        assert_eq!(simplified("x / 1 * 1"), "<x/1>");
+        let mut rng = Rng(11);
+        for _ in 0..100 {
+            let e = random_expr(&mut rng, 4, &['+', '-', '*', '/'], 3);
+            assert_eq!(e.simplify().eval(), e.eval(), "{}", e.repr());
+        }
+    }
+
+    #[test]
+    fn test_derivative(){
+        let derivative = |input: &str| parse(input).unwrap().derivative("x").map(|d| infix(&d.simplify()));
+        assert_eq!(derivative("x*x + 3*x"), Ok("2 * x + 3".to_string()));
+        assert_eq!(derivative("y * x ^ 3 - 5"), Ok("y * (3 * x ^ 2)".to_string()));
+        assert_eq!(derivative("(x - 1) ^ 2 / 2 - (-x) ^ 3"), Ok("2 * (x - 1) / 2 - 3 * (-x) ^ 2 * -1".to_string()));
+        assert_eq!(derivative("sin(2 * x)"), Ok("cos(2 * x) * 2".to_string()));
+        assert_eq!(derivative("1 / x"), Ok("-1 / x ^ 2".to_string()));
+        assert_eq!(derivative("pi"), Ok("0".to_string()));
+        assert_eq!(derivative("e^x"), Ok("e ^ x".to_string()));
+        assert_eq!(derivative("2 ^ (3 * x)"), Ok("2 ^ (3 * x) * 0.6931471805599453 * 3".to_string()));
+        assert_eq!(derivative("x ^ x"), Ok("x ^ x * (log(x) + x * (1 / x))".to_string()));
+        assert_eq!(derivative("y / 2"), Ok("0".to_string()));
+        assert_eq!(derivative("x > 1").map_err(|e| e.to_string()), Err("Error[E0029]: Cannot differentiate >".to_string()));
+        let slope = |input: &str, at: f64| {
+            let mut env = Env::default();
+            env.set("x", Value::Float(at));
+            return parse(input).unwrap().derivative("x").unwrap().simplify().eval_in(&mut env).unwrap().float().unwrap();
+        };
+        assert!((slope("2 ^ x", 3.0) - 8.0 * 2f64.ln()).abs() < 1e-9);
+        assert!((slope("sqrt(x) * cos(x)", 1.0) - (0.5 * 1f64.cos() - 1f64.sin())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_autodiff(){
+        let autodiff = |input: &str, at: f64| parse(input).unwrap().autodiff("x", at).map_err(|e| e.to_string());
+        assert_eq!(autodiff("x*x + 3*x", 2.0), Ok((10.0, 7.0)));
+        assert_eq!(autodiff("y * x", 2.0), Err("Error[E0024]: Unknown variable: y".to_string()));
+        assert_eq!(autodiff("x > 0 ? x ^ 2 : -x", -3.0), Ok((3.0, -1.0)));
+        assert_eq!(autodiff("x > 0 ? x ^ 2 : -x", 3.0), Ok((9.0, 6.0)));
+        assert_eq!(autodiff("abs(x - 1) / 2", 0.0), Ok((0.5, -0.5)));
+        let (v, dv) = autodiff("2 ^ x * sqrt(x) * cos(x)", 1.0).unwrap();
+        let slope = parse("2 ^ x * sqrt(x) * cos(x)").unwrap().derivative("x").unwrap();
+        let mut env = Env::default();
+        env.set("x", Value::Float(1.0));
+        assert!((v - 2.0 * 1f64.cos()).abs() < 1e-12);
+        assert!((dv - slope.eval_in(&mut env).unwrap().float().unwrap()).abs() < 1e-12);
+        assert_eq!(autodiff("sqrt(x)", -1.0), Err("Error[E0016]: Argument 1 of sqrt() out of range: -1".to_string()));
+        assert_eq!(autodiff("x // 2", 1.0), Err("Error[E0029]: Cannot differentiate //".to_string()));
+    }
+
+    #[test]
+    fn test_formats(){
+        let render = |format: &str, input: &str| Format::from_name(format).unwrap().render(&parse(input).unwrap());
+        assert_eq!(render("repr", "1 * (2 + 3)"), "<1*(<2+3>)>");
+        assert_eq!(render("rpn", "1 * (2 + 3)"), "1 2 3 + *");
+        assert_eq!(render("latex", "1 * (2 + 3)"), "1 \\cdot (2 + 3)");
+        assert_eq!(render("sexpr", "1 * (2 + 3)"), "(* 1 (+ 2 3))");
+        assert_eq!(render("infix", "((1 * 2)) + (3 - (4 - 5))"), "1 * 2 + (3 - (4 - 5))");
+        assert_eq!(render("infix", "(2 ^ 3) ^ 2 - (-1) ^ 2"), "(2 ^ 3) ^ 2 - (-1) ^ 2");
+        assert_eq!(render("rpn", "-x ? max(1, 2) : 3%"), "x neg 1 2 max 3 % ?:");
+        assert_eq!(render("sexpr", "a = -sqrt(2)"), "(= a (neg (sqrt 2)))");
+        assert_eq!(render("latex", "sqrt(x ^ 2 + 1) / (2 * pi) <= rate"), "\\frac{\\sqrt{x^{2} + 1}}{2 \\cdot \\pi} \\leq \\mathrm{rate}");
+        assert_eq!(Format::from_name("json"), None);
+        // the minimal form parses back to the same tree
+        let mut rng = Rng(5);
+        for _ in 0..100 {
+            let e = random_expr(&mut rng, 4, &['+', '-', '*', '/'], 3);
+            assert_eq!(parse(&infix(&e)).map(|n| grouping(&n)), Ok(grouping(&e)), "{}", e.repr());
+        }
+    }
+
+    #[test]
+    fn test_visitor(){
+        struct Vars(Vec<String>, usize);
+        impl Visitor for Vars {
+            fn visit(&mut self, n: &Expr) {
+                match n {
+                    Expr::Var(name, _) if !self.0.contains(name) => self.0.push(name.clone()),
+                    Expr::Bin(..) => self.1 += 1,
+                    _ => {},
+                }
+                walk(self, n);
+            }
+        }
+        let mut vars = Vars(Vec::new(), 0);
+        vars.visit(&parse("a = max(x, -y) * (x + 1) ? 2 : z").unwrap());
+        assert_eq!((vars.0, vars.1), (vec!["a".to_string(), "x".to_string(), "y".to_string(), "z".to_string()], 2));
+
+        struct Substitute(&'static str, Value);
+        impl Folder for Substitute {
+            fn fold(&mut self, n: Expr) -> Expr {
+                match n {
+                    Expr::Var(name, span) if name == self.0 => Expr::Num(self.1.clone(), span),
+                    n => fold_children(self, n),
+                }
+            }
+        }
+        let n = Substitute("x", Value::Int(3)).fold(parse("x * x - abs(-x) + y").unwrap());
+        assert_eq!(n.repr(), "<<<3*3>-abs(<-3>)>+y>");
+    }
+
+    #[test]
+    fn test_ast_json(){
+        for input in ["1 + 2.5 * -(3 - x)", "a = max(1, 2) ? ~4 xor 1 : 10% << 2", "y ^ 0.5 >= 0.000001 || !inf"] {
+            let n = parse(input).unwrap();
+            let loaded = Expr::from_json(&n.to_json()).unwrap();
+            assert_eq!((loaded.repr(), loaded.span()), (n.repr(), n.span()), "{}", n.to_json());
+        }
+        assert_eq!(parse("-x").unwrap().to_json(), r#"{"op": "neg", "args": [{"op": "var", "name": "x", "span": [1, 2]}], "span": [0, 2]}"#);
+        let hand_written = r#"{"op": "*", "args": [{"op": "num", "value": "6"}, {"op": "sqrt", "args": [{"op": "num", "value": "4.0"}]}]}"#;
+        assert_eq!(Expr::from_json(hand_written).map(|n| n.eval()), Ok(Ok(Value::Float(12.0))));
+        assert_eq!(Expr::from_json(r#"{"op": "sqrt", "args": []}"#).err(), Some("sqrt expects 1 operand(s), got 0".to_string()));
+        assert_eq!(Expr::from_json(r#"{"op": "%%"}"#).err(), Some("Unknown op \"%%\"".to_string()));
+        assert_eq!(Expr::from_json("{\"op\": ").err(), Some("Unexpected end of JSON".to_string()));
+
+        let tokens = TokenParser::new("x = 0x1F & $HOME".to_string()).map(Result::unwrap).collect::<Vec<Token>>();
+        for t in &tokens {
+            assert_eq!(Token::from_json(&t.to_json()).as_ref(), Ok(t));
+        }
+        assert_eq!(tokens[2].to_json(), r#"{"token": "NUM", "value": "31"}"#);
+        assert_eq!(Json::parse(r#"[1, "a\"é", {"k": null}, true]"#), Ok(Json::Arr(vec![
+            Json::Num(1.0), Json::Str("a\"é".to_string()), Json::Obj(vec![("k".to_string(), Json::Null)]), Json::Bool(true),
+        ])));
+    }
+
+    #[test]
+    fn test_error_spans(){
+        let span = |input: &str| parse_spanned(input).err().map(|(e, s)| (e.code(), s.start, s.end));
+        assert_eq!(span("(1 + 2"), Some(("E0008", 6, 7)));
+        assert_eq!(span("(1 + 2   "), Some(("E0008", 6, 7)));
+        assert_eq!(span("max(1, 2 y)"), Some(("E0008", 9, 10)));
+        assert_eq!(span("1 + * 2"), Some(("E0009", 4, 5)));
+        assert_eq!(span("2 * "), Some(("E0004", 3, 4)));
+        assert_eq!(span("1 y"), Some(("E0003", 2, 3)));
+        assert_eq!(span("1 @ 2"), Some(("E0001", 2, 3)));
+        assert_eq!(span("1_ + 0x"), Some(("E0025", 0, 2)));
+        assert_eq!(span("foo(1) + 1"), Some(("E0014", 0, 3)));
+        assert_eq!(span("1 + max(1)"), Some(("E0015", 4, 10)));
+        assert_eq!(span("1 + 2 = 3"), Some(("E0023", 0, 5)));
+        assert_eq!(span("x ? 1 y"), Some(("E0026", 6, 7)));
+        assert_eq!(span(" "), Some(("E0021", 0, 1)));
+        assert_eq!(span("1 + (2)"), None);
+        assert_eq!(hint("E0008"), Some("expected ')' here"));
+        assert_eq!(hint("E0024"), None);
+    }
+
+    #[test]
+    fn test_error_recovery(){
+        let errors = |input: &str| parse_recovering(input).err().unwrap_or_default()
+            .into_iter().map(|(e, s)| (e.code(), s.start)).collect::<Vec<(&str, usize)>>();
+        assert_eq!(errors("1 + * 2 ) + ("), vec![("E0009", 4), ("E0003", 8), ("E0004", 13), ("E0008", 13)]);
+        assert_eq!(errors("(1 @ 2 ` 3"), vec![("E0001", 3), ("E0001", 7), ("E0008", 10)]);
+        assert_eq!(errors("sqrt 4 + foo(1) + max(1) ? 2"), vec![("E0017", 5), ("E0014", 9), ("E0015", 18), ("E0026", 28)]);
+        assert_eq!(errors("1 + 2 = 3 ) 4"), vec![("E0023", 0)]);
+        assert_eq!(errors("  2 * (3 + 4)"), vec![]);
+        assert_eq!(parse_recovering("2 * (3 + 4)").map(|n| n.eval()), Ok(Ok(Value::Int(14))));
+    }
+
+    #[test]
+    fn test_streaming_tokens(){
+        let spans = |mut p: TokenParser| {
+            let mut out = Vec::new();
+            while let Some(t) = p.next() {
+                out.push((t, p.span));
+            }
+            return out;
+        };
+        let input = "  max(0x1F, 2 5_0.5) ^^ VII * $HOME_DIR1 <= é";
+        let from_string = spans(TokenParser::new(input.to_string()));
+        assert_eq!(from_string, spans(TokenParser::from_reader(std::io::Cursor::new(input.as_bytes().to_vec()))));
+        assert_eq!(from_string[4], (Ok(Token::NUM(Value::Float(250.5))), Span { start: 12, end: 19 }));
+        assert_eq!(from_string.last().unwrap().0, Err(CalcError::InvalidToken('é', 44)));
+        assert_eq!(TokenParser::from_reader(&b"1 \xFF"[..]).last(), Some(Err(CalcError::InvalidToken('\u{FFFD}', 2))));
+        // never collected, only as much as one token is held
+        let endless = "12 + ".chars().cycle().take(1_000_000);
+        assert_eq!(TokenParser::from_chars(endless).filter(|t| *t == Ok(Token::ADD)).count(), 200_000);
+    }
+
+    #[test]
+    fn test_unicode_operators(){
+        assert_eq!(eval_str("6 × 7 − 2 ÷ 2"), Ok(Value::Int(41)));
+        assert_eq!(eval_str("3² + 2³ · 10"), Ok(Value::Int(89)));
+        assert_eq!(eval_str("-2² + 10⁻¹ * 10"), Ok(Value::Float(-3.0)));
+        assert_eq!(eval_str("2¹⁰"), Ok(Value::Int(1024)));
+        assert_eq!(eval_str("\u{FEFF}1\u{00A0}+\u{2003}2\u{200B}\t"), Ok(Value::Int(3)));
+        assert_eq!(parse("x² ^ 2").unwrap().repr(), "<<x^2>^2>");
+        assert_eq!(parse("2 ⁻").err(), Some(CalcError::InvalidNumber("-".to_string(), 2)));
+        assert_eq!(Token::from_json(&Token::SUP(Value::Int(2)).to_json()), Ok(Token::SUP(Value::Int(2))));
+    }
+
+    #[test]
+    fn test_overflow(){
+        assert_eq!(eval_str("2147483647 + 1").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: 2147483647 + 1".to_string()));
+        assert_eq!(eval_str("-(-2147483647 - 1)").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: -(-2147483648)".to_string()));
+        assert_eq!(eval_str("3 ^ 40").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: 3 ^ 40".to_string()));
+        assert_eq!(eval_str("(-2147483647 - 1) // -1").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: -2147483648 // -1".to_string()));
+        assert_eq!(eval_str("2147483648").map_err(|e| e.to_string()), Err("Error[E0022]: Invalid number '2147483648' at index 0".to_string()));
+        assert_eq!(Overflow::Checked.apply(1, 0, '\\').map_err(|e| e.to_string()), Err("Error[E0031]: Division by zero: 1 // 0".to_string()));
+
+        let (wrapping, saturating) = (Overflow::from_name("wrapping").unwrap(), Overflow::from_name("saturating").unwrap());
+        assert_eq!(wrapping.apply(i32::MAX, 1, '+'), Ok(i32::MIN));
+        assert_eq!(wrapping.apply(0, i32::MIN, '_'), Ok(i32::MIN));
+        assert_eq!(wrapping.apply(3, 40, '^'), Ok(3i32.wrapping_pow(40)));
+        assert_eq!(saturating.apply(i32::MIN, 2, '*'), Ok(i32::MIN));
+        assert_eq!(saturating.apply(i32::MIN, -1, '\\'), Ok(i32::MAX));
+        assert_eq!(saturating.apply(2, 40, '^'), Ok(i32::MAX));
+        assert_eq!(wrapping.literal(&BigInt::parse_radix("FFFFFFFF", 16).unwrap()), Some(-1));
+        assert_eq!(saturating.literal(&BigInt::parse("99999999999").unwrap()), Some(i32::MAX));
+        assert_eq!(Overflow::Checked.literal(&BigInt::parse("2147483648").unwrap()), None);
+    }
+
+    #[test]
+    fn test_nan_policy(){
+        let check = |policy: NanPolicy, input: &str| {
+            let n = parse(input).unwrap();
+            let operands = n.children().iter().map(|c| c.eval().unwrap()).collect::<Vec<Value>>();
+            policy.check(&n, &operands, &n.eval().unwrap()).map_err(|e| e.to_string())
+        };
+        assert_eq!(check(NanPolicy::Error, "0.0 / 0"), Err("Error[E0043]: Not a finite number: 0.0 / 0 is NaN".to_string()));
+        assert_eq!(check(NanPolicy::Error, "10.0 ^ 400"), Err("Error[E0043]: Not a finite number: 10.0 ^ 400 is inf".to_string()));
+        assert_eq!(check(NanPolicy::Error, "inf + 1"), Ok(()));
+        assert_eq!(check(NanPolicy::Error, "inf - inf"), Err("Error[E0043]: Not a finite number: inf - inf is NaN".to_string()));
+        assert_eq!(check(NanPolicy::Error, "1.5 * 2"), Ok(()));
+        assert_eq!(check(NanPolicy::Propagate, "0.0 / 0"), Ok(()));
+        assert_eq!(NanPolicy::from_name("error"), Some(NanPolicy::Error));
+        assert_eq!(NanPolicy::from_name("ignore"), None);
+    }
+
+    #[test]
+    fn test_division_by_zero(){
+        assert_eq!(eval_str("7 / (2 - 2) + 1").map_err(|e| e.to_string()), Err("Error[E0031]: Division by zero: 7 / 0".to_string()));
+        assert_eq!(eval_str("1.0 / 0"), Ok(Value::Float(f64::INFINITY)));
+        assert_eq!(Value::Int(1).arith(&Value::Big(BigInt::from(0i64)), '/').map_err(|e| e.to_string()), Err("Error[E0031]: Division by zero: 1 / 0".to_string()));
+        let n = parse("1 + 8 // (3 - 3)").unwrap();
+        let at = Err(CalcError::DivisionByZero("8 // 0".to_string(), Span { start: 6, end: 8 }));
+        assert_eq!(n.eval(), at);
+        assert_eq!(compile(&n).run(&mut Env::default()), at);
+        assert_eq!(n.eval().unwrap_err().position(), Some(6));
+    }
+
+    #[test]
+    fn test_sequence(){
+        assert_eq!(eval_str("x = 2; y = x + 1; x * y"), Ok(Value::Int(6)));
+        assert_eq!(parse("1; 2 + 3;").unwrap().repr(), "<1;<2+3>>");
+        assert_eq!(infix(&parse("a = 1;a+1").unwrap()), "a = 1; a + 1");
+        assert_eq!(parse("1;;").err(), Some(CalcError::UnexpectedToken(Token::SEMI)));
+    }
+
+    #[test]
+    fn test_comments(){
+        assert_eq!(eval_str("1 + /* two */ 2 # and no more\n"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("2 */**/ 3 /* left open"), Ok(Value::Int(6)));
+        assert_eq!(parse("# nothing but a comment").err(), Some(CalcError::EmptyInput));
+        assert_eq!(parse_spanned("1 + /* x */").err(), Some((CalcError::UnexpectedEnd, Span { start: 3, end: 4 })));
+    }
+
+    #[test]
+    fn test_units(){
+        let shown = |s: &str| eval_str(s).map(|v| v.to_string());
+        assert_eq!(shown("3 km + 200 m"), Ok("3.2 km".to_string()));
+        assert_eq!(shown("5 kg * 9.8 m/s^2"), Ok("49 kg*m/s^2".to_string()));
+        assert_eq!(shown("100 mph -> km/h"), Ok("160.9344 km/h".to_string()));
+        assert_eq!(shown("2 * 3 m^2 - 1 m * 1 m"), Ok("5 m^2".to_string()));
+        assert_eq!(eval_str("1 km / 1 m"), Ok(Value::Float(1000.0)));
+        assert_eq!(eval_str("1 kg < 3 lb"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("3 km + 2 kg").map_err(|e| e.to_string()), Err("Error[E0032]: Incompatible units: 3 km + 2 kg".to_string()));
+        assert_eq!(eval_str("3 km -> kg").map_err(|e| e.to_string()), Err("Error[E0032]: Incompatible units: 3 km -> kg".to_string()));
+        assert_eq!(parse("m = 2; 3 m").unwrap().eval(), Ok(Value::Int(6)));
+    }
+
+    #[test]
+    fn test_angles(){
+        assert_eq!(eval_str("deg(pi) + rad(180) / pi"), Ok(Value::Float(181.0)));
+        assert_eq!(eval_str("sin(rad(90))"), Ok(Value::Float(1.0)));
+    }
+
+    #[test]
+    fn test_lists(){
+        let shown = |s: &str| eval_str(s).map(|v| v.to_string());
+        assert_eq!(shown("[1, 2, 3] * 2 - 1"), Ok("[1, 3, 5]".to_string()));
+        assert_eq!(eval_str("[1, 2, 3] * [4, 5, 6]"), Ok(Value::Int(32)));
+        assert_eq!(shown("[[1, 2], [3, 4]] * [[5], [6]]"), Ok("[[17], [39]]".to_string()));
+        assert_eq!(shown("[[1, 2], [3, 4]] * [5, 6] + [1, 1]"), Ok("[18, 40]".to_string()));
+        assert_eq!(eval_str("[1, 2] + [1, 2, 3]").map_err(|e| e.to_string()), Err("Error[E0033]: Shapes do not match: [1, 2] + [1, 2, 3]".to_string()));
+        assert_eq!(eval_str("sqrt([4])").map_err(|e| e.to_string()), Err("Error[E0034]: Expected a number, got [4]".to_string()));
+        assert_eq!(parse_spanned("[1, 2").err(), Some((CalcError::UnbalancedBracket, Span { start: 5, end: 6 })));
+        let n = parse("[x, [], 2 * 3]").unwrap();
+        assert_eq!((n.repr(), infix(&n)), ("[x,[],<2*3>]".to_string(), "[x, [], 2 * 3]".to_string()));
+        let mut env = Env::default();
+        env.set("x", Value::Int(1));
+        assert_eq!(compile(&n).run(&mut env.clone()), n.eval_in(&mut env));
+    }
+
+    #[test]
+    fn test_random(){
+        let draw = || (0..20).map(|_| eval_str("rand() + randint(1, 6) * 10").unwrap().to_string()).collect::<Vec<_>>();
+        set_seed(42);
+        let first = draw();
+        set_seed(42);
+        assert_eq!(draw(), first);
+        assert!(first.iter().all(|v| (10.0..61.0).contains(&v.parse::<f64>().unwrap())));
+        assert_eq!(eval_str("randint(3, 3)"), Ok(Value::Int(3)));
+        assert_eq!(parse("rand() * (1 + 1)").unwrap().simplify().repr(), "<rand()*2>");
+        assert_eq!(parse("rand(").err(), parse("sqrt(").err());
+    }
+
+    #[test]
+    fn test_dates(){
+        let shown = |s: &str| eval_str(s).map(|v| v.to_string());
+        assert_eq!(shown("2024-03-01 + 45d"), Ok("2024-04-15".to_string()));
+        assert_eq!(shown("2024-03-01 - 1998-07-06"), Ok("9370 d".to_string()));
+        assert_eq!(shown("1 week + 2024-02-28T22:30 - 90 * 60 s"), Ok("2024-03-06T21:00:00".to_string()));
+        assert_eq!(eval_str("now() > 2024-01-01 && 1970-01-01 < 2024-01-01"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("2000-1"), Ok(Value::Int(1999)));
+        assert_eq!(eval_str("2024 - 03 - 01"), Ok(Value::Int(2020)));
+        assert_eq!(eval_str("2024-03-01 + 3").map_err(|e| e.to_string()), Err("Error[E0036]: Not defined for dates: 2024-03-01 + 3".to_string()));
+        assert_eq!(parse("2023-02-29").err(), Some(CalcError::InvalidNumber("2023-02-29".to_string(), 0)));
+        assert_eq!(parse_date("1969-12-31T23:59:59"), Some(-1));
+    }
+
+    #[test]
+    fn test_calculator(){
+        let mut calc = Calculator::builder()
+            .function("tax", 1, |v| &v[0] * &Value::Float(0.19))
+            .function("sqrt", 1, |_| Ok(Value::Int(-2)))
+            .function("sqrt", 1, |_| Ok(Value::Int(-1)))
+            .constant("vat", 0.19)
+            .build();
+        assert_eq!(calc.eval("tax(100) == 100 * vat"), Ok(Value::Int(1)));
+        assert_eq!(calc.eval("vat = 2; vat + sqrt(4)"), Ok(Value::Int(1)));
+        assert_eq!(calc.parse("tax(1, 2)").err(), Some(CalcError::ArgumentCount("tax".to_string(), 1, 2)));
+        assert_eq!(eval("tax(1)").err(), Some(CalcError::UnknownFunction("tax".to_string())));
+        let n = calc.parse("tax(x) + 1").unwrap();
+        assert_eq!((n.repr(), n.simplify().repr()), ("<tax(x)+1>".to_string(), "<tax(x)+1>".to_string()));
+        let mut env = Env::default();
+        env.set("x", Value::Int(200));
+        assert_eq!(compile(&n).run(&mut env), Ok(Value::Float(39.0)));
+    }
+
+    #[test]
+    fn test_ffi(){
+        let text = |s: *mut std::ffi::c_char| (!s.is_null()).then(|| unsafe { std::ffi::CStr::from_ptr(s) }.to_str().unwrap().to_string());
+        let mut out = std::mem::MaybeUninit::<CalcResult>::uninit();
+        let input = std::ffi::CString::new("7 // 2").unwrap();
+        assert_eq!(unsafe { calc_eval(input.as_ptr(), out.as_mut_ptr()) }, 1);
+        let mut r = unsafe { out.assume_init() };
+        assert_eq!((text(r.value), text(r.code), r.position), (Some("3".to_string()), None, -1));
+        unsafe { calc_result_free(&mut r) };
+        assert!(r.value.is_null());
+        for (input, code, message, position) in [("1 / 0", "E0031", "Division by zero: 1 / 0", 2), ("(1 + 2", "E0008", "Open parenthesis.", 6)] {
+            let input = std::ffi::CString::new(input).unwrap();
+            assert_eq!(unsafe { calc_eval(input.as_ptr(), &mut r) }, 0);
+            assert_eq!((text(r.value), text(r.code), text(r.message), r.position), (None, Some(code.to_string()), Some(message.to_string()), position));
+            unsafe { calc_result_free(&mut r) };
+        }
+        assert_eq!(unsafe { calc_eval(std::ptr::null(), &mut r) }, -1);
+    }
+
+    #[test]
+    fn test_evaluate_json(){
+        assert_eq!(evaluate_json("1 + 2"), r#"{"input": "1 + 2", "ok": true, "result": 3, "repr": "<1+2>"}"#);
+        assert_eq!(evaluate_json("1 / 0"),
+            r#"{"input": "1 / 0", "ok": false, "repr": "<1/0>", "diagnostics": [{"code": "E0031", "message": "Division by zero: 1 / 0", "span": [2, 3]}]}"#);
+        assert_eq!(evaluate_json("(1 + 2"),
+            r#"{"input": "(1 + 2", "ok": false, "repr": null, "diagnostics": [{"code": "E0008", "message": "Open parenthesis.", "span": [6, 7]}]}"#);
+        assert_eq!(evaluate_json("(1 + ) * 2 +").matches("\"code\"").count(), 4);
+        let input = std::ffi::CString::new("2 ^ 10").unwrap();
+        let out = unsafe { calc_evaluate_json(input.as_ptr()) };
+        assert!(unsafe { std::ffi::CStr::from_ptr(out) }.to_str().unwrap().contains(r#""result": 1024"#));
+        unsafe { calc_string_free(out) };
+        assert!(unsafe { calc_evaluate_json(std::ptr::null()) }.is_null());
+    }
+
+    #[test]
+    fn test_symbols(){
+        let mut env = Env::default();
+        env.set("rate", Value::Int(19));
+        let names = symbols(&env);
+        assert!(["sqrt", "pi", "km", "rate"].iter().all(|n| names.contains(&n.to_string())));
+        assert!(names.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_validate(){
+        assert_eq!(validate("1 / 0 + x"), Ok(()));
+        let errors = validate("(1 + * 2").unwrap_err();
+        assert_eq!(errors.iter().map(|d| d.code).collect::<Vec<_>>(), ["E0009", "E0008"]);
+        assert_eq!(errors[0].span, Span { start: 5, end: 6 });
+        assert!(!errors[0].message.starts_with("Error"));
+    }
+
+    #[test]
+    fn test_partial_eval(){
+        let mut env = Env::default();
+        let partial = |input: &str, env: &Env| infix(&parse(input).unwrap().partial_eval(env));
+        assert_eq!(partial("2 * x + 3 * 4", &env), "2 * x + 12");
+        env.set("y", Value::Int(5));
+        assert_eq!(partial("x * (y - 1) + max(y, 2)", &env), "x * 4 + 5");
+        assert_eq!(partial("y = x; y + 1", &env), "y = x; y + 1");
+        assert_eq!(partial("x / (y - 5)", &env), "x / 0");
+        assert_eq!(partial("x / (y - 4)", &env), "x / 1");
+        env.set("x", Value::Int(2));
+        assert_eq!(partial("2 * x + 3 * 4", &env), "16");
+    }
+
+    #[test]
+    fn test_solve(){
+        let solve = |input: &str| parse_equation(input).unwrap().solve("x");
+        assert_eq!(solve("2*x + 3 = 11"), Ok(vec![Value::Int(4)]));
+        assert_eq!(solve("x / 4 = 3 - x"), Ok(vec![Value::Float(2.4)]));
+        assert_eq!(solve("x^2 - 2*x = 3"), Ok(vec![Value::Int(-1), Value::Int(3)]));
+        assert_eq!(solve("(x - 1) * (x - 1)"), Ok(vec![Value::Int(1)]));
+        assert_eq!(solve("x * x + 1 = 0"), Ok(vec![]));
+        assert_eq!(solve("2 * x = 2 * x + 1"), Ok(vec![]));
+        assert_eq!(solve("x + x = 2 * x").map_err(|e| e.to_string()), Err("Error[E0039]: Every value of x is a solution".to_string()));
+        assert_eq!(solve("x ^ 3 = 8").map_err(|e| e.to_string()), Err("Error[E0038]: Not a linear or quadratic equation in x".to_string()));
+        assert_eq!(solve("1 / x = 2").map_err(|e| e.to_string()), Err("Error[E0038]: Not a linear or quadratic equation in x".to_string()));
+        assert_eq!(solve("x + y = 2").map_err(|e| e.to_string()), Err("Error[E0024]: Unknown variable: y".to_string()));
+        assert_eq!(parse_equation("x <= 2 = (1").err(), Some((CalcError::UnbalancedParen, Span { start: 11, end: 12 })));
+    }
+
+    #[test]
+    fn test_max_depth(){
+        // on the test's own thread, so the default limit is checked against a default stack
+        let nested = |n: usize| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert_eq!(parse(&nested(99)).unwrap().eval(), Ok(Value::Int(1)));
+        assert_eq!(parse(&nested(100_000)).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&format!("{}1", "-".repeat(100_000))).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&vec!["1"; 100_000].join("^")).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&format!("{}1", "1 ? 1 : ".repeat(100_000))).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&format!("{}1", "1 ? ".repeat(100_000))).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&format!("{}1", "x = ".repeat(100_000))).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(validate(&nested(100_000)).unwrap_err()[0].code, "E0037");
+        // a chain is flat, only long ones are too deep
+        assert_eq!(parse(&vec!["1"; 1001].join(" + ")).unwrap().eval(), Ok(Value::Int(1001)));
+        assert_eq!(parse(&vec!["1"; 1400].join(" - ")).unwrap().eval(), Ok(Value::Int(-1398)));
+        assert_eq!(parse(&vec!["1"; 100_000].join(" + ")).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(parse(&vec!["1"; 100_000].join("; ")).err(), Some(CalcError::TooDeep(100)));
+        assert_eq!(CalcError::TooDeep(100).to_string(), "Error[E0037]: Nested more than 100 levels deep");
+    }
+
+    #[test]
+    fn test_expr1(){
+        let parser = TokenParser::new(
+            "-1 * (-2 + 5)".to_string()
+        );
+        let n = evaluate(parser).unwrap();
+        assert_eq!(n.eval(), Ok(Value::Int(-3)));
+    }
+
+    #[test]
+    fn test_expr2(){
+        let parser = TokenParser::new(
+            "12 + 34 - (56 / 7) * 8".to_string()
+        );
+        let n = evaluate(parser).unwrap();
+        assert_eq!(n.eval(), Ok(Value::Int(-18)));
+    }
+
+    #[test]
+    fn test_expr3(){
+        let parser = TokenParser::new(
+            "(-12 + 34) * ((56 / 7) + 8)".to_string()
+        );
+        let n = evaluate(parser).unwrap();
+        assert_eq!(n.eval(), Ok(Value::Int(352)));
+    }
+
+    #[test]
+    fn test_env(){
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::set_var("CALC_TEST_COLUMNS", " 80 ");
+            std::env::set_var("CALC_TEST_WORD", "abc");
+        }
+        let parse = |input: &str| evaluate(TokenParser { env_vars: true, ..TokenParser::new(input.to_string()) }).map_err(|e| e.to_string());
+        let n = parse("$CALC_TEST_COLUMNS / 2").unwrap();
+        assert_eq!((n.eval(), n.repr()), (Ok(Value::Int(40)), "<$CALC_TEST_COLUMNS/2>".to_string()));
+        assert_eq!(parse("env(\"CALC_TEST_COLUMNS\") + 1").unwrap().eval(), Ok(Value::Int(81)));
+        assert_eq!(parse("$CALC_TEST_UNSET").err(), Some("Error[E0012]: Environment variable $CALC_TEST_UNSET is not set".to_string()));
+        assert_eq!(parse("1 + $CALC_TEST_WORD").err(), Some("Error[E0013]: Environment variable $CALC_TEST_WORD is not a number: abc".to_string()));
+        assert_eq!(parse("env(\"CALC_TEST_COLUMNS)").err(), Some("Error[E0001]: Invalid token '\"' at index 4".to_string()));
+        // off unless asked for, in a library
+        assert_eq!(eval_str("$CALC_TEST_COLUMNS").map_err(|e| e.to_string()), Err("Error[E0041]: Environment variables are not allowed here: $CALC_TEST_COLUMNS".to_string()));
+        let mut calc = Calculator::builder().env_vars(true).build();
+        assert_eq!(calc.eval("$CALC_TEST_COLUMNS"), Ok(Value::Int(80)));
+    }
+
+    #[test]
+    fn test_functions(){
+        assert_eq!(eval_str("roundto(1234, 25)"), Ok(Value::Int(1225)));
+        assert_eq!(eval_str("roundto(1238, 25) + roundto(-13, 5)"), Ok(Value::Int(1250 - 15)));
+        assert_eq!(eval_str("roundto(7, -2)"), Ok(Value::Int(8)));
+        assert_eq!(eval_str("roundto(2147483647, 10)").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: roundto(2147483647, 10)".to_string()));
+        assert_eq!(eval_str("roundto(17.3, 0.5) + roundto(-2.5, 5)"), Ok(Value::Float(17.5)));
+        assert_eq!(eval_str("roundto(7.1, 2)"), Ok(Value::Float(8.0)));
+        let dec = |s: &str| Value::Dec(Decimal::parse(s).unwrap());
+        assert_eq!(roundto(&dec("1.12"), &dec("0.05")), Ok(dec("1.10")));
+        assert_eq!(roundto(&dec("1.125"), &dec("-0.05")).map(|v| v.to_string()), Ok("1.15".to_string()));
+        assert_eq!(eval_str("roundto(7, 1 m)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of roundto() out of range: 1 m".to_string()));
+        assert_eq!(eval_str("roundto(7, 0)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of roundto() out of range: 0".to_string()));
+        assert_eq!(eval_str("roundto(7)").map_err(|e| e.to_string()), Err("Error[E0015]: roundto() takes 2 argument(s), got 1".to_string()));
+        assert_eq!(eval_str("round(7)").map_err(|e| e.to_string()), Err("Error[E0014]: Unknown function: round".to_string()));
+        let n = evaluate(TokenParser::new("2 * roundto(3 + 4, 5)".to_string())).unwrap();
+        assert_eq!((n.repr(), grouping(&n)), ("<2*roundto(<3+4>,5)>".to_string(), "2 * roundto(3 + 4, 5)".to_string()));
+        assert_eq!(explain(&n).unwrap(), vec!["3 + 4 → 7", "roundto(7, 5) → 5", "2 * 5 → 10"]);
+    }
+
+    #[test]
+    fn test_bit_functions(){
+        assert_eq!(eval_str("popcount(255) + popcount(-1)"), Ok(Value::Int(8 + 32)));
+        assert_eq!(eval_str("clz(1) * 100 + ctz(8)"), Ok(Value::Int(3103)));
+        assert_eq!(eval_str("ctz(0)"), Ok(Value::Int(32)));
+        assert_eq!(eval_str("bit(5, 2) * 10 + bit(5, 1)"), Ok(Value::Int(10)));
+        assert_eq!(eval_str("setbit(5, 1) * 100 + clearbit(5, 0)"), Ok(Value::Int(704)));
+        assert_eq!(eval_str("setbit(0, 31)"), Ok(Value::Int(i32::MIN)));
+        assert_eq!(eval_str("bit(1, 32)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of bit() out of range: 32".to_string()));
+    }
+
+    #[test]
+    fn test_rotate(){
+        assert_eq!(eval_str("rotl(129, 1, 8)"), Ok(Value::Int(3)));
+        assert_eq!(eval_str("rotr(3, 1, 8)"), Ok(Value::Int(129)));
+        assert_eq!(eval_str("rotl(1, 17, 16)"), Ok(Value::Int(2)));
+        assert_eq!(eval_str("rotr(1, 1, 32)"), Ok(Value::Int(i32::MIN)));
+        assert_eq!(eval_str("rotl(1, -1, 8)"), Ok(Value::Int(128)));
+        assert_eq!(eval_str("rotr(2, 1, 64)"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("rotl(-1, 5, 64)"), Ok(Value::Int(-1)));
+        assert_eq!(eval_str("rotl(1, 63, 64)").map_err(|e| e.to_string()), Err("Error[E0030]: Integer overflow: rotl(1, 63, 64)".to_string()));
+        assert_eq!(rotate_bits("rotl", 1, 63, 64), Ok(i64::MIN));
+        assert_eq!(rotate_bits("rotr", i64::MIN, 63, 64), Ok(1));
+        assert_eq!(rotate_bits("rotl", 0x0123456789abcdef, 8, 64), Ok(0x23456789abcdef01));
+        assert_eq!(BigInt::parse("-9223372036854775808").unwrap().to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::parse("9223372036854775808").unwrap().to_i64(), None);
+        assert_eq!(eval_str("rotl(1, 1, 128)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 3 of rotl() out of range: 128".to_string()));
+    }
+
+    #[test]
+    fn test_roman(){
+        assert_eq!(eval_str("MCMXCIV + 6"), Ok(Value::Int(2000)));
+        assert_eq!(eval_str("X * 2"), Ok(Value::Int(20)));
+        // an uppercase or mixed-case word is a name, assigning one hides the numeral
+        assert_eq!(eval_str("X = 3; X * 2"), Ok(Value::Int(6)));
+        assert_eq!(eval_str("Total = 3; Rate = 2; Total * Rate"), Ok(Value::Int(6)));
+        assert_eq!(eval_str("Total_2024 = 1; Total_2024 + 1"), Ok(Value::Int(2)));
+        assert_eq!(eval_str("Rate").map_err(|e| e.to_string()), Err("Error[E0024]: Unknown variable: Rate".to_string()));
+        // cell references only in a sheet
+        assert_eq!(eval_str("C1 = 2; XL - C1"), Ok(Value::Int(38)));
+        let sheet = |formula: &str| evaluate(TokenParser::with_cells(formula.to_string(), HashMap::new())).map(|_| ()).map_err(|e| e.to_string());
+        assert_eq!(sheet("XL - C1"), Err("Error[E0005]: Unknown cell reference: C1".to_string()));
+        assert_eq!(sheet("A0 + 1"), Err("Error[E0002]: Invalid cell reference 'A0' at index 0".to_string()));
+        assert_eq!(parse_roman("MMXXIV"), Some(2024));
+        assert_eq!(parse_roman("IIII"), None);
+        assert_eq!(parse_roman("VX"), None);
+        assert_eq!(to_roman(3999).as_deref(), Some("MMMCMXCIX"));
+        assert_eq!(to_roman(0), None);
+    }
+
+    #[test]
+    fn test_iroot(){
+        assert_eq!(eval_str("isqrt(24) * 10 + isqrt(25)"), Ok(Value::Int(45)));
+        assert_eq!(eval_str("isqrt(2147483647)"), Ok(Value::Int(46340)));
+        assert_eq!(eval_str("iroot(1000, 3) + iroot(1023, 10)"), Ok(Value::Int(10 + 1)));
+        assert_eq!(eval_str("iroot(-9, 3)"), Ok(Value::Int(-3)));
+        assert_eq!(eval_str("iroot(5, 1)"), Ok(Value::Int(5)));
+        assert_eq!(eval_str("isqrt(-4)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of isqrt() out of range: -4".to_string()));
+        assert_eq!(eval_str("iroot(8, 0)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of iroot() out of range: 0".to_string()));
+    }
+
+    #[test]
+    fn test_sequences(){
+        assert_eq!((0..10).map(|n| sequence("fib", n)).collect::<Result<Vec<i32>, CalcError>>(), Ok(vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]));
+        assert_eq!((0..6).map(|n| sequence("lucas", n)).collect::<Result<Vec<i32>, CalcError>>(), Ok(vec![2, 1, 3, 4, 7, 11]));
+        assert_eq!((0..7).map(|n| sequence("catalan", n)).collect::<Result<Vec<i32>, CalcError>>(), Ok(vec![1, 1, 2, 5, 14, 42, 132]));
+        assert_eq!(eval_str("triangular(4) + fib(46)"), Ok(Value::Int(10 + 1836311903)));
+        assert_eq!(eval_str("catalan(19)"), Ok(Value::Int(1767263190)));
+        assert_eq!(eval_str("fib(47)").map_err(|e| e.to_string()), Err("Error[E0019]: fib(47) does not fit in 32 bits".to_string()));
+        assert_eq!(eval_str("catalan(20)").map_err(|e| e.to_string()), Err("Error[E0019]: catalan(20) does not fit in 32 bits".to_string()));
+        assert_eq!(eval_str("lucas(-1)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of lucas() out of range: -1".to_string()));
+        // the --bignum versions
+        let big = |s: &str| BigInt::parse(s).unwrap();
+        assert_eq!(big_sequence("fib", &big("100")).map(|b| b.to_string()), Ok("354224848179261915075".to_string()));
+        assert_eq!(big_sequence("lucas", &big("5")), Ok(big("11")));
+        assert_eq!(big_sequence("catalan", &big("30")).map(|b| b.to_string()), Ok("3814986502092304".to_string()));
+        assert_eq!(big_sequence("triangular", &big("100000000000")).map(|b| b.to_string()), Ok("5000000000050000000000".to_string()));
+        assert_eq!(big_iroot("isqrt", &big("99999999999999999999"), 2), Ok(big("9999999999")));
+        assert_eq!(big_iroot("iroot", &big("-1000000000000000000000"), 3), Ok(big("-10000000")));
+        assert_eq!(big_iroot("iroot", &big("-5"), 1001), Ok(big("-2")));
+        assert!(big("-3") < big("2") && big("-3") < big("-2") && big("4294967296") > big("4294967295"));
+    }
+
+    #[test]
+    fn test_modular(){
+        assert_eq!(eval_str("powmod(2, 100, 1000000007)"), Ok(Value::Int(976371285)));
+        assert_eq!(eval_str("powmod(-3, 3, 7)"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("powmod(5, 0, 1)"), Ok(Value::Int(0)));
+        assert_eq!(eval_str("invmod(3, 11)"), Ok(Value::Int(4)));
+        assert_eq!(eval_str("invmod(-3, 11)"), Ok(Value::Int(7)));
+        assert_eq!(eval_str("powmod(3, -2, 11)"), Ok(Value::Int(5)));
+        assert_eq!(eval_str("invmod(6, 9)").map_err(|e| e.to_string()), Err("Error[E0020]: 6 has no inverse modulo 9".to_string()));
+        assert_eq!(eval_str("powmod(2, 3, 0)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 3 of powmod() out of range: 0".to_string()));
+        // the --bignum versions
+        let big = |s: &str| BigInt::parse(s).unwrap();
+        assert_eq!(big_powmod(&[big("2"), big("100"), big("1000000007")]), Ok(big("976371285")));
+        assert_eq!(big_powmod(&[big("3"), big("-2"), big("11")]), Ok(big("5")));
+        assert_eq!(big_powmod(&[big("2"), big("127"), big("170141183460469231731687303715884105727")]), Ok(big("1")));
+        assert_eq!(big_invmod(&big("-3"), &big("100000000000000000039")).map(|b| b.to_string()), Ok("33333333333333333346".to_string()));
+        assert_eq!(big_invmod(&big("6"), &big("9")).map_err(|e| e.to_string()), Err("Error[E0020]: 6 has no inverse modulo 9".to_string()));
+    }
+
+    #[test]
+    fn test_float_bits(){
+        assert_eq!(eval_str("float_bits(1)").unwrap().to_string(), "4607182418800017408");
+        assert_eq!(eval_str("float_bits(-0.0)").unwrap().to_string(), "9223372036854775808");
+        assert_eq!(eval_str("bits_to_float(float_bits(0.1))"), Ok(Value::Float(0.1)));
+        assert_eq!(eval_str("bits_to_float(1)"), Ok(Value::Float(f64::from_bits(1))));
+        assert_eq!(eval_str("ulp(1)"), Ok(Value::Float(f64::EPSILON)));
+        assert_eq!(eval_str("ulp(-1)"), Ok(Value::Float(f64::EPSILON)));
+        assert_eq!(eval_str("nextafter(1, 2) - 1"), Ok(Value::Float(f64::EPSILON)));
+        assert_eq!(eval_str("nextafter(0, -1)"), Ok(Value::Float(-f64::from_bits(1))));
+        assert_eq!(eval_str("nextafter(3, 3)"), Ok(Value::Float(3.0)));
+        assert_eq!(eval_str("bits_to_float(-1)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of bits_to_float() out of range: -1".to_string()));
+    }
+
+    #[test]
+    fn test_weighted(){
+        assert_eq!(eval_str("wmean([90, 80, 70], [0.5, 0.3, 0.2])"), Ok(Value::Float(83.0)));
+        assert_eq!(eval_str("wmean([1, 2], [1, 1])"), Ok(Value::Float(1.5)));
+        assert_eq!(eval_str("wvar([1, 3], [1, 1])"), Ok(Value::Float(1.0)));
+        assert_eq!(eval_str("wvar([1, 3], [3, 1])"), Ok(Value::Float(0.75)));
+        assert_eq!(eval_str("percentile([4, 1, 3, 2], 50)"), Ok(Value::Float(2.5)));
+        assert_eq!(eval_str("percentile([15, 20, 35, 40, 50], 40)"), Ok(Value::Float(29.0)));
+        assert_eq!(eval_str("percentile([7], 90) + percentile([1, 9], 100)"), Ok(Value::Float(16.0)));
+        assert_eq!(eval_str("wmean([1, 2], [1])").map_err(|e| e.to_string()), Err("Error[E0033]: Shapes do not match: wmean([1, 2], [1])".to_string()));
+        assert_eq!(eval_str("wmean([1, 2], [0, 0])").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of wmean() out of range: [0, 0]".to_string()));
+        assert_eq!(eval_str("percentile(5, 50)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of percentile() out of range: 5".to_string()));
+        assert_eq!(eval_str("percentile([1], 101)").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 2 of percentile() out of range: 101".to_string()));
+    }
+
+    #[test]
+    fn test_polynomials(){
+        let p = eval_str("poly([1, -3, 2])").unwrap();
+        assert_eq!(p.to_string(), "(x - 3) * x + 2");
+        assert_eq!(eval_str("x = poly([1, 0]); x^2 - 3*x + 2"), Ok(p.clone()));
+        assert_eq!(eval_str("poly(1, -3, 2)"), Ok(p.clone()));
+        assert_eq!(eval_str("x^2 - 3x + 2"), Ok(p.clone()));
+        assert_eq!(eval_str("poly(x^2 - 3x + 2)"), Ok(p.clone()));
+        assert_eq!(eval_str("roots(x^2 - 3x + 2)"), Ok(Value::List(vec![Value::Int(1), Value::Int(2)])));
+        assert_eq!(eval_str("x = 5; x^2 - 3x + 2"), Ok(Value::Int(12)));
+        assert_eq!(eval_str("polyval(poly([1, -3, 2]), 5)"), Ok(Value::Int(12)));
+        assert_eq!(eval_str("roots(poly([1, -3, 2]))"), Ok(Value::List(vec![Value::Int(1), Value::Int(2)])));
+        assert_eq!(eval_str("roots(poly([2, -1]))"), Ok(Value::List(vec![Value::Float(0.5)])));
+        assert_eq!(eval_str("roots(poly([1, 0, 1]))"), Ok(Value::List(vec![])));
+        assert_eq!(eval_str("poly([2, 0, -1]) * poly([1, 1])").unwrap().to_string(), "((2 * x + 2) * x - 1) * x - 1");
+        assert_eq!(eval_str("poly([1, 2]) ^ 2 / 2").unwrap().to_string(), "(0.5 * x + 2.0) * x + 2.0");
+        assert_eq!(eval_str("-poly([1, 0, 0])").unwrap().to_string(), "-x * x");
+        assert_eq!(eval_str("poly([1, 1]) - poly([1, 0])"), Ok(Value::Int(1)));
+        assert_eq!(eval_str("polyval(poly([1, 0, 0]), poly([1, 1]))").unwrap().to_string(), "(x + 2) * x + 1");
+        assert_eq!(eval_str("poly([1, 1]) / poly([1, 0])").map_err(|e| e.to_string()), Err("Error[E0042]: Not defined for polynomials: (x + 1) / (x)".to_string()));
+        assert_eq!(eval_str("roots(poly([1, 0, 0, 1]))").map_err(|e| e.to_string()), Err("Error[E0038]: Not a linear or quadratic equation in x".to_string()));
+        assert_eq!(eval_str("poly([])").map_err(|e| e.to_string()), Err("Error[E0016]: Argument 1 of poly() out of range: []".to_string()));
+        let mut env = Env::default();
+        env.set("p", p);
+        assert_eq!(infix(&parse("p * y").unwrap().partial_eval(&env)), "((x - 3) * x + 2) * y");
+    }
+
+    #[test]
+    fn test_config(){
+        let config = parse_config("# operators\nalias x = *\n\nalias ÷ = /\ninfix ** 14 right = ^\n").unwrap();
+        assert_eq!(config.aliases.get("x"), Some(&Token::MUL));
+        assert_eq!(config.aliases.get("÷"), Some(&Token::DIV));
+        assert_eq!((config.operators[0].symbol, config.operators[0].precedence, config.operators[0].assoc), ("**", 14, Assoc::Right));
+        // every binary operator, `mod` reads as the '%' of 7 % 3
+        let config = parse_config("alias mod = %\nalias and = &&\nalias in = ->\nalias bxor = xor\nalias shl = <<").unwrap();
+        assert_eq!(config.aliases.get("mod"), Some(&Token::PCT));
+        assert_eq!(config.aliases.get("and"), Some(&Token::AND));
+        assert_eq!(config.aliases.get("in"), Some(&Token::TO));
+        assert_eq!(config.aliases.get("bxor"), Some(&Token::XOR));
+        assert_eq!(config.aliases.get("shl"), Some(&Token::SHL));
+        let mut tokens = TokenParser::new("7 % 3".to_string()).collect::<Result<Vec<Token>, CalcError>>().unwrap();
+        tokens[1] = config.aliases["mod"].clone();
+        assert_eq!(tokens, vec![Token::NUM(Value::Int(7)), Token::PCT, Token::NUM(Value::Int(3))]);
+        assert_eq!(eval_str("7 % 3"), Ok(Value::Int(1)));
+        assert_eq!(parse_config("alias not = !").unwrap_err(), "line 1: cannot alias unsupported operator '!'");
+        assert_eq!(parse_config("alias plus = + +").unwrap_err(), "line 1: cannot alias unsupported operator '+ +'");
+        assert_eq!(parse_config("alias 2 = +").unwrap_err(), "line 1: alias name must be a lowercase word or one symbol, got '2'");
+        assert_eq!(parse_config("infix ** 15 right = ^").unwrap_err(), "line 1: precedence must be 4 (like ||) to 14 (like ^), got '15'");
+        assert_eq!(parse_config("infix -- 11 left = sqrt").unwrap_err(), "line 1: 'sqrt' is neither a binary operator nor a function of 2 arguments");
+        assert!(parse_config("infix ( 11 left = +").is_err());
+        assert!(parse_config("x = *").is_err());
+        // set once at startup, empty here so that other tests see no change
+        let _ = set_aliases(HashMap::new());
+        assert_eq!(set_aliases(HashMap::new()), Err("operator aliases are set already".to_string()));
+    }
+
+    #[test]
+    fn test_operators(){
+        let infix = |line: &str| parse_infix(line).unwrap();
+        let operators = vec![infix("** 14 right = ^"), infix("// 11 left = //"), infix("<? 7 left = min"), infix("mod 12 left = roundto")];
+        let eval = |input: &str| {
+            let p = TokenParser { operators: operators.clone(), ..TokenParser::new(input.to_string()) };
+            evaluate(p).map(|n| (n.repr(), n.eval().unwrap())).map_err(|e| e.to_string())
+        };
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(("<2^<3^2>>".to_string(), Value::Int(512))));
+        assert_eq!(eval("-2 ** 2 * 3"), Ok(("<<-<2^2>>*3>".to_string(), Value::Int(-12))));
+        assert_eq!(eval("2 * 3 ** 2"), Ok(("<2*<3^2>>".to_string(), Value::Int(18))));
+        // as loose as + and -
+        assert_eq!(eval("17 // 2 * 3").map(|(_, v)| v), Ok(Value::Int(2)));
+        assert_eq!(eval("1 + 4 <? 2 * 3").map(|(_, v)| v), Ok(Value::Int(5)));
+        assert_eq!(eval("1238 mod 25").map(|(_, v)| v), Ok(Value::Int(1250)));
+        assert_eq!(eval("2 ** "), Err("Error[E0004]: Unexpected end of input".to_string()));
+        assert_eq!(eval("2 * * 3"), Err("Error[E0009]: Illegal factor: MUL".to_string()));
+        // the built-in precedences are unchanged without configured operators
+        assert_eq!(parse("1 | 2 ^ 3 & 4 << 1 + 2 * 3 == 5 || 6").unwrap().repr(), "<<<1|<<2^3>&<4<<<1+<2*3>>>>>==5>||6>");
+        let mut calc = Calculator::builder()
+            .operator("<>", 12, Assoc::Right, |_, _| Ok(Value::Int(0))).unwrap()
+            .operator("<>", 11, Assoc::Left, |a, b| if a < b { b - a } else { a - b }).unwrap()
+            .build();
+        assert_eq!(calc.eval("3 <> 10 <> 2"), Ok(Value::Int(5)));
+        assert_eq!(calc.eval("1 + 3 <> 10"), Ok(Value::Int(6)));
+        assert_eq!(Calculator::builder().operator("(*", 12, Assoc::Left, |a, b| a * b).err(),
+            Some("operator must be a lowercase word or symbols other than ()[],;#$\"._', got '(*'".to_string()));
+        assert_eq!(Calculator::builder().operator("**", 15, Assoc::Left, |a, b| a * b).err(),
+            Some("precedence must be 4 (like ||) to 14 (like ^), got '15'".to_string()));
+        // set once at startup, empty here so that other tests see no change
+        let _ = set_operators(Vec::new());
+        assert_eq!(set_operators(Vec::new()), Err("operators are set already".to_string()));
+    }
+
+    #[test]
+    fn test_localize(){
+        assert_eq!(localize(0, "E0008", &[]), "Error[E0008]: Open parenthesis.");
+        assert_eq!(localize(1, "E0001", &[&'?', &3]), "错误[E0001]: 无效的符号 '?'，位于索引 3");
+        assert_eq!(localize(2, "E0005", &[&"Z9"]), "Error[E0005]: Referencia de celda desconocida: Z9");
+        // --json takes the message without the header, whatever the language
+        assert_eq!(localized_message(1, "E0024", &[&"x"]), "未知的变量: x");
+        assert_eq!(runtime("E0024", &[&"x"]).message(), "Unknown variable: x");
+        assert_eq!(CalcError::UnbalancedParen.message(), "Open parenthesis.");
+        assert_eq!(eval_str("(1 + 2").map_err(|e| e.to_string()), Err("Error[E0008]: Open parenthesis.".to_string()));
+    }
+
+    #[test]
+    fn test_explain(){
+        let n = evaluate(TokenParser::new("12 + 34 - (56 // 7) * 8".to_string())).unwrap();
+        assert_eq!(explain(&n).unwrap(), vec!["56 // 7 → 8", "8 * 8 → 64", "12 + 34 → 46", "46 - 64 → -18"]);
+        let n = evaluate(TokenParser::new("(-12 + 34) * ((56 // 7) + 8)".to_string())).unwrap();
+        assert_eq!(explain(&n).unwrap(), vec!["56 // 7 → 8", "-12 + 34 → 22", "8 + 8 → 16", "22 * 16 → 352"]);
+    }
+
+    #[test]
+    fn test_spans(){
+        let n = evaluate(TokenParser::new("12 + (-3 * 4)".to_string())).unwrap();
+        assert_eq!(n.span(), Span { start: 0, end: 13 });
+        let n = evaluate(TokenParser::new("(-3 * 4) - 1".to_string())).unwrap();
+        assert_eq!(n.span(), Span { start: 0, end: 12 });
+        // a binary node also knows where its operator is
+        let Expr::Bin(_, l, _, at) = &n else { panic!("{}", n.repr()) };
+        assert_eq!(*at, Span { start: 9, end: 10 });
+        assert!(matches!(&**l, Expr::Par(c, _) if matches!(**c, Expr::Bin(Op::Mul, _, _, Span { start: 4, end: 5 }))));
+        let n = parse("2 ^ 3 -> 1 ; 1").unwrap();
+        assert!(matches!(n, Expr::Bin(Op::Seq, _, _, Span { start: 11, end: 12 })));
+        let loaded = Expr::from_json(&n.to_json()).unwrap();
+        assert!(matches!(loaded, Expr::Bin(Op::Seq, _, _, Span { start: 11, end: 12 })));
+    }
+
+    #[test]
+    fn test_grouping(){
+        let n = evaluate(TokenParser::new("12 + 34 - (56 / 7) * 8".to_string())).unwrap();
+        assert_eq!(grouping(&n), "12 + 34 - ((56 / 7) * 8)");
+        let n = evaluate(TokenParser::new("1 - 2 * -3 / 4 + 5".to_string())).unwrap();
+        assert_eq!(grouping(&n), "1 - (2 * -3 / 4) + 5");
+    }
+
+    #[test]
+    fn test_json_trace(){
+        let mut trace = JsonTrace::new("1 + 2");
+        evaluate(TokenParser::new("1 + 2".to_string())).unwrap().eval_with(&mut Env::default(), &mut trace).unwrap();
+        assert!(trace.root.starts_with("{\"op\": \"+\", \"text\": \"1 + 2\", \"span\": [0, 5], \"operands\": [1, 2], \"result\": 3, "));
+        assert!(trace.root.contains("\"children\": [{\"op\": \"num\", \"text\": \"1\", \"span\": [0, 1], \"operands\": [], \"result\": 1, "));
+        assert_eq!(trace.root.matches("\"op\"").count(), 3);
+        assert_eq!(json_string("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+        // alongside another observer, in the one evaluation
+        let mut both = (None::<Explain>, Some(JsonTrace::new("2 * 3")));
+        parse("2 * 3").unwrap().eval_with(&mut Env::default(), &mut both).unwrap();
+        assert_eq!(both.1.unwrap().root.matches("\"op\"").count(), 3);
+        // an error closes the nodes it stopped
+        let mut trace = JsonTrace::new("1 + 4 / 0");
+        let e = parse("1 + 4 / 0").unwrap().eval_with(&mut Env::default(), &mut trace).unwrap_err();
+        trace.fail(&e);
+        assert!(trace.root.starts_with("{\"op\": \"+\", \"text\": \"1 + 4 / 0\", \"span\": [0, 9], \"operands\": [], \"result\": null, \
+            \"error\": {\"code\": \"E0031\", \"message\": \"Division by zero: 4 / 0\"}, "), "{}", trace.root);
+        assert!(trace.root.contains("{\"op\": \"/\", \"text\": \"4 / 0\", \"span\": [4, 9], \"operands\": [], \"result\": null, \"error\": "));
+        assert_eq!(trace.root.matches("\"op\"").count(), 5);
+    }
+
+    #[test]
+    fn test_pretty(){
+        let n = evaluate(TokenParser::new("1111 + 2222 * (3333 - 4444 + 5555) - 6666".to_string())).unwrap();
+        assert_eq!(pretty(&n, 80, None), vec!["1111 + (2222 * (3333 - 4444 + 5555)) - 6666"]);
+        assert_eq!(pretty(&n, 40, None), vec![
+            "1111",
+            "  + (2222 * (3333 - 4444 + 5555))",
+            "  - 6666",
+        ]);
+        assert_eq!(pretty(&n, 20, None), vec![
+            "1111",
+            "  + (2222",
+            "       * (3333",
+            "            - 4444",
+            "            + 5555))",
+            "  - 6666",
+        ]);
+        assert_eq!(pretty(&n, 20, Some(Format::Infix)), vec![
+            "1111",
+            "  + 2222",
+            "      * (3333",
+            "           - 4444",
+            "           + 5555)",
+            "  - 6666",
+        ]);
+        assert_eq!(pretty(&n, 20, Some(Format::Repr)), vec![
+            "<<1111",
+            "  + <2222",
+            "      * (<<3333",
+            "           - 4444>",
+            "           + 5555>)>>",
+            "  - 6666>",
+        ]);
+        assert_eq!(pretty(&n, 20, Some(Format::Rpn)), vec![Format::Rpn.render(&n)]);
+        let n = evaluate(TokenParser::new("2 ^ 3 ^ 2 - (1000000 - 2000000)".to_string())).unwrap();
+        assert_eq!(pretty(&n, 30, Some(Format::Infix)), vec!["2 ^ 3 ^ 2", "  - (1000000 - 2000000)"]);
+    }
+
+    #[test]
+    fn test_tree(){
+        let source = "2 * (3 + -4)";
+        let mut lines = Vec::new();
+        tree(&evaluate(TokenParser::new(source.to_string())).unwrap(), &source.chars().collect::<Vec<char>>(), 0, &mut lines);
+        assert_eq!(lines, vec!["* 2 * (3 + -4)", "  num 2", "  () (3 + -4)", "    + 3 + -4", "      num 3", "      neg -4", "        num 4"]);
+    }
+
+    #[test]
+    fn test_lint(){
+        let mut lints = Vec::new();
+        lint(&evaluate(TokenParser::new("8 // 4 + (7 // 2) * 3".to_string())).unwrap(), &mut lints);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].span, Span { start: 10, end: 16 });
+        assert_eq!(lints[0].message, "floor division drops the remainder: 7 // 2 = 3 (remainder 1)");
+        let messages = |input: &str| {
+            let mut lints = Vec::new();
+            lint(&parse(input).unwrap(), &mut lints);
+            lints.into_iter().map(|l| l.message).collect::<Vec<String>>()
+        };
+        // i32::MIN // -1 overflows, which the evaluation reports
+        assert!(messages("(-2147483647 - 1) // -1").is_empty() && messages("7 // 0").is_empty());
+        assert_eq!(eval_str("(-2147483647 - 1) // -1").map_err(|e| e.code()), Err("E0030"));
+        assert_eq!(messages("2147483647 // -2"), vec!["floor division drops the remainder: 2147483647 // -2 = -1073741824 (remainder -1)"]);
+
+        assert_eq!(messages("1 < 2 ? x : y"), vec!["comparison is always true: 1 < 2"]);
+        assert_eq!(messages("x + 1 != (x + 1)"), vec!["comparison is always false: x + 1 != x + 1"]);
+        assert_eq!(messages("x >= x"), vec!["comparison is always true: x >= x"]);
+        assert!(messages("x < y").is_empty() && messages("rand() < rand()").is_empty());
+
+        let unused = |input: &str| {
+            let mut lints = Vec::new();
+            lint_assignments(&parse(input).unwrap(), &mut lints);
+            lints.into_iter().map(|l| (l.span.start, l.message)).collect::<Vec<_>>()
+        };
+        assert_eq!(unused("x = 1; x = 2; y = 3; x * 2"), vec![(0, "the value assigned to x is never used".to_string()), (14, "the value assigned to y is never used".to_string())]);
+        assert!(unused("x = 1; x = x + 1; x").is_empty());
+        assert!(unused("total = 3").is_empty() && unused("x = 2; y = x * 2").is_empty());
+
+        let mut lints = Vec::new();
+        let span = Span::default();
+        lint(&Expr::Neg(Box::new(Expr::Neg(Box::new(Expr::Num(Value::Int(5), span)), span)), span), &mut lints);
+        assert_eq!(lints[0].message, "redundant double negation");
+    }
+
+    #[test]
+    fn test_generate(){
+        let a = generate(&mut Rng(42), 4, GEN_OPS, 20);
+        assert_eq!(a.len(), 20);
+        assert_eq!(a.iter().map(|e| &e.0).collect::<Vec<_>>(), generate(&mut Rng(42), 4, GEN_OPS, 20).iter().map(|e| &e.0).collect::<Vec<_>>());
+        for (expr, value) in a {
+            assert_eq!(eval_str(&expr), Ok(value), "{}", expr);
+        }
+        for (expr, _) in generate(&mut Rng(7), 3, &['+'], 5) {
+            assert!(!expr.contains(['-', '*', '/']), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_generated_round_trip(){
+        // printing with explicit grouping and parsing back must not change the tree
+        let mut rng = Rng(2024);
+        for _ in 0..1000 {
+            let n = random_expr(&mut rng, 5, GEN_OPS, 99);
+            let printed = grouping(&n);
+            let parsed = evaluate(TokenParser::new(printed.clone())).unwrap();
+            assert_eq!(grouping(&parsed), printed);
+            assert_eq!(parsed.eval(), n.eval(), "{}", printed);
+        }
+    }
+}